@@ -0,0 +1,21 @@
+-- Persist in-progress multipart uploads so they survive app restarts
+CREATE TABLE IF NOT EXISTS multipart_uploads (
+  id INTEGER PRIMARY KEY,
+  config_id INTEGER NOT NULL,
+  multipart_id TEXT NOT NULL,
+  obj_name TEXT NOT NULL,
+  mime_type TEXT NOT NULL,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+  FOREIGN KEY (config_id) REFERENCES s3config (id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS multipart_parts (
+  upload_id INTEGER NOT NULL,
+  part_number INTEGER NOT NULL,
+  etag TEXT NOT NULL,
+  size INTEGER NOT NULL,
+
+  PRIMARY KEY (upload_id, part_number),
+  FOREIGN KEY (upload_id) REFERENCES multipart_uploads (id) ON DELETE CASCADE
+);
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE s3config ADD COLUMN max_concurrent_parts INTEGER NOT NULL DEFAULT 4;
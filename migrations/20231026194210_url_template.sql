@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE s3config ADD COLUMN url_template TEXT;
+
+-- keep the object key so uploads can be deleted no matter which url they are shared with
+ALTER TABLE uploads ADD COLUMN obj_key TEXT;
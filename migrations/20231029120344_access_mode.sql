@@ -0,0 +1,6 @@
+-- Add migration script here
+ALTER TABLE s3config ADD COLUMN access_mode TEXT NOT NULL DEFAULT 'public_acl';
+ALTER TABLE s3config ADD COLUMN presign_expiry_secs INTEGER NOT NULL DEFAULT 604800;
+
+-- the config an upload was made with, needed to sign links for private buckets
+ALTER TABLE uploads ADD COLUMN config_id INTEGER REFERENCES s3config (id) ON DELETE SET NULL;
@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE s3config ADD COLUMN url_style TEXT NOT NULL DEFAULT 'auto';
+ALTER TABLE s3config ADD COLUMN disable_acl BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE s3config ADD COLUMN unsigned_payload BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE s3config ADD COLUMN checksum_algorithm TEXT NOT NULL DEFAULT 'none';
+ALTER TABLE multipart_uploads ADD COLUMN checksum_algorithm TEXT NOT NULL DEFAULT 'none';
+ALTER TABLE multipart_parts ADD COLUMN checksum TEXT;
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE s3config ADD COLUMN min_part_size_mib INTEGER NOT NULL DEFAULT 5;
+ALTER TABLE s3config ADD COLUMN max_part_size_mib INTEGER NOT NULL DEFAULT 5120;
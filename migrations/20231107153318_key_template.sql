@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE s3config ADD COLUMN key_template TEXT;
+ALTER TABLE s3config ADD COLUMN key_prefix TEXT;
@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE s3config ADD COLUMN encryption TEXT NOT NULL DEFAULT 'none';
+ALTER TABLE s3config ADD COLUMN kms_key_id TEXT;
+ALTER TABLE s3config ADD COLUMN customer_key TEXT;
+ALTER TABLE s3config ADD COLUMN storage_class TEXT;
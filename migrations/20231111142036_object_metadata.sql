@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE s3config ADD COLUMN cache_control TEXT;
+ALTER TABLE s3config ADD COLUMN content_disposition TEXT NOT NULL DEFAULT 'none';
+ALTER TABLE s3config ADD COLUMN metadata TEXT NOT NULL DEFAULT '{}';
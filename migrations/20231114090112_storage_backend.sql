@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE s3config ADD COLUMN backend TEXT NOT NULL DEFAULT 's3';
+ALTER TABLE s3config ADD COLUMN local_root TEXT;
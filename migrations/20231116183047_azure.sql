@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS azureconfig (
+  config_id INTEGER PRIMARY KEY,
+  account_name TEXT NOT NULL,
+  account_key TEXT,
+  sas_token TEXT,
+  container TEXT NOT NULL,
+  endpoint TEXT,
+
+  FOREIGN KEY (config_id) REFERENCES s3config (id) ON DELETE CASCADE
+);
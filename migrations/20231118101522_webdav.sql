@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS webdavconfig (
+  config_id INTEGER PRIMARY KEY,
+  url TEXT NOT NULL,
+  username TEXT NOT NULL,
+  password TEXT NOT NULL,
+  share_links BOOLEAN NOT NULL DEFAULT 0,
+
+  FOREIGN KEY (config_id) REFERENCES s3config (id) ON DELETE CASCADE
+);
@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS sftpconfig (
+  config_id INTEGER PRIMARY KEY,
+  host TEXT NOT NULL,
+  port INTEGER NOT NULL DEFAULT 22,
+  username TEXT NOT NULL,
+  password TEXT,
+  key_file TEXT,
+  key_passphrase TEXT,
+  remote_dir TEXT NOT NULL,
+
+  FOREIGN KEY (config_id) REFERENCES s3config (id) ON DELETE CASCADE
+);
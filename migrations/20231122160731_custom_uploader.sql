@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS customuploader (
+  config_id INTEGER PRIMARY KEY,
+  definition TEXT NOT NULL,
+
+  FOREIGN KEY (config_id) REFERENCES s3config (id) ON DELETE CASCADE
+);
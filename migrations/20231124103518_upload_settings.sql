@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS upload_settings (
+  id INTEGER PRIMARY KEY CHECK (id = 0),
+  max_concurrent_jobs INTEGER NOT NULL DEFAULT 2
+);
+
+INSERT OR IGNORE INTO upload_settings (id) VALUES (0);
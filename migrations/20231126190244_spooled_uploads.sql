@@ -0,0 +1,16 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS spooled_uploads (
+  id INTEGER PRIMARY KEY,
+  config_id INTEGER NOT NULL,
+  -- picked from the key template once the upload goes through if not set
+  obj_name TEXT,
+  mime_type TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  metadata TEXT NOT NULL DEFAULT '{}',
+  attempts INTEGER NOT NULL DEFAULT 0,
+  last_error TEXT,
+  next_attempt_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+  FOREIGN KEY (config_id) REFERENCES s3config (id) ON DELETE CASCADE
+);
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- objects imported from a bucket know their size, uploads made by the app leave it unset
+ALTER TABLE uploads ADD COLUMN size INTEGER;
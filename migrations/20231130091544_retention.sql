@@ -0,0 +1,14 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS retention_rules (
+  id INTEGER PRIMARY KEY,
+  config_id INTEGER NOT NULL,
+  -- the part of the mime type before the slash, a rule without one applies
+  -- to every upload which no more specific rule covers
+  mime_class TEXT,
+  max_age_days INTEGER NOT NULL,
+
+  FOREIGN KEY (config_id) REFERENCES s3config (id) ON DELETE CASCADE
+);
+
+-- pinned uploads are never expired
+ALTER TABLE uploads ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,23 @@
+-- Add migration script here
+-- configs which get a copy of every upload made with the selected config
+CREATE TABLE IF NOT EXISTS replica_configs (
+  config_id INTEGER PRIMARY KEY,
+
+  FOREIGN KEY (config_id) REFERENCES s3config (id) ON DELETE CASCADE
+);
+
+-- the copies of an upload, the upload itself is the primary copy
+CREATE TABLE IF NOT EXISTS upload_replicas (
+  id INTEGER PRIMARY KEY,
+  upload_id INTEGER NOT NULL,
+  config_id INTEGER,
+  obj_key TEXT NOT NULL,
+  -- set once the copy is stored
+  url TEXT,
+  -- why the copy failed
+  error TEXT,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+  FOREIGN KEY (upload_id) REFERENCES uploads (id) ON DELETE CASCADE,
+  FOREIGN KEY (config_id) REFERENCES s3config (id) ON DELETE SET NULL
+);
@@ -0,0 +1,8 @@
+-- Add migration script here
+-- custom uploaders are deleted through a url from their response, which was
+-- kept in obj_key until now
+ALTER TABLE uploads ADD COLUMN deletion_url TEXT;
+
+UPDATE uploads SET deletion_url = obj_key
+WHERE (obj_key LIKE 'http://%' OR obj_key LIKE 'https://%')
+  AND config_id IN (SELECT id FROM s3config WHERE backend = 'custom');
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- permanent failures, such as a denied request, wait to be retried by hand
+ALTER TABLE spooled_uploads ADD COLUMN failure_kind TEXT NOT NULL DEFAULT 'retryable';
@@ -0,0 +1,8 @@
+-- Add migration script here
+-- imported objects keep their LastModified as created_at, retention counts
+-- from when they were imported instead
+ALTER TABLE uploads ADD COLUMN imported_at DATETIME;
+
+-- the background sweep holds back a rule which would delete many uploads at
+-- once until a sweep started by hand confirmed it
+ALTER TABLE retention_rules ADD COLUMN confirmed BOOLEAN NOT NULL DEFAULT FALSE;
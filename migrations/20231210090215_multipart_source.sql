@@ -0,0 +1,4 @@
+-- Add migration script here
+-- uploads streamed from a file remember it, so an interrupted one can be
+-- resumed by reading the rest of the file again
+ALTER TABLE multipart_uploads ADD COLUMN source_path TEXT;
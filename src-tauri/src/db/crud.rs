@@ -1,13 +1,29 @@
 use crate::{
-    error::{AnyhowError, AppError}, s3::uploader::S3Config,
+    error::{AnyhowError, AppError},
+    s3::encryption::{validate_customer_key, EncryptionMode, ServerSideEncryption, STORAGE_CLASSES},
+    s3::integrity::ChecksumAlgorithm,
+    s3::metadata::{validate_metadata_key, ContentDisposition, ObjectMetadata, METADATA_PLACEHOLDERS},
+    s3::uploader::{
+        AccessMode, AddressingStyle, CompletedData, PartSizing, S3Config, DEFAULT_MAX_CONCURRENT_PARTS,
+        DEFAULT_MAX_PART_SIZE_MIB, DEFAULT_MIN_PART_SIZE_MIB, DEFAULT_PRESIGN_EXPIRY_SECS,
+        URL_TEMPLATE_PLACEHOLDERS,
+    },
+    s3::retention::MimeClass,
+    s3::retry::FailureKind,
+    storage::{
+        azure::validate_account_key, custom::CustomUploader, keys::{ObjectKind, KEY_TEMPLATE_PLACEHOLDERS},
+        replicated::Replica, BackendKind,
+    },
+    template,
 };
 use async_trait::async_trait;
+use std::{collections::BTreeMap, path::Path, time::Duration};
 use mime::Mime;
-use rusty_s3::{Bucket, Credentials, UrlStyle};
+use rusty_s3::{Bucket, Credentials};
 use serde::{Deserialize, Serialize};
-use sqlx::{sqlite::SqliteQueryResult, FromRow, SqlitePool};
+use sqlx::{sqlite::SqliteQueryResult, types::Json, FromRow, SqlitePool};
 use tauri_plugin_http::reqwest::Url;
-use validator::Validate;
+use validator::{Validate, ValidationError};
 
 pub trait Identity<T> {
     fn identity(&self) -> T;
@@ -52,28 +68,570 @@ pub trait List: Sized {
 }
 
 #[derive(Debug, FromRow, Clone, Default, Validate, Serialize, Deserialize)]
+#[validate(schema(function = "validate_part_sizes", skip_on_field_errors = true))]
+#[validate(schema(function = "validate_encryption", skip_on_field_errors = true))]
+#[validate(schema(function = "validate_backend", skip_on_field_errors = true))]
 pub struct S3ConfigFields {
-    #[validate(length(min = 1, message = "Required Field"))]
+    #[serde(default)]
+    pub backend: BackendKind,
+
+    // the S3 fields are checked by `validate_backend` as only S3 needs them
     pub private_key: String,
 
-    #[validate(length(min = 1, message = "Required Field"))]
     pub public_key: String,
 
     #[validate(length(min = 1, message = "Required Field"))]
     pub nickname: String,
 
-    #[validate(url(message = "Must be a valid url"))]
     pub endpoint: String,
 
-    #[validate(length(min = 1, message = "Required Field"))]
     pub region: String,
 
-    #[validate(length(min = 1, message = "Required Field"))]
     pub bucket_name: String,
 
+    /// directory the local backend stores objects in
+    #[sqlx(default)]
+    #[serde(default)]
+    pub local_root: Option<String>,
+
     #[sqlx(default)]
     #[validate(url(message = "Must be a valid url or empty"))]
     pub host_rewrite: Option<String>,
+
+    #[sqlx(default)]
+    #[serde(default)]
+    #[validate(custom = "validate_url_template")]
+    pub url_template: Option<String>,
+
+    #[sqlx(default)]
+    #[serde(default)]
+    #[validate(custom = "validate_key_template")]
+    pub key_template: Option<String>,
+
+    #[sqlx(default)]
+    #[serde(default)]
+    pub key_prefix: Option<String>,
+
+    #[serde(default = "default_max_concurrent_parts")]
+    #[validate(range(min = 1, max = 32, message = "Must be between 1 and 32"))]
+    pub max_concurrent_parts: i64,
+
+    #[serde(default)]
+    pub access_mode: AccessMode,
+
+    #[serde(default = "default_presign_expiry_secs")]
+    #[validate(range(min = 1, max = 604800, message = "Must be between 1 second and 7 days"))]
+    pub presign_expiry_secs: i64,
+
+    #[serde(default)]
+    pub url_style: AddressingStyle,
+
+    #[serde(default)]
+    pub disable_acl: bool,
+
+    #[serde(default)]
+    pub unsigned_payload: bool,
+
+    #[serde(default)]
+    pub checksum_algorithm: ChecksumAlgorithm,
+
+    /// server-side encryption requested for every upload
+    #[serde(default)]
+    pub encryption: EncryptionMode,
+
+    #[sqlx(default)]
+    #[serde(default)]
+    pub kms_key_id: Option<String>,
+
+    /// base64 encoded 256 bit key for SSE-C
+    #[sqlx(default)]
+    #[serde(default)]
+    pub customer_key: Option<String>,
+
+    #[sqlx(default)]
+    #[serde(default)]
+    #[validate(custom = "validate_storage_class")]
+    pub storage_class: Option<String>,
+
+    #[sqlx(default)]
+    #[serde(default)]
+    pub cache_control: Option<String>,
+
+    #[serde(default)]
+    pub content_disposition: ContentDisposition,
+
+    /// `x-amz-meta-*` entries, values may use the metadata placeholders
+    #[serde(default)]
+    #[validate(custom = "validate_metadata")]
+    pub metadata: Json<BTreeMap<String, String>>,
+
+    /// AWS rejects parts under 5 MiB
+    #[serde(default = "default_min_part_size_mib")]
+    #[validate(range(min = 5, max = 5120, message = "Must be between 5 and 5120 MiB"))]
+    pub min_part_size_mib: i64,
+
+    #[serde(default = "default_max_part_size_mib")]
+    #[validate(range(min = 5, max = 5120, message = "Must be between 5 and 5120 MiB"))]
+    pub max_part_size_mib: i64,
+
+    /// stored in `azureconfig`, only set for Azure configs
+    #[sqlx(skip)]
+    #[serde(default)]
+    #[validate]
+    pub azure: Option<AzureConfigFields>,
+
+    /// stored in `webdavconfig`, only set for WebDAV configs
+    #[sqlx(skip)]
+    #[serde(default)]
+    #[validate]
+    pub webdav: Option<WebdavConfigFields>,
+
+    /// stored in `sftpconfig`, only set for SFTP configs
+    #[sqlx(skip)]
+    #[serde(default)]
+    #[validate]
+    pub sftp: Option<SftpConfigFields>,
+
+    /// stored in `customuploader`, only set for custom uploader configs
+    #[sqlx(skip)]
+    #[serde(default)]
+    #[validate]
+    pub custom: Option<CustomUploaderFields>,
+}
+
+#[derive(Debug, FromRow, Clone, Default, Validate, Serialize, Deserialize)]
+#[validate(schema(function = "validate_azure_auth", skip_on_field_errors = true))]
+pub struct AzureConfigFields {
+    #[validate(length(min = 1, message = "Required Field"))]
+    pub account_name: String,
+
+    /// base64 encoded account key, requests are signed with Shared Key
+    #[sqlx(default)]
+    #[serde(default)]
+    pub account_key: Option<String>,
+
+    /// used instead of the account key when set, needs read, create and
+    /// write permissions and delete for removing uploads
+    #[sqlx(default)]
+    #[serde(default)]
+    pub sas_token: Option<String>,
+
+    #[validate(length(min = 1, message = "Required Field"))]
+    pub container: String,
+
+    /// the blob endpoint including the account, for Azurite
+    /// `http://127.0.0.1:10000/devstoreaccount1`. Defaults to
+    /// `https://{account_name}.blob.core.windows.net`
+    #[sqlx(default)]
+    #[serde(default)]
+    #[validate(url(message = "Must be a valid url or empty"))]
+    pub endpoint: Option<String>,
+}
+
+fn validate_azure_auth(fields: &AzureConfigFields) -> Result<(), ValidationError> {
+    let message = match (
+        non_empty(fields.account_key.clone()),
+        non_empty(fields.sas_token.clone()),
+    ) {
+        (None, None) => "Either an account key or a SAS token is required",
+        (Some(key), None) if validate_account_key(&key).is_err() => {
+            "The account key must be base64 encoded"
+        }
+        _ => return Ok(()),
+    };
+    let mut e = ValidationError::new("azure_auth");
+    e.message = Some(message.into());
+    Err(e)
+}
+
+impl AzureConfigFields {
+    async fn read(config_id: i64, conn: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM azureconfig WHERE config_id = ?")
+            .bind(config_id)
+            .fetch_optional(conn)
+            .await
+    }
+
+    /// Store the Azure settings of a config, or remove them when the config
+    /// no longer has any
+    async fn store(
+        config_id: i64,
+        fields: Option<&Self>,
+        conn: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        match fields {
+            Some(fields) => {
+                sqlx::query("INSERT OR REPLACE INTO azureconfig (config_id, account_name, account_key, sas_token, container, endpoint) VALUES (?, ?, ?, ?, ?, ?)")
+                    .bind(config_id)
+                    .bind(&fields.account_name)
+                    .bind(&fields.account_key)
+                    .bind(&fields.sas_token)
+                    .bind(&fields.container)
+                    .bind(&fields.endpoint)
+                    .execute(conn)
+                    .await?;
+            }
+            None => {
+                sqlx::query("DELETE FROM azureconfig WHERE config_id = ?")
+                    .bind(config_id)
+                    .execute(conn)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, FromRow, Clone, Default, Validate, Serialize, Deserialize)]
+#[validate(schema(function = "validate_webdav_shares", skip_on_field_errors = true))]
+pub struct WebdavConfigFields {
+    /// the collection uploads go to, for Nextcloud
+    /// `https://cloud.example.com/remote.php/dav/files/{user}/{dir}`
+    #[validate(url(message = "Must be a valid url"))]
+    pub url: String,
+
+    #[validate(length(min = 1, message = "Required Field"))]
+    pub username: String,
+
+    /// an app password for Nextcloud
+    pub password: String,
+
+    /// create a public link share for every upload, Nextcloud and ownCloud
+    /// only
+    #[serde(default)]
+    pub share_links: bool,
+}
+
+fn validate_webdav_shares(fields: &WebdavConfigFields) -> Result<(), ValidationError> {
+    if !fields.share_links || fields.url.contains("/remote.php/") {
+        return Ok(());
+    }
+    let mut e = ValidationError::new("share_links");
+    e.message = Some("Share links need a Nextcloud or ownCloud url below /remote.php/".into());
+    Err(e)
+}
+
+impl WebdavConfigFields {
+    async fn read(config_id: i64, conn: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM webdavconfig WHERE config_id = ?")
+            .bind(config_id)
+            .fetch_optional(conn)
+            .await
+    }
+
+    /// Store the WebDAV settings of a config, or remove them when the
+    /// config no longer has any
+    async fn store(
+        config_id: i64,
+        fields: Option<&Self>,
+        conn: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        match fields {
+            Some(fields) => {
+                sqlx::query("INSERT OR REPLACE INTO webdavconfig (config_id, url, username, password, share_links) VALUES (?, ?, ?, ?, ?)")
+                    .bind(config_id)
+                    .bind(&fields.url)
+                    .bind(&fields.username)
+                    .bind(&fields.password)
+                    .bind(fields.share_links)
+                    .execute(conn)
+                    .await?;
+            }
+            None => {
+                sqlx::query("DELETE FROM webdavconfig WHERE config_id = ?")
+                    .bind(config_id)
+                    .execute(conn)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, FromRow, Clone, Default, Validate, Serialize, Deserialize)]
+#[validate(schema(function = "validate_sftp_auth", skip_on_field_errors = true))]
+pub struct SftpConfigFields {
+    #[validate(length(min = 1, message = "Required Field"))]
+    pub host: String,
+
+    #[serde(default = "default_sftp_port")]
+    #[validate(range(min = 1, max = 65535, message = "Must be between 1 and 65535"))]
+    pub port: i64,
+
+    #[validate(length(min = 1, message = "Required Field"))]
+    pub username: String,
+
+    #[sqlx(default)]
+    #[serde(default)]
+    pub password: Option<String>,
+
+    /// private key used instead of the password when set, `~/` is expanded
+    #[sqlx(default)]
+    #[serde(default)]
+    pub key_file: Option<String>,
+
+    #[sqlx(default)]
+    #[serde(default)]
+    pub key_passphrase: Option<String>,
+
+    /// relative to the home directory unless absolute, like
+    /// `~/public_html/shots`
+    #[validate(length(min = 1, message = "Required Field"))]
+    pub remote_dir: String,
+}
+
+fn default_sftp_port() -> i64 {
+    22
+}
+
+fn validate_sftp_auth(fields: &SftpConfigFields) -> Result<(), ValidationError> {
+    if non_empty(fields.key_file.clone()).is_some() || non_empty(fields.password.clone()).is_some() {
+        return Ok(());
+    }
+    let mut e = ValidationError::new("sftp_auth");
+    e.message = Some("Either a key file or a password is required".into());
+    Err(e)
+}
+
+impl SftpConfigFields {
+    async fn read(config_id: i64, conn: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM sftpconfig WHERE config_id = ?")
+            .bind(config_id)
+            .fetch_optional(conn)
+            .await
+    }
+
+    /// Store the SFTP settings of a config, or remove them when the config
+    /// no longer has any
+    async fn store(
+        config_id: i64,
+        fields: Option<&Self>,
+        conn: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        match fields {
+            Some(fields) => {
+                sqlx::query("INSERT OR REPLACE INTO sftpconfig (config_id, host, port, username, password, key_file, key_passphrase, remote_dir) VALUES (?, ?, ?, ?, ?, ?, ?, ?)")
+                    .bind(config_id)
+                    .bind(&fields.host)
+                    .bind(fields.port)
+                    .bind(&fields.username)
+                    .bind(&fields.password)
+                    .bind(&fields.key_file)
+                    .bind(&fields.key_passphrase)
+                    .bind(&fields.remote_dir)
+                    .execute(conn)
+                    .await?;
+            }
+            None => {
+                sqlx::query("DELETE FROM sftpconfig WHERE config_id = ?")
+                    .bind(config_id)
+                    .execute(conn)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, FromRow, Clone, Default, Validate, Serialize, Deserialize)]
+pub struct CustomUploaderFields {
+    #[validate(custom = "validate_custom_uploader")]
+    pub definition: Json<CustomUploader>,
+}
+
+fn validate_custom_uploader(definition: &Json<CustomUploader>) -> Result<(), ValidationError> {
+    definition.validate().map_err(|err| {
+        let mut e = ValidationError::new("definition");
+        e.message = Some(err.to_string().into());
+        e
+    })
+}
+
+impl CustomUploaderFields {
+    async fn read(config_id: i64, conn: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM customuploader WHERE config_id = ?")
+            .bind(config_id)
+            .fetch_optional(conn)
+            .await
+    }
+
+    /// Store the uploader definition of a config, or remove it when the
+    /// config no longer has one
+    async fn store(
+        config_id: i64,
+        fields: Option<&Self>,
+        conn: &SqlitePool,
+    ) -> Result<(), sqlx::Error> {
+        match fields {
+            Some(fields) => {
+                sqlx::query("INSERT OR REPLACE INTO customuploader (config_id, definition) VALUES (?, ?)")
+                    .bind(config_id)
+                    .bind(&fields.definition)
+                    .execute(conn)
+                    .await?;
+            }
+            None => {
+                sqlx::query("DELETE FROM customuploader WHERE config_id = ?")
+                    .bind(config_id)
+                    .execute(conn)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn default_min_part_size_mib() -> i64 {
+    DEFAULT_MIN_PART_SIZE_MIB as i64
+}
+
+fn default_max_part_size_mib() -> i64 {
+    DEFAULT_MAX_PART_SIZE_MIB as i64
+}
+
+fn validate_backend(fields: &S3ConfigFields) -> Result<(), ValidationError> {
+    let required: Vec<(&str, &str)> = match fields.backend {
+        BackendKind::S3 => vec![
+            ("private key", fields.private_key.as_str()),
+            ("public key", fields.public_key.as_str()),
+            ("region", fields.region.as_str()),
+            ("bucket name", fields.bucket_name.as_str()),
+        ],
+        BackendKind::Local => vec![(
+            "directory",
+            fields.local_root.as_deref().unwrap_or_default(),
+        )],
+        // the fields themselves are checked by `AzureConfigFields`
+        BackendKind::Azure => vec![(
+            "Azure settings",
+            if fields.azure.is_some() { "set" } else { "" },
+        )],
+        BackendKind::Webdav => vec![(
+            "WebDAV settings",
+            if fields.webdav.is_some() { "set" } else { "" },
+        )],
+        BackendKind::Custom => vec![(
+            "uploader definition",
+            if fields.custom.is_some() { "set" } else { "" },
+        )],
+        // files on the server are only reachable through the web server
+        BackendKind::Sftp => vec![
+            ("SFTP settings", if fields.sftp.is_some() { "set" } else { "" }),
+            (
+                "public base url",
+                fields
+                    .host_rewrite
+                    .as_deref()
+                    .filter(|s| !s.is_empty())
+                    .or(fields.url_template.as_deref())
+                    .unwrap_or_default(),
+            ),
+        ],
+    };
+    let missing: Vec<&str> = required
+        .into_iter()
+        .filter(|(_, value)| value.is_empty())
+        .map(|(name, _)| name)
+        .collect();
+
+    let message = if !missing.is_empty() {
+        format!("Required Field: {}", missing.join(", "))
+    } else if fields.backend == BackendKind::S3 && Url::parse(&fields.endpoint).is_err() {
+        "Endpoint must be a valid url".to_owned()
+    } else if fields.backend == BackendKind::Local
+        && !Path::new(fields.local_root.as_deref().unwrap_or_default()).is_absolute()
+    {
+        "Directory must be an absolute path".to_owned()
+    } else {
+        return Ok(());
+    };
+    let mut e = ValidationError::new("backend");
+    e.message = Some(message.into());
+    Err(e)
+}
+
+fn validate_part_sizes(fields: &S3ConfigFields) -> Result<(), ValidationError> {
+    if fields.min_part_size_mib <= fields.max_part_size_mib {
+        return Ok(());
+    }
+    let mut e = ValidationError::new("part_sizes");
+    e.message = Some("The minimum part size can't be larger than the maximum".into());
+    Err(e)
+}
+
+fn default_presign_expiry_secs() -> i64 {
+    DEFAULT_PRESIGN_EXPIRY_SECS as i64
+}
+
+fn default_max_concurrent_parts() -> i64 {
+    DEFAULT_MAX_CONCURRENT_PARTS as i64
+}
+
+fn validate_url_template(template: &str) -> Result<(), ValidationError> {
+    if template.is_empty() {
+        return Ok(());
+    }
+    template::validate(template, URL_TEMPLATE_PLACEHOLDERS).map_err(|_| {
+        let mut e = ValidationError::new("url_template");
+        e.message = Some("Unknown placeholder, use {key}, {filename}, {bucket} or {region}".into());
+        e
+    })
+}
+
+fn validate_encryption(fields: &S3ConfigFields) -> Result<(), ValidationError> {
+    let customer_key = fields.customer_key.as_deref().unwrap_or_default();
+    if fields.encryption != EncryptionMode::CustomerKey || validate_customer_key(customer_key).is_ok() {
+        return Ok(());
+    }
+    let mut e = ValidationError::new("encryption");
+    e.message = Some("SSE-C needs a base64 encoded 256 bit key".into());
+    Err(e)
+}
+
+fn validate_storage_class(storage_class: &str) -> Result<(), ValidationError> {
+    if storage_class.is_empty() || STORAGE_CLASSES.contains(&storage_class) {
+        return Ok(());
+    }
+    let mut e = ValidationError::new("storage_class");
+    e.message = Some(format!("Must be one of {}", STORAGE_CLASSES.join(", ")).into());
+    Err(e)
+}
+
+fn validate_metadata(metadata: &Json<BTreeMap<String, String>>) -> Result<(), ValidationError> {
+    for (key, value) in metadata.iter() {
+        if let Err(err) = validate_metadata_key(key) {
+            let mut e = ValidationError::new("metadata");
+            e.message = Some(err.to_string().into());
+            return Err(e);
+        }
+        if template::validate(value, METADATA_PLACEHOLDERS).is_err() {
+            let mut e = ValidationError::new("metadata");
+            e.message = Some(
+                "Unknown placeholder, use {hostname}, {kind}, {version} or {filename}".into(),
+            );
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+fn validate_key_template(template: &str) -> Result<(), ValidationError> {
+    if template.is_empty() {
+        return Ok(());
+    }
+    template::validate(template, KEY_TEMPLATE_PLACEHOLDERS).map_err(|_| {
+        let mut e = ValidationError::new("key_template");
+        e.message = Some(
+            "Unknown placeholder, use {yyyy}, {mm}, {dd}, {uuid}, {shortid}, {hostname}, {kind} or {ext}"
+                .into(),
+        );
+        e
+    })
+}
+
+/// The form sends empty strings for optional fields which were left blank
+fn non_empty(s: Option<String>) -> Option<String> {
+    s.filter(|s| !s.is_empty())
 }
 
 impl Identity<i64> for &S3ConfigRaw {
@@ -86,7 +644,7 @@ impl Identity<i64> for &S3ConfigRaw {
 impl Create<S3ConfigFields> for S3ConfigRaw {
     async fn create(input: S3ConfigFields, conn: &SqlitePool) -> Result<S3ConfigRaw, AppError> {
         input.validate()?;
-        let res = sqlx::query("INSERT INTO s3config (private_key, public_key, nickname, endpoint, region, bucket_name, host_rewrite) VALUES (?, ?, ?, ?, ?, ?, ?)")
+        let res = sqlx::query("INSERT INTO s3config (private_key, public_key, nickname, endpoint, region, bucket_name, host_rewrite, url_template, max_concurrent_parts, access_mode, presign_expiry_secs, url_style, disable_acl, unsigned_payload, checksum_algorithm, min_part_size_mib, max_part_size_mib, key_template, key_prefix, encryption, kms_key_id, customer_key, storage_class, cache_control, content_disposition, metadata, backend, local_root) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
             .bind(&input.private_key)
             .bind(&input.public_key)
             .bind(&input.nickname)
@@ -94,9 +652,42 @@ impl Create<S3ConfigFields> for S3ConfigRaw {
             .bind(&input.region)
             .bind(&input.bucket_name)
             .bind(&input.host_rewrite)
+            .bind(&input.url_template)
+            .bind(input.max_concurrent_parts)
+            .bind(input.access_mode)
+            .bind(input.presign_expiry_secs)
+            .bind(input.url_style)
+            .bind(input.disable_acl)
+            .bind(input.unsigned_payload)
+            .bind(input.checksum_algorithm)
+            .bind(input.min_part_size_mib)
+            .bind(input.max_part_size_mib)
+            .bind(&input.key_template)
+            .bind(&input.key_prefix)
+            .bind(input.encryption)
+            .bind(&input.kms_key_id)
+            .bind(&input.customer_key)
+            .bind(&input.storage_class)
+            .bind(&input.cache_control)
+            .bind(input.content_disposition)
+            .bind(&input.metadata)
+            .bind(input.backend)
+            .bind(&input.local_root)
             .execute(conn)
             .await.map_err(AppError::anyhow)?;
         let id = res.last_insert_rowid();
+        AzureConfigFields::store(id, input.azure.as_ref(), conn)
+            .await
+            .map_err(AppError::anyhow)?;
+        WebdavConfigFields::store(id, input.webdav.as_ref(), conn)
+            .await
+            .map_err(AppError::anyhow)?;
+        SftpConfigFields::store(id, input.sftp.as_ref(), conn)
+            .await
+            .map_err(AppError::anyhow)?;
+        CustomUploaderFields::store(id, input.custom.as_ref(), conn)
+            .await
+            .map_err(AppError::anyhow)?;
         Ok(S3ConfigRaw { fields: input, id })
     }
 }
@@ -109,12 +700,11 @@ impl Read<i64> for S3ConfigRaw {
     ) -> Result<S3ConfigRaw, AnyhowError> {
         let id = i.identity();
 
-        Ok(
-            sqlx::query_as::<_, Self>("SELECT * FROM s3config WHERE id = ?")
-                .bind(id)
-                .fetch_one(conn)
-                .await?,
-        )
+        let config = sqlx::query_as::<_, Self>("SELECT * FROM s3config WHERE id = ?")
+            .bind(id)
+            .fetch_one(conn)
+            .await?;
+        config.with_extensions(conn).await
     }
 }
 
@@ -128,7 +718,7 @@ impl Update<i64, S3ConfigFields> for S3ConfigRaw {
         input.validate().map_err(AppError::ValidationError)?;
         let id = i.identity();
 
-        sqlx::query_as::<_, Self>("UPDATE s3config SET private_key = ?, public_key = ?, nickname = ?, endpoint = ?, region = ?, bucket_name = ?, host_rewrite = ? WHERE id = ?")
+        sqlx::query("UPDATE s3config SET private_key = ?, public_key = ?, nickname = ?, endpoint = ?, region = ?, bucket_name = ?, host_rewrite = ?, url_template = ?, max_concurrent_parts = ?, access_mode = ?, presign_expiry_secs = ?, url_style = ?, disable_acl = ?, unsigned_payload = ?, checksum_algorithm = ?, min_part_size_mib = ?, max_part_size_mib = ?, key_template = ?, key_prefix = ?, encryption = ?, kms_key_id = ?, customer_key = ?, storage_class = ?, cache_control = ?, content_disposition = ?, metadata = ?, backend = ?, local_root = ? WHERE id = ?")
             .bind(&input.private_key)
             .bind(&input.public_key)
             .bind(&input.nickname)
@@ -136,9 +726,43 @@ impl Update<i64, S3ConfigFields> for S3ConfigRaw {
             .bind(&input.region)
             .bind(&input.bucket_name)
             .bind(&input.host_rewrite)
+            .bind(&input.url_template)
+            .bind(input.max_concurrent_parts)
+            .bind(input.access_mode)
+            .bind(input.presign_expiry_secs)
+            .bind(input.url_style)
+            .bind(input.disable_acl)
+            .bind(input.unsigned_payload)
+            .bind(input.checksum_algorithm)
+            .bind(input.min_part_size_mib)
+            .bind(input.max_part_size_mib)
+            .bind(&input.key_template)
+            .bind(&input.key_prefix)
+            .bind(input.encryption)
+            .bind(&input.kms_key_id)
+            .bind(&input.customer_key)
+            .bind(&input.storage_class)
+            .bind(&input.cache_control)
+            .bind(input.content_disposition)
+            .bind(&input.metadata)
+            .bind(input.backend)
+            .bind(&input.local_root)
             .bind(id)
-            .fetch_one(conn)
-            .await.map_err(|e| AppError::Anyhow(anyhow::Error::new(e)))
+            .execute(conn)
+            .await.map_err(|e| AppError::Anyhow(anyhow::Error::new(e)))?;
+        AzureConfigFields::store(id, input.azure.as_ref(), conn)
+            .await
+            .map_err(AppError::anyhow)?;
+        WebdavConfigFields::store(id, input.webdav.as_ref(), conn)
+            .await
+            .map_err(AppError::anyhow)?;
+        SftpConfigFields::store(id, input.sftp.as_ref(), conn)
+            .await
+            .map_err(AppError::anyhow)?;
+        CustomUploaderFields::store(id, input.custom.as_ref(), conn)
+            .await
+            .map_err(AppError::anyhow)?;
+        Ok(S3ConfigRaw { fields: input, id })
     }
 }
 
@@ -160,9 +784,14 @@ impl Delete<i64> for S3ConfigFields {
 #[async_trait]
 impl List for S3ConfigRaw {
     async fn list(conn: &SqlitePool) -> Result<Vec<S3ConfigRaw>, AnyhowError> {
-        Ok(sqlx::query_as::<_, S3ConfigRaw>("SELECT * FROM s3config")
+        let configs = sqlx::query_as::<_, S3ConfigRaw>("SELECT * FROM s3config")
             .fetch_all(conn)
-            .await?)
+            .await?;
+        let mut out = Vec::with_capacity(configs.len());
+        for config in configs {
+            out.push(config.with_extensions(conn).await?);
+        }
+        Ok(out)
     }
 }
 
@@ -176,18 +805,72 @@ pub struct S3ConfigRaw {
 }
 
 impl S3ConfigRaw {
+    /// A config which has not been saved yet, for trying it out
+    pub fn unsaved(fields: S3ConfigFields) -> Self {
+        Self { id: 0, fields }
+    }
+
     pub fn build(self) -> anyhow::Result<S3Config> {
         let url = Url::parse(&self.fields.endpoint)?;
 
+        let url_style = self.fields.url_style.resolve(&url);
         let bucket = Bucket::new(
             url,
-            UrlStyle::VirtualHost,
+            url_style,
             self.fields.bucket_name,
             self.fields.region,
         )?;
         let credentials = Credentials::new(self.fields.public_key, self.fields.private_key);
 
-        Ok(S3Config::new(bucket, credentials, self.fields.host_rewrite))
+        Ok(S3Config::new(
+            self.id,
+            bucket,
+            credentials,
+            non_empty(self.fields.host_rewrite),
+        )
+        .with_url_template(non_empty(self.fields.url_template))
+        .with_key_template(
+            non_empty(self.fields.key_template),
+            non_empty(self.fields.key_prefix),
+        )
+        .with_max_concurrent_parts(self.fields.max_concurrent_parts as usize)
+        .with_access_mode(
+            self.fields.access_mode,
+            Duration::from_secs(self.fields.presign_expiry_secs as u64),
+        )
+        .with_quirks(self.fields.disable_acl, self.fields.unsigned_payload)
+        .with_checksum(self.fields.checksum_algorithm)
+        .with_storage(
+            ServerSideEncryption::new(
+                self.fields.encryption,
+                non_empty(self.fields.kms_key_id),
+                non_empty(self.fields.customer_key),
+            )?,
+            non_empty(self.fields.storage_class),
+        )
+        .with_metadata(
+            non_empty(self.fields.cache_control),
+            self.fields.content_disposition,
+            self.fields.metadata.0,
+        )
+        .with_part_sizing(PartSizing::from_mib(
+            // configs saved before the minimum was raised may still ask for less
+            (self.fields.min_part_size_mib as usize).max(DEFAULT_MIN_PART_SIZE_MIB),
+            self.fields.max_part_size_mib as usize,
+        )))
+    }
+
+    pub fn backend(&self) -> BackendKind {
+        self.fields.backend
+    }
+
+    /// Load the settings which live in their own table
+    async fn with_extensions(mut self, conn: &SqlitePool) -> Result<Self, AnyhowError> {
+        self.fields.azure = AzureConfigFields::read(self.id, conn).await?;
+        self.fields.webdav = WebdavConfigFields::read(self.id, conn).await?;
+        self.fields.sftp = SftpConfigFields::read(self.id, conn).await?;
+        self.fields.custom = CustomUploaderFields::read(self.id, conn).await?;
+        Ok(self)
     }
 
     pub fn into_parts(self) -> (i64, S3ConfigFields) {
@@ -203,11 +886,47 @@ pub struct SelectedConfig {
 
 impl SelectedConfig {
     pub async fn get(conn: &SqlitePool) -> Result<Option<S3ConfigRaw>, AnyhowError> {
-        Ok(
-            sqlx::query_as::<_, S3ConfigRaw>("SELECT s3config.* FROM selected_config LEFT JOIN s3config ON selected_config.config_id = s3config.id")
-                .fetch_optional(conn)
-                .await?
+        let config = sqlx::query_as::<_, S3ConfigRaw>("SELECT s3config.* FROM selected_config LEFT JOIN s3config ON selected_config.config_id = s3config.id")
+            .fetch_optional(conn)
+            .await?;
+        match config {
+            Some(config) => Ok(Some(config.with_extensions(conn).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Configs which get a copy of every upload, the selected config is
+    /// never one of them
+    pub async fn replicas(conn: &SqlitePool) -> Result<Vec<S3ConfigRaw>, AnyhowError> {
+        let configs = sqlx::query_as::<_, S3ConfigRaw>(
+            "SELECT s3config.* FROM replica_configs JOIN s3config ON replica_configs.config_id = s3config.id WHERE s3config.id IS NOT (SELECT config_id FROM selected_config WHERE id = 0) ORDER BY s3config.id ASC",
         )
+        .fetch_all(conn)
+        .await?;
+        let mut out = Vec::with_capacity(configs.len());
+        for config in configs {
+            out.push(config.with_extensions(conn).await?);
+        }
+        Ok(out)
+    }
+
+    pub async fn replica_ids(conn: &SqlitePool) -> Result<Vec<i64>, AnyhowError> {
+        Ok(sqlx::query_scalar::<_, i64>("SELECT config_id FROM replica_configs ORDER BY config_id ASC")
+            .fetch_all(conn)
+            .await?)
+    }
+
+    pub async fn set_replicas(ids: &[i64], conn: &SqlitePool) -> Result<(), AnyhowError> {
+        sqlx::query("DELETE FROM replica_configs")
+            .execute(conn)
+            .await?;
+        for id in ids {
+            sqlx::query("INSERT OR IGNORE INTO replica_configs (config_id) VALUES (?)")
+                .bind(id)
+                .execute(conn)
+                .await?;
+        }
+        Ok(())
     }
 
     pub async fn set(id: impl Identity<i64>, conn: &SqlitePool) -> Result<(), AnyhowError> {
@@ -221,23 +940,201 @@ impl SelectedConfig {
     }
 }
 
+pub const DEFAULT_MAX_CONCURRENT_JOBS: i64 = 2;
+
+/// Settings which apply to every upload regardless of its config
+#[derive(FromRow, Serialize, Deserialize, Debug, Clone, Copy, Validate)]
+pub struct UploadSettings {
+    /// how many queued uploads are sent at the same time, recordings are
+    /// streamed as they happen and never wait in the queue
+    #[validate(range(min = 1, max = 16, message = "Must be between 1 and 16"))]
+    pub max_concurrent_jobs: i64,
+}
+
+impl Default for UploadSettings {
+    fn default() -> Self {
+        Self {
+            max_concurrent_jobs: DEFAULT_MAX_CONCURRENT_JOBS,
+        }
+    }
+}
+
+impl UploadSettings {
+    pub async fn get(conn: &SqlitePool) -> Result<UploadSettings, AnyhowError> {
+        let settings = sqlx::query_as::<_, UploadSettings>(
+            "SELECT max_concurrent_jobs FROM upload_settings WHERE id = 0",
+        )
+        .fetch_optional(conn)
+        .await?;
+        Ok(settings.unwrap_or_default())
+    }
+
+    pub async fn set(input: UploadSettings, conn: &SqlitePool) -> Result<UploadSettings, AppError> {
+        input.validate()?;
+        sqlx::query("INSERT OR REPLACE INTO upload_settings (id, max_concurrent_jobs) VALUES (0, ?)")
+            .bind(input.max_concurrent_jobs)
+            .execute(conn)
+            .await
+            .map_err(AppError::anyhow)?;
+        Ok(input)
+    }
+}
+
 #[derive(FromRow, Serialize, Deserialize, Debug)]
 pub struct Upload {
     id: i64,
     url: String,
     created_at: String,
     mime_type: String,
+    obj_key: Option<String>,
+    config_id: Option<i64>,
+    size: Option<i64>,
+    pinned: bool,
+    /// custom uploaders are deleted through a url from their response
+    #[serde(skip)]
+    deletion_url: Option<String>,
 }
 
 impl Upload {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
     pub fn url(&self) -> Result<Url, AnyhowError> {
         Ok(Url::parse(&self.url)?)
     }
+
+    /// The key of the object in the bucket. Uploads made before keys were
+    /// stored fall back to the path of their url.
+    pub fn obj_key(&self) -> Result<String, AnyhowError> {
+        if let Some(key) = &self.obj_key {
+            return Ok(key.clone());
+        }
+        let url = self.url()?;
+        Ok(url.path().trim_start_matches('/').to_owned())
+    }
+
+    pub fn config_id(&self) -> Option<i64> {
+        self.config_id
+    }
+
+    pub fn deletion_url(&self) -> Option<&str> {
+        self.deletion_url.as_deref()
+    }
+
+    /// Pinned uploads are kept no matter what the retention rules say
+    pub async fn set_pinned(
+        i: impl Identity<i64>,
+        pinned: bool,
+        conn: &SqlitePool,
+    ) -> Result<Upload, AnyhowError> {
+        Ok(
+            sqlx::query_as::<_, Upload>("UPDATE uploads SET pinned = ? WHERE id = ? RETURNING *")
+                .bind(pinned)
+                .bind(i.identity())
+                .fetch_one(conn)
+                .await?,
+        )
+    }
+
+    /// Uploads which are older than the retention rule of their config
+    /// allows. A rule for the mime class of an upload takes precedence over
+    /// the rule for the whole config. Imported uploads are aged from when
+    /// they were imported.
+    pub async fn expired(conn: &SqlitePool) -> Result<Vec<ExpiredUpload>, AnyhowError> {
+        Ok(sqlx::query_as::<_, ExpiredUpload>(
+            "SELECT uploads.*, rule.max_age_days, rule.id AS rule_id, rule.confirmed AS rule_confirmed FROM uploads JOIN retention_rules AS rule ON rule.id = (SELECT r.id FROM retention_rules AS r WHERE r.config_id = uploads.config_id AND (r.mime_class IS NULL OR uploads.mime_type LIKE r.mime_class || '/%') ORDER BY r.mime_class IS NULL, r.max_age_days DESC LIMIT 1) WHERE NOT uploads.pinned AND COALESCE(uploads.imported_at, uploads.created_at) < datetime('now', '-' || rule.max_age_days || ' days') ORDER BY uploads.created_at ASC",
+        )
+        .fetch_all(conn)
+        .await?)
+    }
+
+    pub async fn set_url(
+        i: impl Identity<i64>,
+        url: &Url,
+        conn: &SqlitePool,
+    ) -> Result<Upload, AnyhowError> {
+        Ok(
+            sqlx::query_as::<_, Upload>("UPDATE uploads SET url = ? WHERE id = ? RETURNING *")
+                .bind(url.to_string())
+                .bind(i.identity())
+                .fetch_one(conn)
+                .await?,
+        )
+    }
+
+    /// Add an object to the history unless it is in there already, in which
+    /// case nothing is returned
+    pub async fn import(
+        input: ImportedUpload,
+        conn: &SqlitePool,
+    ) -> Result<Option<Upload>, AnyhowError> {
+        Ok(sqlx::query_as::<_, Upload>(
+            "INSERT INTO uploads (url, mime_type, obj_key, config_id, size, created_at, imported_at) SELECT ?, ?, ?, ?, ?, COALESCE(datetime(?), CURRENT_TIMESTAMP), CURRENT_TIMESTAMP WHERE NOT EXISTS (SELECT 1 FROM uploads WHERE config_id = ? AND obj_key = ?) RETURNING *",
+        )
+        .bind(input.url.to_string())
+        .bind(input.mime.to_string())
+        .bind(&input.obj_key)
+        .bind(input.config_id)
+        .bind(input.size)
+        .bind(&input.last_modified)
+        .bind(input.config_id)
+        .bind(&input.obj_key)
+        .fetch_optional(conn)
+        .await?)
+    }
 }
 
 pub struct UploadBuilder {
     pub url: Url,
+    pub obj_key: String,
+    pub config_id: i64,
     pub mime: Mime,
+    pub deletion_url: Option<String>,
+    pub replicas: Vec<Replica>,
+}
+
+impl UploadBuilder {
+    pub fn completed(completed: CompletedData, mime: Mime) -> Self {
+        Self {
+            url: completed.upload_url,
+            obj_key: completed.obj_key,
+            config_id: completed.config_id,
+            mime,
+            deletion_url: completed.deletion_url,
+            replicas: completed.replicas,
+        }
+    }
+}
+
+/// An object which is already in a bucket and is added to the history
+pub struct ImportedUpload {
+    pub url: Url,
+    pub obj_key: String,
+    pub config_id: i64,
+    pub mime: Mime,
+    pub size: i64,
+    /// RFC 3339 timestamp from the bucket listing
+    pub last_modified: String,
+}
+
+impl Identity<i64> for &Upload {
+    fn identity(&self) -> i64 {
+        self.id
+    }
+}
+
+/// An upload which a retention rule says should be deleted
+#[derive(FromRow, Serialize, Debug)]
+pub struct ExpiredUpload {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub upload: Upload,
+    /// the age limit of the rule which applies to it
+    pub max_age_days: i64,
+    pub rule_id: i64,
+    /// whether a sweep started by hand has deleted uploads for the rule
+    pub rule_confirmed: bool,
 }
 
 #[async_trait]
@@ -256,14 +1153,55 @@ impl Read<i64> for Upload {
 #[async_trait]
 impl Create<UploadBuilder> for Upload {
     async fn create(input: UploadBuilder, conn: &SqlitePool) -> Result<Upload, AppError> {
-        sqlx::query_as::<_, Upload>(
-            "INSERT INTO uploads (url, mime_type) VALUES (?, ?) RETURNING *",
+        let upload = sqlx::query_as::<_, Upload>(
+            "INSERT INTO uploads (url, mime_type, obj_key, config_id, deletion_url) VALUES (?, ?, ?, ?, ?) RETURNING *",
         )
         .bind(input.url.to_string())
         .bind(input.mime.to_string())
+        .bind(&input.obj_key)
+        .bind(input.config_id)
+        .bind(&input.deletion_url)
         .fetch_one(conn)
         .await
-        .map_err(AppError::anyhow)
+        .map_err(AppError::anyhow)?;
+        for replica in &input.replicas {
+            sqlx::query(
+                "INSERT INTO upload_replicas (upload_id, config_id, obj_key, url, error) VALUES (?, ?, ?, ?, ?)",
+            )
+            .bind(upload.id)
+            .bind(replica.config_id)
+            .bind(&replica.obj_key)
+            .bind(replica.url.as_ref().map(Url::to_string))
+            .bind(&replica.error)
+            .execute(conn)
+            .await
+            .map_err(AppError::anyhow)?;
+        }
+        Ok(upload)
+    }
+}
+
+/// A copy of an upload which was sent to a replica config
+#[derive(FromRow, Serialize, Debug)]
+pub struct UploadReplica {
+    id: i64,
+    pub upload_id: i64,
+    pub config_id: Option<i64>,
+    pub obj_key: String,
+    pub url: Option<String>,
+    /// set when the copy failed
+    pub error: Option<String>,
+    created_at: String,
+}
+
+#[async_trait]
+impl List for UploadReplica {
+    async fn list(conn: &SqlitePool) -> Result<Vec<UploadReplica>, AnyhowError> {
+        Ok(sqlx::query_as::<_, UploadReplica>(
+            "SELECT * FROM upload_replicas ORDER BY upload_id DESC, config_id ASC",
+        )
+        .fetch_all(conn)
+        .await?)
     }
 }
 
@@ -271,7 +1209,7 @@ impl Create<UploadBuilder> for Upload {
 impl List for Upload {
     async fn list(conn: &SqlitePool) -> Result<Vec<Upload>, AnyhowError> {
         Ok(
-            sqlx::query_as::<_, Upload>("SELECT * from uploads ORDER BY id DESC")
+            sqlx::query_as::<_, Upload>("SELECT * from uploads ORDER BY created_at DESC, id DESC")
                 .fetch_all(conn)
                 .await?,
         )
@@ -291,3 +1229,399 @@ impl Delete<i64> for Upload {
             .await?)
     }
 }
+
+/// A multipart upload which was started but never completed. Rows are written
+/// as soon as the upload is created and removed once it is completed, so any
+/// rows left over on startup belong to uploads that were interrupted.
+#[derive(FromRow, Serialize, Deserialize, Debug, Clone)]
+pub struct PendingMultipart {
+    id: i64,
+    pub config_id: i64,
+    pub multipart_id: String,
+    pub obj_name: String,
+    pub mime_type: String,
+    pub checksum_algorithm: ChecksumAlgorithm,
+    created_at: String,
+    /// the file the upload is read from, recordings have none and can't be
+    /// resumed
+    pub source_path: Option<String>,
+}
+
+impl PendingMultipart {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn mime(&self) -> Result<Mime, AnyhowError> {
+        Ok(self.mime_type.parse::<Mime>()?)
+    }
+
+    pub async fn parts(
+        i: impl Identity<i64>,
+        conn: &SqlitePool,
+    ) -> Result<Vec<PendingPart>, AnyhowError> {
+        Ok(sqlx::query_as::<_, PendingPart>(
+            "SELECT part_number, etag, size, checksum FROM multipart_parts WHERE upload_id = ? ORDER BY part_number ASC",
+        )
+        .bind(i.identity())
+        .fetch_all(conn)
+        .await?)
+    }
+
+    pub async fn delete_by_multipart_id(
+        multipart_id: &str,
+        conn: &SqlitePool,
+    ) -> Result<SqliteQueryResult, AnyhowError> {
+        Ok(sqlx::query("DELETE FROM multipart_uploads WHERE multipart_id = ?")
+            .bind(multipart_id)
+            .execute(conn)
+            .await?)
+    }
+
+    pub async fn record_part(
+        i: impl Identity<i64>,
+        part: &PendingPart,
+        conn: &SqlitePool,
+    ) -> Result<(), AnyhowError> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO multipart_parts (upload_id, part_number, etag, size, checksum) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(i.identity())
+        .bind(part.part_number)
+        .bind(&part.etag)
+        .bind(part.size)
+        .bind(&part.checksum)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Forget the parts numbered above `part_number`, they are sent again
+    pub async fn forget_parts_after(
+        i: impl Identity<i64>,
+        part_number: i64,
+        conn: &SqlitePool,
+    ) -> Result<(), AnyhowError> {
+        sqlx::query("DELETE FROM multipart_parts WHERE upload_id = ? AND part_number > ?")
+            .bind(i.identity())
+            .bind(part_number)
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+}
+
+impl Identity<i64> for &PendingMultipart {
+    fn identity(&self) -> i64 {
+        self.id
+    }
+}
+
+#[derive(FromRow, Serialize, Deserialize, Debug, Clone)]
+pub struct PendingPart {
+    pub part_number: i64,
+    pub etag: String,
+    pub size: i64,
+    /// base64 checksum sent with the part, if the upload uses checksums
+    pub checksum: Option<String>,
+}
+
+pub struct PendingMultipartBuilder {
+    pub config_id: i64,
+    pub multipart_id: String,
+    pub obj_name: String,
+    pub mime: Mime,
+    pub checksum_algorithm: ChecksumAlgorithm,
+    pub source_path: Option<String>,
+}
+
+#[async_trait]
+impl Create<PendingMultipartBuilder> for PendingMultipart {
+    async fn create(
+        input: PendingMultipartBuilder,
+        conn: &SqlitePool,
+    ) -> Result<PendingMultipart, AppError> {
+        sqlx::query_as::<_, PendingMultipart>(
+            "INSERT INTO multipart_uploads (config_id, multipart_id, obj_name, mime_type, checksum_algorithm, source_path) VALUES (?, ?, ?, ?, ?, ?) RETURNING *",
+        )
+        .bind(input.config_id)
+        .bind(&input.multipart_id)
+        .bind(&input.obj_name)
+        .bind(input.mime.to_string())
+        .bind(input.checksum_algorithm)
+        .bind(&input.source_path)
+        .fetch_one(conn)
+        .await
+        .map_err(AppError::anyhow)
+    }
+}
+
+#[async_trait]
+impl Read<i64> for PendingMultipart {
+    async fn read<U: Identity<i64> + Send>(
+        i: U,
+        conn: &SqlitePool,
+    ) -> Result<PendingMultipart, AnyhowError> {
+        let id = i.identity();
+        Ok(
+            sqlx::query_as::<_, PendingMultipart>("SELECT * FROM multipart_uploads WHERE id = ?")
+                .bind(id)
+                .fetch_one(conn)
+                .await?,
+        )
+    }
+}
+
+#[async_trait]
+impl List for PendingMultipart {
+    async fn list(conn: &SqlitePool) -> Result<Vec<PendingMultipart>, AnyhowError> {
+        Ok(sqlx::query_as::<_, PendingMultipart>(
+            "SELECT * FROM multipart_uploads ORDER BY id DESC",
+        )
+        .fetch_all(conn)
+        .await?)
+    }
+}
+
+#[async_trait]
+impl Delete<i64> for PendingMultipart {
+    async fn delete<U: Identity<i64> + Send>(
+        i: U,
+        conn: &SqlitePool,
+    ) -> Result<SqliteQueryResult, AnyhowError> {
+        let id = i.identity();
+        Ok(sqlx::query("DELETE FROM multipart_uploads WHERE id = ?")
+            .bind(id)
+            .execute(conn)
+            .await?)
+    }
+}
+
+/// An upload which failed, its bytes are kept in the spool directory until
+/// it is retried successfully
+#[derive(FromRow, Serialize, Debug)]
+pub struct SpooledUpload {
+    id: i64,
+    pub config_id: i64,
+    pub obj_name: Option<String>,
+    pub mime_type: String,
+    pub kind: ObjectKind,
+    #[serde(skip)]
+    pub metadata: Json<ObjectMetadata>,
+    pub attempts: i64,
+    pub last_error: Option<String>,
+    /// permanent failures are only tried again by hand
+    pub failure_kind: FailureKind,
+    pub next_attempt_at: String,
+    created_at: String,
+}
+
+impl SpooledUpload {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn mime(&self) -> Result<Mime, AnyhowError> {
+        Ok(self.mime_type.parse::<Mime>()?)
+    }
+
+    /// Uploads which are due to be tried again
+    pub async fn due(conn: &SqlitePool) -> Result<Vec<SpooledUpload>, AnyhowError> {
+        Ok(sqlx::query_as::<_, SpooledUpload>(
+            "SELECT * FROM spooled_uploads WHERE failure_kind = 'retryable' AND next_attempt_at <= CURRENT_TIMESTAMP ORDER BY id ASC",
+        )
+        .fetch_all(conn)
+        .await?)
+    }
+
+    /// Another attempt failed, try again once `retry_in` has passed
+    pub async fn failed(
+        i: impl Identity<i64>,
+        error: &str,
+        kind: FailureKind,
+        retry_in: Duration,
+        conn: &SqlitePool,
+    ) -> Result<(), AnyhowError> {
+        sqlx::query(
+            "UPDATE spooled_uploads SET attempts = attempts + 1, last_error = ?, failure_kind = ?, next_attempt_at = datetime('now', ?) WHERE id = ?",
+        )
+        .bind(error)
+        .bind(kind)
+        .bind(format!("+{} seconds", retry_in.as_secs()))
+        .bind(i.identity())
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Try an upload again right away, even after a permanent failure
+    pub async fn make_due(i: impl Identity<i64>, conn: &SqlitePool) -> Result<(), AnyhowError> {
+        sqlx::query(
+            "UPDATE spooled_uploads SET failure_kind = 'retryable', next_attempt_at = CURRENT_TIMESTAMP WHERE id = ?",
+        )
+        .bind(i.identity())
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
+
+impl Identity<i64> for &SpooledUpload {
+    fn identity(&self) -> i64 {
+        self.id
+    }
+}
+
+pub struct SpooledUploadBuilder {
+    pub config_id: i64,
+    pub obj_name: Option<String>,
+    pub mime: Mime,
+    pub kind: ObjectKind,
+    pub metadata: ObjectMetadata,
+    pub error: String,
+    pub failure_kind: FailureKind,
+    pub retry_in: Duration,
+}
+
+#[async_trait]
+impl Create<SpooledUploadBuilder> for SpooledUpload {
+    async fn create(
+        input: SpooledUploadBuilder,
+        conn: &SqlitePool,
+    ) -> Result<SpooledUpload, AppError> {
+        sqlx::query_as::<_, SpooledUpload>(
+            "INSERT INTO spooled_uploads (config_id, obj_name, mime_type, kind, metadata, attempts, last_error, failure_kind, next_attempt_at) VALUES (?, ?, ?, ?, ?, 1, ?, ?, datetime('now', ?)) RETURNING *",
+        )
+        .bind(input.config_id)
+        .bind(&input.obj_name)
+        .bind(input.mime.to_string())
+        .bind(input.kind)
+        .bind(Json(&input.metadata))
+        .bind(&input.error)
+        .bind(input.failure_kind)
+        .bind(format!("+{} seconds", input.retry_in.as_secs()))
+        .fetch_one(conn)
+        .await
+        .map_err(AppError::anyhow)
+    }
+}
+
+#[async_trait]
+impl Read<i64> for SpooledUpload {
+    async fn read<U: Identity<i64> + Send>(
+        i: U,
+        conn: &SqlitePool,
+    ) -> Result<SpooledUpload, AnyhowError> {
+        let id = i.identity();
+        Ok(
+            sqlx::query_as::<_, SpooledUpload>("SELECT * FROM spooled_uploads WHERE id = ?")
+                .bind(id)
+                .fetch_one(conn)
+                .await?,
+        )
+    }
+}
+
+#[async_trait]
+impl List for SpooledUpload {
+    async fn list(conn: &SqlitePool) -> Result<Vec<SpooledUpload>, AnyhowError> {
+        Ok(sqlx::query_as::<_, SpooledUpload>(
+            "SELECT * FROM spooled_uploads ORDER BY id DESC",
+        )
+        .fetch_all(conn)
+        .await?)
+    }
+}
+
+#[async_trait]
+impl Delete<i64> for SpooledUpload {
+    async fn delete<U: Identity<i64> + Send>(
+        i: U,
+        conn: &SqlitePool,
+    ) -> Result<SqliteQueryResult, AnyhowError> {
+        let id = i.identity();
+        Ok(sqlx::query("DELETE FROM spooled_uploads WHERE id = ?")
+            .bind(id)
+            .execute(conn)
+            .await?)
+    }
+}
+
+/// Uploads of a config are deleted once they are `max_age_days` old
+#[derive(FromRow, Serialize, Debug)]
+pub struct RetentionRule {
+    id: i64,
+    pub config_id: i64,
+    /// only uploads of this class are covered, every upload when unset
+    pub mime_class: Option<MimeClass>,
+    pub max_age_days: i64,
+    /// set by the first sweep started by hand, until then the background
+    /// sweep holds back if the rule would delete many uploads at once
+    pub confirmed: bool,
+}
+
+impl RetentionRule {
+    pub async fn confirm(i: impl Identity<i64>, conn: &SqlitePool) -> Result<(), AnyhowError> {
+        sqlx::query("UPDATE retention_rules SET confirmed = TRUE WHERE id = ?")
+            .bind(i.identity())
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct RetentionRuleFields {
+    pub config_id: i64,
+    pub mime_class: Option<MimeClass>,
+    #[validate(range(min = 1, max = 36500, message = "Must be between 1 and 36500 days"))]
+    pub max_age_days: i64,
+}
+
+#[async_trait]
+impl Create<RetentionRuleFields> for RetentionRule {
+    /// Replaces the rule for the same config and mime class if there is one
+    async fn create(input: RetentionRuleFields, conn: &SqlitePool) -> Result<RetentionRule, AppError> {
+        input.validate()?;
+        sqlx::query("DELETE FROM retention_rules WHERE config_id = ? AND mime_class IS ?")
+            .bind(input.config_id)
+            .bind(input.mime_class)
+            .execute(conn)
+            .await
+            .map_err(AppError::anyhow)?;
+        sqlx::query_as::<_, RetentionRule>(
+            "INSERT INTO retention_rules (config_id, mime_class, max_age_days) VALUES (?, ?, ?) RETURNING *",
+        )
+        .bind(input.config_id)
+        .bind(input.mime_class)
+        .bind(input.max_age_days)
+        .fetch_one(conn)
+        .await
+        .map_err(AppError::anyhow)
+    }
+}
+
+#[async_trait]
+impl List for RetentionRule {
+    async fn list(conn: &SqlitePool) -> Result<Vec<RetentionRule>, AnyhowError> {
+        Ok(sqlx::query_as::<_, RetentionRule>(
+            "SELECT * FROM retention_rules ORDER BY config_id ASC, mime_class ASC",
+        )
+        .fetch_all(conn)
+        .await?)
+    }
+}
+
+#[async_trait]
+impl Delete<i64> for RetentionRule {
+    async fn delete<U: Identity<i64> + Send>(
+        i: U,
+        conn: &SqlitePool,
+    ) -> Result<SqliteQueryResult, AnyhowError> {
+        let id = i.identity();
+        Ok(sqlx::query("DELETE FROM retention_rules WHERE id = ?")
+            .bind(id)
+            .execute(conn)
+            .await?)
+    }
+}
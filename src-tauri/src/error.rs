@@ -10,6 +10,19 @@ impl AnyhowError {
     ) -> Self {
         Self(anyhow::Error::new(e))
     }
+
+    pub fn downcast_ref<E>(&self) -> Option<&E>
+    where
+        E: std::fmt::Display + std::fmt::Debug + Send + Sync + 'static,
+    {
+        self.0.downcast_ref::<E>()
+    }
+}
+
+impl std::fmt::Display for AnyhowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
 }
 
 // impl From<anyhow::Error> for AnyhowError {
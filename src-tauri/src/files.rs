@@ -0,0 +1,52 @@
+use std::path::{Path, PathBuf};
+
+use mime::Mime;
+use tokio::io::AsyncReadExt;
+
+use crate::error::AnyhowError;
+
+/// Enough of the start of a file to recognise every format `infer` knows
+const SNIFF_LEN: usize = 8 * 1024;
+
+/// A file on disk which is about to be uploaded
+#[derive(Debug, Clone)]
+pub struct LocalFile {
+    pub path: PathBuf,
+    pub size: u64,
+    pub mime: Mime,
+    /// the name the object is downloaded as
+    pub filename: Option<String>,
+}
+
+impl LocalFile {
+    pub async fn inspect(path: impl Into<PathBuf>) -> Result<Self, AnyhowError> {
+        let path = path.into();
+        let meta = tokio::fs::metadata(&path).await?;
+        if !meta.is_file() {
+            return Err(anyhow::anyhow!("{} is not a file", path.display()).into());
+        }
+        Ok(Self {
+            mime: sniff_mime(&path).await?,
+            size: meta.len(),
+            filename: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned()),
+            path,
+        })
+    }
+}
+
+/// The type of a file from its contents, falling back to its extension for
+/// text formats which have no magic bytes
+pub async fn sniff_mime(path: &Path) -> Result<Mime, AnyhowError> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    (&mut file)
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut head)
+        .await?;
+    let sniffed = infer::get(&head).and_then(|kind| kind.mime_type().parse::<Mime>().ok());
+    Ok(sniffed
+        .or_else(|| mime_guess::from_path(path).first())
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM))
+}
@@ -5,22 +5,26 @@
 
 pub mod db;
 mod error;
+mod files;
 mod rect;
 mod s3;
 mod screenshot;
+mod storage;
+mod template;
 mod window_config;
 
 use anyhow::Context;
-use db::crud::{Upload, Read, Delete, List, S3ConfigRaw, S3ConfigFields, Create, Update, SelectedConfig, UploadBuilder};
+use db::crud::{Upload, Read, Delete, List, S3ConfigRaw, S3ConfigFields, Create, Update, SelectedConfig, UploadBuilder, PendingMultipart, UploadSettings, SpooledUpload, RetentionRule, RetentionRuleFields, UploadReplica};
 use error::{AnyhowError, Validated};
 use mime::Mime;
 use screenshot::ScreenshotPlugin;
 use sqlx::SqlitePool;
-use std::{borrow::Cow, path::PathBuf};
+use std::{path::PathBuf, time::Duration};
 use tauri::{generate_handler, ipc::InvokeBody, tray::ClickType, Manager, RunEvent, State};
 use tauri_plugin_positioner::{Position, WindowExt};
-use s3::plugin::UploadManager;
-use uuid::Uuid;
+use s3::{browse::{self, BucketObject, ImportReport, ObjectPage}, diagnostics::ConfigReport, retention::{self, RetentionReport}, jobs::{JobId, UploadJob}, maintenance::OpenMultipartUpload, metadata::ObjectMetadata, plugin::UploadManager};
+use validator::Validate;
+use storage::{custom::CustomUploader, keys::ObjectKind, NewObject};
 
 
 
@@ -79,16 +83,45 @@ fn main() {
         .invoke_handler(generate_handler![
             list_configs,
             create_config,
+            test_config,
             get_config,
             update_config,
             delete_config,
             get_selected,
             set_selected,
+            get_replicas,
+            set_replicas,
+            list_upload_replicas,
             begin_upload,
             upload_url_part,
+            upload_file,
             list_uploads,
             get_rms,
             delete_upload,
+            list_interrupted_uploads,
+            list_bucket,
+            import_objects,
+            import_prefix,
+            set_upload_pinned,
+            list_retention_rules,
+            create_retention_rule,
+            delete_retention_rule,
+            sweep_expired_uploads,
+            resume_upload,
+            finalize_upload,
+            discard_upload,
+            list_upload_jobs,
+            cancel_upload_job,
+            retry_upload_job,
+            get_upload_settings,
+            list_spooled_uploads,
+            retry_spooled_upload,
+            discard_spooled_upload,
+            set_upload_settings,
+            list_open_multipart_uploads,
+            abort_stale_uploads,
+            refresh_share_link,
+            parse_custom_uploader,
         ])
         .build(tauri::generate_context!())
         .expect("error while building tauri application");
@@ -121,6 +154,18 @@ async fn create_config(
     S3ConfigRaw::create(config, &s).await.try_into()
 }
 
+#[tauri::command]
+async fn test_config(
+    manager: State<'_, UploadManager>,
+    config: S3ConfigFields,
+) -> Result<Validated<ConfigReport>, AnyhowError> {
+    if let Err(e) = config.validate() {
+        return Ok(Validated::ValidationError(e));
+    }
+    let test = manager.read().await.test_config(config);
+    Ok(Validated::Ok(test.await))
+}
+
 #[tauri::command]
 async fn update_config(
     s: State<'_, SqlitePool>,
@@ -144,6 +189,13 @@ async fn delete_config(s: State<'_, SqlitePool>, config_id: i64) -> Result<(), A
     Ok(())
 }
 
+/// Check a custom uploader definition, which may be a ShareX `.sxcu` file
+/// being imported
+#[tauri::command]
+async fn parse_custom_uploader(definition: String) -> Result<CustomUploader, AnyhowError> {
+    Ok(CustomUploader::parse(&definition)?)
+}
+
 #[tauri::command]
 async fn get_selected(s: State<'_, SqlitePool>) -> Result<Option<S3ConfigRaw>, AnyhowError> {
     SelectedConfig::get(&s).await
@@ -156,59 +208,118 @@ async fn set_selected(
     config_id: i64,
 ) -> Result<(), AnyhowError> {
     SelectedConfig::set(config_id, &s).await?;
-    let conf = SelectedConfig::get(&s)
+    apply_selected(&s, &manager).await
+}
+
+/// Load the selected config and its replicas into the manager
+async fn apply_selected(pool: &SqlitePool, manager: &UploadManager) -> Result<(), AnyhowError> {
+    let conf = SelectedConfig::get(pool)
         .await?
         .context("No config selected")?;
-    manager.write().await.set_config(conf.build()?)?;
+    let replicas = SelectedConfig::replicas(pool).await?;
+    manager.write().await.set_config(conf, replicas)?;
     Ok(())
 }
 
+#[tauri::command]
+async fn get_replicas(s: State<'_, SqlitePool>) -> Result<Vec<i64>, AnyhowError> {
+    SelectedConfig::replica_ids(&s).await
+}
+
+/// Choose the configs which get a copy of every upload on top of the
+/// selected one
+#[tauri::command]
+async fn set_replicas(
+    s: State<'_, SqlitePool>,
+    manager: State<'_, UploadManager>,
+    config_ids: Vec<i64>,
+) -> Result<(), AnyhowError> {
+    SelectedConfig::set_replicas(&config_ids, &s).await?;
+    apply_selected(&s, &manager).await
+}
+
+#[tauri::command]
+async fn list_upload_replicas(s: State<'_, SqlitePool>) -> Result<Vec<UploadReplica>, AnyhowError> {
+    UploadReplica::list(&s).await
+}
+
 
 #[tauri::command]
 async fn begin_upload(
     manager: State<'_, UploadManager>,
     window: tauri::Window,
-) -> Result<(), AnyhowError> {
-    manager
-        .write()
-        .await
-        .new_multipart_upload(format!("{}.mp4", Uuid::new_v4()))
+    metadata: Option<ObjectMetadata>,
+    mime_type: Option<String>,
+) -> Result<JobId, AnyhowError> {
+    // the recorder picks its container, parameters such as codecs don't
+    // belong in the content type
+    let mime: Mime = match mime_type {
+        Some(mime_type) => mime_type.parse::<Mime>()?.essence_str().parse()?,
+        None => "video/mp4".parse()?,
+    };
+    let manager = manager.read().await;
+    let kind = ObjectKind::Recording;
+    let obj_name = manager.new_object_key(kind, &mime).await?;
+    let job = manager
+        .new_multipart_upload(NewObject {
+            key: obj_name,
+            mime,
+            kind,
+            metadata: metadata.unwrap_or_default(),
+            source: None,
+        })
         .await?;
     let _ = window.hide();
-    Ok(())
+    Ok(job)
 }
 
 #[tauri::command]
 async fn upload_url_part<'a>(
     manager: State<'_, UploadManager>,
-    conn: State<'_, SqlitePool>,
     request: tauri::ipc::Request<'a>,
 ) -> Result<bool, AnyhowError> {
     let slice: &[u8] = match request.body() {
         InvokeBody::Raw(b) => Ok(b),
         _ => Err(anyhow::anyhow!("expected raw bytes")),
     }?;
+    let job: JobId = request
+        .headers()
+        .get("job")
+        .context("expected the job id in a job header")?
+        .to_str()?
+        .parse()?;
 
     match request.headers().get("final") {
         None => {
-            manager.write().await.upload_part(slice).await?;
+            manager.read().await.upload_part(job, slice).await?;
             Ok(false)
         }
         Some(_) => {
-            let url = manager
-                .write()
+            let o = manager
+                .read()
                 .await
-                .complete_upload(slice)
-                .await?
-                .upload_url;
-            let mime = "video/mp4".parse::<Mime>()?;
-            let o = Upload::create(UploadBuilder { url, mime }, &conn).await?;
+                .complete_upload(job, slice)
+                .await?;
             dbg!(&o);
             Ok(true)
         }
     }
 }
 
+/// Upload files from disk, each as its own job
+#[tauri::command]
+async fn upload_file(
+    manager: State<'_, UploadManager>,
+    paths: Vec<PathBuf>,
+) -> Result<Vec<JobId>, AnyhowError> {
+    let manager = manager.read().await;
+    let mut jobs = Vec::with_capacity(paths.len());
+    for path in paths {
+        jobs.push(manager.upload_file(path).await?.id);
+    }
+    Ok(jobs)
+}
+
 #[tauri::command]
 async fn list_uploads(pool: State<'_, SqlitePool>) -> Result<Vec<Upload>, AnyhowError> {
     Upload::list(&pool).await
@@ -232,15 +343,218 @@ async fn delete_upload(
     pool: State<'_, SqlitePool>,
     id: i64,
 ) -> Result<(), AnyhowError> {
-    let url = Upload::read(id, &pool).await?.url()?;
-    let mut path = Cow::from(url.path());
-    if path.starts_with('/') {
-        path.to_mut().remove(0);
-    }
-
-    manager.read().await.delete(&path).await?;
+    let upload = Upload::read(id, &pool).await?;
+    manager.read().await.delete(&upload).await?;
     Upload::delete(id, &pool).await?;
     Ok(())
 }
 
+#[tauri::command]
+async fn list_bucket(
+    manager: State<'_, UploadManager>,
+    config_id: i64,
+    prefix: String,
+    continuation_token: Option<String>,
+) -> Result<ObjectPage, AnyhowError> {
+    let backend = manager.read().await.backend_for(config_id).await?;
+    browse::list_objects(backend.as_ref(), &prefix, false, continuation_token.as_deref()).await
+}
+
+#[tauri::command]
+async fn import_objects<R: tauri::Runtime>(
+    app: tauri::AppHandle<R>,
+    manager: State<'_, UploadManager>,
+    pool: State<'_, SqlitePool>,
+    config_id: i64,
+    objects: Vec<BucketObject>,
+) -> Result<ImportReport, AnyhowError> {
+    let backend = manager.read().await.backend_for(config_id).await?;
+    let report = browse::import_objects(backend.as_ref(), &pool, objects).await?;
+    app.emit_all("reload-uploads", ())?;
+    Ok(report)
+}
+
+/// Import everything under a prefix, which can be the whole bucket
+#[tauri::command]
+async fn import_prefix<R: tauri::Runtime>(
+    app: tauri::AppHandle<R>,
+    manager: State<'_, UploadManager>,
+    pool: State<'_, SqlitePool>,
+    config_id: i64,
+    prefix: String,
+) -> Result<ImportReport, AnyhowError> {
+    let backend = manager.read().await.backend_for(config_id).await?;
+    let report = browse::import_prefix(backend.as_ref(), &pool, &prefix).await?;
+    app.emit_all("reload-uploads", ())?;
+    Ok(report)
+}
+
+#[tauri::command]
+async fn set_upload_pinned(
+    pool: State<'_, SqlitePool>,
+    id: i64,
+    pinned: bool,
+) -> Result<Upload, AnyhowError> {
+    Upload::set_pinned(id, pinned, &pool).await
+}
+
+#[tauri::command]
+async fn list_retention_rules(pool: State<'_, SqlitePool>) -> Result<Vec<RetentionRule>, AnyhowError> {
+    RetentionRule::list(&pool).await
+}
+
+#[tauri::command]
+async fn create_retention_rule(
+    pool: State<'_, SqlitePool>,
+    rule: RetentionRuleFields,
+) -> Result<Validated<RetentionRule>, AnyhowError> {
+    RetentionRule::create(rule, &pool).await.try_into()
+}
+
+#[tauri::command]
+async fn delete_retention_rule(pool: State<'_, SqlitePool>, id: i64) -> Result<(), AnyhowError> {
+    RetentionRule::delete(id, &pool).await?;
+    Ok(())
+}
+
+/// Delete expired uploads now, or only report what would be deleted
+#[tauri::command]
+async fn sweep_expired_uploads<R: tauri::Runtime>(
+    app: tauri::AppHandle<R>,
+    pool: State<'_, SqlitePool>,
+    dry_run: bool,
+) -> Result<RetentionReport, AnyhowError> {
+    let mode = if dry_run {
+        retention::Sweep::DryRun
+    } else {
+        retention::Sweep::Confirmed
+    };
+    retention::sweep(&app, &pool, mode).await
+}
+
+#[tauri::command]
+async fn list_interrupted_uploads(
+    manager: State<'_, UploadManager>,
+) -> Result<Vec<PendingMultipart>, AnyhowError> {
+    manager.read().await.pending_uploads().await
+}
+
+#[tauri::command]
+async fn resume_upload(
+    manager: State<'_, UploadManager>,
+    pool: State<'_, SqlitePool>,
+    id: i64,
+) -> Result<JobId, AnyhowError> {
+    let record = PendingMultipart::read(id, &pool).await?;
+    Ok(manager.read().await.resume_upload(record).await?.id)
+}
+
+#[tauri::command]
+async fn finalize_upload(
+    manager: State<'_, UploadManager>,
+    pool: State<'_, SqlitePool>,
+    id: i64,
+) -> Result<Upload, AnyhowError> {
+    let record = PendingMultipart::read(id, &pool).await?;
+    let mime = record.mime()?;
+    let completed = manager.read().await.finalize_upload(record).await?;
+    let builder = UploadBuilder::completed(completed, mime);
+    Ok(Upload::create(builder, &pool).await?)
+}
+
+#[tauri::command]
+async fn discard_upload(
+    manager: State<'_, UploadManager>,
+    pool: State<'_, SqlitePool>,
+    id: i64,
+) -> Result<(), AnyhowError> {
+    let record = PendingMultipart::read(id, &pool).await?;
+    manager.read().await.discard_upload(record).await
+}
+
+#[tauri::command]
+async fn list_upload_jobs(manager: State<'_, UploadManager>) -> Result<Vec<UploadJob>, AnyhowError> {
+    Ok(manager.read().await.list_jobs())
+}
+
+#[tauri::command]
+async fn cancel_upload_job(manager: State<'_, UploadManager>, id: JobId) -> Result<(), AnyhowError> {
+    manager.read().await.cancel_job(id).await
+}
+
+#[tauri::command]
+async fn retry_upload_job(manager: State<'_, UploadManager>, id: JobId) -> Result<(), AnyhowError> {
+    manager.read().await.retry_job(id)
+}
+
+#[tauri::command]
+async fn list_spooled_uploads(
+    manager: State<'_, UploadManager>,
+) -> Result<Vec<SpooledUpload>, AnyhowError> {
+    manager.read().await.spooled_uploads().await
+}
+
+#[tauri::command]
+async fn retry_spooled_upload(manager: State<'_, UploadManager>, id: i64) -> Result<(), AnyhowError> {
+    manager.read().await.retry_spooled_now(id).await
+}
+
+#[tauri::command]
+async fn discard_spooled_upload(manager: State<'_, UploadManager>, id: i64) -> Result<(), AnyhowError> {
+    manager.read().await.discard_spooled(id).await
+}
+
+#[tauri::command]
+async fn get_upload_settings(s: State<'_, SqlitePool>) -> Result<UploadSettings, AnyhowError> {
+    UploadSettings::get(&s).await
+}
 
+#[tauri::command]
+async fn set_upload_settings(
+    s: State<'_, SqlitePool>,
+    manager: State<'_, UploadManager>,
+    settings: UploadSettings,
+) -> Result<Validated<UploadSettings>, AnyhowError> {
+    let res = UploadSettings::set(settings, &s).await;
+    if let Ok(settings) = &res {
+        manager
+            .read()
+            .await
+            .set_job_limit(settings.max_concurrent_jobs as usize);
+    }
+    res.try_into()
+}
+
+#[tauri::command]
+async fn list_open_multipart_uploads(
+    manager: State<'_, UploadManager>,
+) -> Result<Vec<OpenMultipartUpload>, AnyhowError> {
+    manager.read().await.list_open_uploads().await
+}
+
+#[tauri::command]
+async fn abort_stale_uploads(
+    manager: State<'_, UploadManager>,
+    max_age_hours: u64,
+) -> Result<Vec<OpenMultipartUpload>, AnyhowError> {
+    manager
+        .read()
+        .await
+        .abort_stale_uploads(Duration::from_secs(max_age_hours * 60 * 60))
+        .await
+}
+
+#[tauri::command]
+async fn refresh_share_link(
+    manager: State<'_, UploadManager>,
+    pool: State<'_, SqlitePool>,
+    id: i64,
+) -> Result<Upload, AnyhowError> {
+    let upload = Upload::read(id, &pool).await?;
+    let url = manager
+        .read()
+        .await
+        .share_url(upload.config_id(), &upload.obj_key()?)
+        .await?;
+    Upload::set_url(id, &url, &pool).await
+}
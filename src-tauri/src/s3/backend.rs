@@ -0,0 +1,251 @@
+use std::{borrow::Cow, time::Duration};
+
+use bytes::Bytes;
+use rusty_s3::{
+    actions::{DeleteObject, HeadObject, PutObject},
+    S3Action,
+};
+use sqlx::SqlitePool;
+use tauri::{
+    async_runtime::Sender,
+    http::header::{CONTENT_LENGTH, CONTENT_TYPE},
+};
+use tauri_plugin_http::reqwest::{Client, Url};
+
+use super::{
+    integrity::verify_size,
+    metadata::metadata_headers,
+    progress::ProgressTracker,
+    retry::RetryPolicy,
+    uploader::{
+        with_headers, CompletedData, InProgressUpload, InProgressUploadBuilder, S3Config,
+        UploadEvent, Uploader,
+    },
+};
+use crate::{
+    db::crud::PendingMultipart,
+    error::AnyhowError,
+    storage::{NewObject, StorageBackend, UploadSession},
+};
+
+/// Delete a single object from the bucket of `config`
+pub async fn delete_object(
+    config: &S3Config,
+    client: &Client,
+    obj_name: &str,
+) -> Result<(), AnyhowError> {
+    let d = DeleteObject::new(config.bucket(), Some(config.credentials()), obj_name);
+    let signed = d.sign(Duration::from_secs(3600));
+    RetryPolicy::default()
+        .send(|| client.delete(signed.clone()))
+        .await?;
+    Ok(())
+}
+
+/// Whether an object is already stored under `key`
+pub async fn object_exists(
+    config: &S3Config,
+    client: &Client,
+    key: &str,
+) -> Result<bool, AnyhowError> {
+    let headers = config.read_headers();
+    let mut action = HeadObject::new(config.bucket(), Some(config.credentials()), key);
+    for (name, value) in &headers {
+        action.headers_mut().insert(name.as_str(), value.as_str());
+    }
+    let url = action.sign(Duration::from_secs(3600));
+    match RetryPolicy::default()
+        .send(|| with_headers(client.head(url.clone()), &headers))
+        .await
+    {
+        Ok(_) => Ok(true),
+        Err(e) if e.status == Some(404) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct S3Backend {
+    config: S3Config,
+    client: Client,
+    pool: SqlitePool,
+}
+
+impl S3Backend {
+    pub fn new(config: S3Config, client: Client, pool: SqlitePool) -> Self {
+        Self {
+            config,
+            client,
+            pool,
+        }
+    }
+
+    pub fn config(&self) -> &S3Config {
+        &self.config
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Continue a multipart upload which was interrupted, reporting its
+    /// progress on `tx` if given
+    pub async fn resume(
+        &self,
+        record: PendingMultipart,
+        tx: Option<Sender<UploadEvent>>,
+    ) -> Result<S3Session, AnyhowError> {
+        let mut upload = InProgressUpload::resume(record, self.pool.clone()).await?;
+        upload.track(tx);
+        Ok(S3Session {
+            upload,
+            backend: self.clone(),
+        })
+    }
+
+    /// Continue an interrupted upload by reading the rest of its source,
+    /// returns the session and the offset in the source it continues from
+    pub async fn resume_source(
+        &self,
+        record: PendingMultipart,
+        tx: Option<Sender<UploadEvent>>,
+    ) -> Result<(S3Session, u64), AnyhowError> {
+        let mut upload = InProgressUpload::resume(record, self.pool.clone()).await?;
+        let offset = upload.rewind().await?;
+        upload.track(tx);
+        Ok((
+            S3Session {
+                upload,
+                backend: self.clone(),
+            },
+            offset,
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for S3Backend {
+    fn config_id(&self) -> i64 {
+        self.config.id()
+    }
+
+    fn key_template(&self) -> Option<&str> {
+        self.config.key_template()
+    }
+
+    fn key_prefix(&self) -> Option<&str> {
+        self.config.key_prefix()
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, AnyhowError> {
+        object_exists(&self.config, &self.client, key).await
+    }
+
+    async fn put(
+        &self,
+        object: NewObject,
+        bytes: Bytes,
+        progress: ProgressTracker,
+    ) -> Result<CompletedData, AnyhowError> {
+        let conf = &self.config;
+        let checksum = conf.checksum();
+        let mut object_headers = conf.object_headers();
+        object_headers.extend(metadata_headers(conf, object.kind, &object.key, &object.metadata)?);
+        object_headers.extend(checksum.checksum_headers(checksum.checksum(&bytes).as_deref()));
+        let size = bytes.len() as u64;
+        let mime = object.mime.essence_str();
+        let mut up = PutObject::new(conf.bucket(), Some(conf.credentials()), &object.key);
+
+        let headers = up.headers_mut();
+        let content = Cow::from(CONTENT_TYPE.to_string());
+        headers.insert(content, mime);
+        for (name, value) in &object_headers {
+            headers.insert(name.as_str(), value.as_str());
+        }
+        let signed = up.sign(Duration::from_secs(3600));
+        progress.add_total(size);
+        let body = progress.body(bytes, None);
+        RetryPolicy::default()
+            .send(|| {
+                let request = self
+                    .client
+                    .put(signed.clone())
+                    .header(CONTENT_LENGTH, body.content_length())
+                    .body(body.attempt())
+                    .header(CONTENT_TYPE, mime);
+                with_headers(request, &object_headers)
+            })
+            .await?;
+        verify_size(conf, &self.client, &object.key, size).await?;
+        let upload_url = conf.public_url(&object.key)?;
+        Ok(CompletedData {
+            upload_url,
+            obj_key: object.key,
+            config_id: conf.id(),
+            deletion_url: None,
+            replicas: Vec::new(),
+        })
+    }
+
+    async fn begin_upload(
+        &self,
+        object: NewObject,
+        progress: ProgressTracker,
+    ) -> Result<Box<dyn UploadSession>, AnyhowError> {
+        let headers = metadata_headers(&self.config, object.kind, &object.key, &object.metadata)?;
+        let upload = InProgressUpload::new(
+            InProgressUploadBuilder {
+                obj_name: object.key,
+                mime: object.mime,
+                pool: self.pool.clone(),
+                progress,
+                headers,
+                source: object.source,
+            },
+            &self.config,
+            &self.client,
+        )
+        .await?;
+        Ok(Box::new(S3Session {
+            upload,
+            backend: self.clone(),
+        }))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), AnyhowError> {
+        delete_object(&self.config, &self.client, key).await
+    }
+
+    fn public_url(&self, key: &str) -> Result<Url, AnyhowError> {
+        self.config.public_url(key)
+    }
+
+    fn as_s3(&self) -> Option<&S3Backend> {
+        Some(self)
+    }
+}
+
+/// A multipart upload to S3
+#[derive(Debug)]
+pub struct S3Session {
+    upload: InProgressUpload,
+    backend: S3Backend,
+}
+
+#[async_trait::async_trait]
+impl UploadSession for S3Session {
+    async fn write(&mut self, slice: &[u8]) -> Result<(), AnyhowError> {
+        let S3Backend { config, client, .. } = &self.backend;
+        self.upload.upload_part(slice, config, client).await
+    }
+
+    async fn complete(&mut self, slice: &[u8]) -> Result<CompletedData, AnyhowError> {
+        let S3Backend { config, client, .. } = &self.backend;
+        self.upload.complete_upload(slice, config, client).await
+    }
+
+    async fn abort(&mut self) -> Result<(), AnyhowError> {
+        let S3Backend { config, client, .. } = &self.backend;
+        self.upload.abort(config, client).await
+    }
+}
@@ -0,0 +1,219 @@
+use std::time::Duration;
+
+use futures_util::{stream, StreamExt, TryStreamExt};
+use mime::Mime;
+use percent_encoding::percent_decode_str;
+use rusty_s3::{
+    actions::{HeadObject, ListObjectsV2},
+    S3Action,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use tauri::http::header::CONTENT_TYPE;
+
+use super::{backend::S3Backend, retry::RetryPolicy, uploader::with_headers};
+use crate::{
+    db::crud::{ImportedUpload, Upload},
+    error::AnyhowError,
+    storage::StorageBackend,
+};
+
+/// How many objects have their content type looked up at the same time
+const IMPORT_CONCURRENCY: usize = 8;
+
+/// An object found in a bucket listing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BucketObject {
+    pub key: String,
+    pub size: i64,
+    pub last_modified: String,
+}
+
+/// One page of a bucket listing, keys below `prefix` which contain another
+/// `/` are grouped into `prefixes` like directories
+#[derive(Debug, Clone, Serialize)]
+pub struct ObjectPage {
+    pub prefix: String,
+    pub prefixes: Vec<String>,
+    pub objects: Vec<BucketObject>,
+    /// pass this back to get the next page
+    pub continuation_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ImportReport {
+    pub imported: usize,
+    /// objects which were in the history already
+    pub skipped: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListBucketResult {
+    #[serde(default)]
+    is_truncated: bool,
+    next_continuation_token: Option<String>,
+    #[serde(default)]
+    contents: Vec<ListedObject>,
+    #[serde(default)]
+    common_prefixes: Vec<CommonPrefix>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListedObject {
+    key: String,
+    last_modified: String,
+    size: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct CommonPrefix {
+    prefix: String,
+}
+
+fn decode(key: &str) -> Result<String, AnyhowError> {
+    Ok(percent_decode_str(key).decode_utf8()?.into_owned())
+}
+
+/// Only S3 buckets can be listed
+fn s3(backend: &dyn StorageBackend) -> Result<&S3Backend, AnyhowError> {
+    backend
+        .as_s3()
+        .ok_or_else(|| anyhow::anyhow!("Only S3 buckets can be browsed").into())
+}
+
+/// List one page of the objects under `prefix`. With `recursive` unset keys
+/// are grouped by the next `/` like a directory listing.
+pub async fn list_objects(
+    backend: &dyn StorageBackend,
+    prefix: &str,
+    recursive: bool,
+    continuation_token: Option<&str>,
+) -> Result<ObjectPage, AnyhowError> {
+    let backend = s3(backend)?;
+    let config = backend.config();
+    let mut action = ListObjectsV2::new(config.bucket(), Some(config.credentials()));
+    let query = action.query_mut();
+    query.insert("encoding-type", "url");
+    if !prefix.is_empty() {
+        query.insert("prefix", prefix.to_owned());
+    }
+    if !recursive {
+        query.insert("delimiter", "/");
+    }
+    if let Some(token) = continuation_token {
+        query.insert("continuation-token", token.to_owned());
+    }
+    let url = action.sign(Duration::from_secs(3600));
+    let body = RetryPolicy::default()
+        .send_text(|| backend.client().get(url.clone()))
+        .await?;
+    let page: ListBucketResult = quick_xml::de::from_str(&body)?;
+
+    let mut objects = Vec::with_capacity(page.contents.len());
+    for object in page.contents {
+        let key = decode(&object.key)?;
+        // zero byte "folders" made by bucket consoles
+        if key.ends_with('/') {
+            continue;
+        }
+        objects.push(BucketObject {
+            key,
+            size: object.size,
+            last_modified: object.last_modified,
+        });
+    }
+    Ok(ObjectPage {
+        prefix: prefix.to_owned(),
+        prefixes: page
+            .common_prefixes
+            .iter()
+            .map(|p| decode(&p.prefix))
+            .collect::<Result<_, _>>()?,
+        objects,
+        continuation_token: page.next_continuation_token.filter(|_| page.is_truncated),
+    })
+}
+
+/// The content type the object was stored with, the listing doesn't include
+/// it. Falls back to a guess from the key when the object can't be read.
+async fn content_type(backend: &S3Backend, key: &str) -> Mime {
+    let config = backend.config();
+    let headers = config.read_headers();
+    let mut action = HeadObject::new(config.bucket(), Some(config.credentials()), key);
+    for (name, value) in &headers {
+        action.headers_mut().insert(name.as_str(), value.as_str());
+    }
+    let url = action.sign(Duration::from_secs(3600));
+    let stored = RetryPolicy::default()
+        .send(|| with_headers(backend.client().head(url.clone()), &headers))
+        .await
+        .ok()
+        .and_then(|res| {
+            res.headers()
+                .get(CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<Mime>().ok())
+        })
+        // generic types are what most tools send when they don't know
+        .filter(|mime| *mime != mime::APPLICATION_OCTET_STREAM);
+    stored
+        .or_else(|| mime_guess::from_path(key).first())
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM)
+}
+
+/// Add objects from the bucket to the history, objects which are in the
+/// history already are skipped
+pub async fn import_objects(
+    backend: &dyn StorageBackend,
+    pool: &SqlitePool,
+    objects: Vec<BucketObject>,
+) -> Result<ImportReport, AnyhowError> {
+    let s3 = s3(backend)?;
+    let imported = stream::iter(objects)
+        .map(|object| async move {
+            let mime = content_type(s3, &object.key).await;
+            Upload::import(
+                ImportedUpload {
+                    url: backend.public_url(&object.key)?,
+                    obj_key: object.key,
+                    config_id: backend.config_id(),
+                    mime,
+                    size: object.size,
+                    last_modified: object.last_modified,
+                },
+                pool,
+            )
+            .await
+        })
+        .buffer_unordered(IMPORT_CONCURRENCY)
+        .try_collect::<Vec<_>>()
+        .await?;
+    let count = imported.iter().filter(|u| u.is_some()).count();
+    Ok(ImportReport {
+        imported: count,
+        skipped: imported.len() - count,
+    })
+}
+
+/// Import every object under `prefix`, one page at a time
+pub async fn import_prefix(
+    backend: &dyn StorageBackend,
+    pool: &SqlitePool,
+    prefix: &str,
+) -> Result<ImportReport, AnyhowError> {
+    let mut report = ImportReport::default();
+    let mut token: Option<String> = None;
+    loop {
+        let page = list_objects(backend, prefix, true, token.as_deref()).await?;
+        let imported = import_objects(backend, pool, page.objects).await?;
+        report.imported += imported.imported;
+        report.skipped += imported.skipped;
+        match page.continuation_token {
+            Some(next) => token = Some(next),
+            None => return Ok(report),
+        }
+    }
+}
@@ -0,0 +1,376 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use rusty_s3::{
+    actions::{HeadBucket, ListObjectsV2},
+    S3Action,
+};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use tauri_plugin_http::reqwest::{Client, StatusCode};
+
+use super::{
+    backend::S3Backend,
+    metadata::ObjectMetadata,
+    progress::ProgressTracker,
+    retry::{RetryPolicy, S3Error},
+    uploader::{with_headers, CompletedData},
+};
+use crate::{
+    db::crud::{S3ConfigFields, S3ConfigRaw},
+    error::AnyhowError,
+    storage::{
+        keys::{unique_key, ObjectKind},
+        open_backend, BackendKind, NewObject, StorageBackend,
+    },
+};
+
+/// Every request of a test gives up after this long, a config which is
+/// that slow is as good as broken
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+/// What the probe object contains, read back through the public URL
+const PROBE_BODY: &str = "boom connection test, safe to delete";
+
+/// A test reports problems straight away instead of retrying them
+const NO_RETRY: RetryPolicy = RetryPolicy {
+    max_attempts: 1,
+    base_delay: Duration::ZERO,
+    max_delay: Duration::ZERO,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Passed,
+    /// the step worked but something will not behave as expected
+    Warning,
+    Failed,
+    /// an earlier step failed so this one could not run
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Step {
+    /// one of `config`, `endpoint`, `credentials`, `bucket`, `put`,
+    /// `public_read` or `delete`
+    pub name: &'static str,
+    pub status: StepStatus,
+    pub message: String,
+    /// the form fields which most likely need changing
+    pub fields: &'static [&'static str],
+}
+
+/// The result of trying out a config before it is saved
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConfigReport {
+    pub ok: bool,
+    pub steps: Vec<Step>,
+}
+
+impl ConfigReport {
+    fn push(
+        &mut self,
+        name: &'static str,
+        status: StepStatus,
+        message: impl Into<String>,
+        fields: &'static [&'static str],
+    ) -> bool {
+        self.steps.push(Step {
+            name,
+            status,
+            message: message.into(),
+            fields,
+        });
+        status != StepStatus::Failed
+    }
+
+    fn passed(&mut self, name: &'static str, message: impl Into<String>) -> bool {
+        self.push(name, StepStatus::Passed, message, &[])
+    }
+
+    fn skip(&mut self, names: &[&'static str]) {
+        for name in names {
+            self.push(name, StepStatus::Skipped, "an earlier step failed", &[]);
+        }
+    }
+
+    fn finish(mut self) -> Self {
+        self.ok = self.steps.iter().all(|s| s.status != StepStatus::Failed);
+        self
+    }
+}
+
+/// Check that a config works by talking to its storage and uploading,
+/// reading back and deleting a small probe object
+pub async fn test_config(
+    fields: S3ConfigFields,
+    client: &Client,
+    pool: &SqlitePool,
+) -> ConfigReport {
+    let mut report = ConfigReport::default();
+    let endpoint = fields.endpoint.clone();
+    let kind = fields.backend;
+    let backend = match open_backend(S3ConfigRaw::unsaved(fields), client, pool) {
+        Ok(backend) => backend,
+        Err(e) => {
+            report.push("config", StepStatus::Failed, e.to_string(), &[]);
+            return report.finish();
+        }
+    };
+    if let Some(s3) = backend.as_s3() {
+        if !check_bucket(s3, &endpoint, &mut report).await {
+            report.skip(&["put", "public_read", "delete"]);
+            return report.finish();
+        }
+    }
+    probe(backend.as_ref(), kind, client, &mut report).await;
+    report.finish()
+}
+
+/// The endpoint, credentials and bucket steps, which only S3 has. Returns
+/// whether the probe object can be uploaded.
+async fn check_bucket(backend: &S3Backend, endpoint: &str, report: &mut ConfigReport) -> bool {
+    let config = backend.config();
+    let client = backend.client();
+
+    // any response at all, even an error, means the endpoint is reachable
+    match client.get(endpoint).timeout(TIMEOUT).send().await {
+        Ok(_) => report.passed("endpoint", format!("{} responded", endpoint)),
+        Err(e) => {
+            report.push(
+                "endpoint",
+                StepStatus::Failed,
+                format!("Could not reach {}: {}", endpoint, e),
+                &["endpoint"],
+            );
+            report.skip(&["credentials", "bucket"]);
+            return false;
+        }
+    };
+
+    let mut list = ListObjectsV2::new(config.bucket(), Some(config.credentials()));
+    list.query_mut().insert("max-keys", "0");
+    let url = list.sign(TIMEOUT);
+    let res = NO_RETRY
+        .send(|| client.get(url.clone()).timeout(TIMEOUT))
+        .await;
+    let credentials_ok = match res {
+        Ok(_) => report.passed("credentials", "The keys were accepted"),
+        Err(e) => match e.code.as_deref() {
+            // the keys are fine, the bucket step explains what is wrong with it
+            Some("NoSuchBucket") => report.passed("credentials", "The keys were accepted"),
+            Some("InvalidAccessKeyId") => report.push(
+                "credentials",
+                StepStatus::Failed,
+                "The public key does not exist",
+                &["public_key"],
+            ),
+            Some("SignatureDoesNotMatch") => report.push(
+                "credentials",
+                StepStatus::Failed,
+                "The private key does not match the public key",
+                &["private_key"],
+            ),
+            Some("AuthorizationHeaderMalformed" | "AuthorizationQueryParametersError") => report
+                .push(
+                    "credentials",
+                    StepStatus::Failed,
+                    "The request was signed for the wrong region",
+                    &["region"],
+                ),
+            Some("AccessDenied") => report.push(
+                "credentials",
+                StepStatus::Warning,
+                "The keys were accepted but are not allowed to list the bucket",
+                &[],
+            ),
+            _ => report.push(
+                "credentials",
+                StepStatus::Failed,
+                e.message,
+                &["public_key", "private_key"],
+            ),
+        },
+    };
+    if !credentials_ok {
+        report.skip(&["bucket"]);
+        return false;
+    }
+
+    // HeadBucket responses have no body, the status and headers are all
+    // there is to go on
+    let url = HeadBucket::new(config.bucket(), Some(config.credentials())).sign(TIMEOUT);
+    let res = match client.head(url).timeout(TIMEOUT).send().await {
+        Ok(res) => res,
+        Err(e) => {
+            return report.push("bucket", StepStatus::Failed, e.to_string(), &["endpoint"]);
+        }
+    };
+    let region = res
+        .headers()
+        .get("x-amz-bucket-region")
+        .and_then(|v| v.to_str().ok())
+        .filter(|region| *region != config.bucket().region());
+    match (res.status(), region) {
+        (status, _) if status.is_success() => report.passed("bucket", "The bucket exists"),
+        (StatusCode::MOVED_PERMANENTLY | StatusCode::BAD_REQUEST, Some(region)) => report.push(
+            "bucket",
+            StepStatus::Failed,
+            format!("The bucket is in region {}", region),
+            &["region"],
+        ),
+        (StatusCode::NOT_FOUND, _) => report.push(
+            "bucket",
+            StepStatus::Failed,
+            "The bucket does not exist",
+            &["bucket_name"],
+        ),
+        (StatusCode::FORBIDDEN, _) => report.push(
+            "bucket",
+            StepStatus::Failed,
+            "The keys are not allowed to use this bucket",
+            &["bucket_name", "public_key", "private_key"],
+        ),
+        (status, _) => report.push(
+            "bucket",
+            StepStatus::Failed,
+            format!("Unexpected response {}", status),
+            &["endpoint", "bucket_name"],
+        ),
+    }
+}
+
+/// Upload, read back and delete a probe object, which works the same for
+/// every backend
+async fn probe(
+    backend: &dyn StorageBackend,
+    kind: BackendKind,
+    client: &Client,
+    report: &mut ConfigReport,
+) {
+    let completed = match put_probe(backend).await {
+        Ok(completed) => {
+            report.passed("put", format!("Uploaded {}", completed.obj_key));
+            completed
+        }
+        Err(e) => {
+            let (message, fields) = explain_put(&e);
+            report.push("put", StepStatus::Failed, message, fields);
+            report.skip(&["public_read", "delete"]);
+            return;
+        }
+    };
+
+    let read_headers = backend
+        .as_s3()
+        .map(|s3| s3.config().read_headers())
+        .unwrap_or_default();
+    let request = with_headers(client.get(completed.upload_url.clone()), &read_headers);
+    match request.timeout(TIMEOUT).send().await {
+        Ok(res) if res.status().is_success() => match res.text().await {
+            Ok(body) if body == PROBE_BODY => {
+                report.passed("public_read", format!("{} is readable", completed.upload_url))
+            }
+            _ => report.push(
+                "public_read",
+                StepStatus::Warning,
+                format!(
+                    "{} responded but served something other than the upload",
+                    completed.upload_url
+                ),
+                &["host_rewrite", "url_template"],
+            ),
+        },
+        Ok(res) if matches!(res.status().as_u16(), 401 | 403) => report.push(
+            "public_read",
+            StepStatus::Failed,
+            "Uploads are not publicly readable, the bucket needs a public read policy or ACLs which allow public-read",
+            &["access_mode", "disable_acl"],
+        ),
+        Ok(res) if res.status() == StatusCode::NOT_FOUND => report.push(
+            "public_read",
+            StepStatus::Failed,
+            format!("Nothing is served at {}", completed.upload_url),
+            &["host_rewrite", "url_template"],
+        ),
+        Ok(res) => report.push(
+            "public_read",
+            StepStatus::Failed,
+            format!("{} responded with {}", completed.upload_url, res.status()),
+            &["host_rewrite", "url_template"],
+        ),
+        Err(e) => report.push(
+            "public_read",
+            StepStatus::Failed,
+            format!("Could not reach {}: {}", completed.upload_url, e),
+            &["host_rewrite", "url_template"],
+        ),
+    };
+
+    if kind == BackendKind::Custom && completed.deletion_url.is_none() {
+        report.push(
+            "delete",
+            StepStatus::Warning,
+            format!(
+                "The uploader has no deletion url, deleting an upload only removes it from the history and {} has to be removed by hand",
+                completed.upload_url
+            ),
+            &[],
+        );
+        return;
+    }
+    let res = backend
+        .delete_upload(&completed.obj_key, completed.deletion_url.as_deref())
+        .await;
+    match res {
+        Ok(()) => report.passed("delete", "The probe object was deleted"),
+        Err(e) => report.push(
+            "delete",
+            StepStatus::Failed,
+            format!(
+                "Deleting uploads from the history will not work, {} has to be removed by hand: {}",
+                completed.obj_key, e
+            ),
+            &["public_key", "private_key"],
+        ),
+    };
+}
+
+async fn put_probe(backend: &dyn StorageBackend) -> Result<CompletedData, AnyhowError> {
+    let key = unique_key(backend, ObjectKind::File, &mime::TEXT_PLAIN, None).await?;
+    let object = NewObject {
+        key,
+        mime: mime::TEXT_PLAIN,
+        kind: ObjectKind::File,
+        metadata: ObjectMetadata::default(),
+        source: None,
+    };
+    backend
+        .put(
+            object,
+            Bytes::from_static(PROBE_BODY.as_bytes()),
+            ProgressTracker::default(),
+        )
+        .await
+}
+
+fn explain_put(e: &AnyhowError) -> (String, &'static [&'static str]) {
+    let code = e.downcast_ref::<S3Error>().and_then(|e| e.code.as_deref());
+    match code {
+        Some("AccessControlListNotSupported") => (
+            "The bucket has ACLs disabled, use the bucket policy access mode or never send ACL headers".to_owned(),
+            &["access_mode", "disable_acl"],
+        ),
+        Some("AccessDenied") => (
+            "The keys are not allowed to upload to this bucket".to_owned(),
+            &["public_key", "private_key"],
+        ),
+        Some("InvalidArgument" | "InvalidEncryptionAlgorithmError" | "KMS.NotFoundException") => (
+            e.to_string(),
+            &["encryption", "kms_key_id", "customer_key"],
+        ),
+        Some("InvalidStorageClass") => (e.to_string(), &["storage_class"]),
+        _ => (e.to_string(), &[]),
+    }
+}
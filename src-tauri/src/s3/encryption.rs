@@ -0,0 +1,136 @@
+use std::fmt;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+
+/// Storage classes which serve reads straight away, archive classes would
+/// break every share link until the object is restored
+pub const STORAGE_CLASSES: &[&str] = &[
+    "STANDARD",
+    "REDUCED_REDUNDANCY",
+    "STANDARD_IA",
+    "ONEZONE_IA",
+    "INTELLIGENT_TIERING",
+    "GLACIER_IR",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum EncryptionMode {
+    /// whatever default encryption the bucket has
+    #[default]
+    #[serde(rename = "none")]
+    #[sqlx(rename = "none")]
+    BucketDefault,
+    /// SSE-S3
+    Aes256,
+    /// SSE-KMS, with the AWS managed key unless a key id is given
+    AwsKms,
+    /// SSE-C, the key has to be sent with every request which reads or
+    /// writes the object so share links won't open these objects
+    CustomerKey,
+}
+
+#[derive(Clone, Default)]
+pub enum ServerSideEncryption {
+    #[default]
+    BucketDefault,
+    Aes256,
+    AwsKms { key_id: Option<String> },
+    CustomerKey { key: String, key_md5: String },
+}
+
+/// Leaves out the customer key, configs are printed while debugging
+impl fmt::Debug for ServerSideEncryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BucketDefault => f.write_str("BucketDefault"),
+            Self::Aes256 => f.write_str("Aes256"),
+            Self::AwsKms { key_id } => f.debug_struct("AwsKms").field("key_id", key_id).finish(),
+            Self::CustomerKey { .. } => f.write_str("CustomerKey { .. }"),
+        }
+    }
+}
+
+/// Decode a base64 SSE-C key, which has to be 256 bits
+fn decode_customer_key(key: &str) -> anyhow::Result<Vec<u8>> {
+    let decoded = STANDARD.decode(key)?;
+    if decoded.len() != 32 {
+        anyhow::bail!("SSE-C keys must be 256 bits");
+    }
+    Ok(decoded)
+}
+
+pub fn validate_customer_key(key: &str) -> anyhow::Result<()> {
+    decode_customer_key(key).map(|_| ())
+}
+
+impl ServerSideEncryption {
+    pub fn new(
+        mode: EncryptionMode,
+        kms_key_id: Option<String>,
+        customer_key: Option<String>,
+    ) -> anyhow::Result<Self> {
+        Ok(match mode {
+            EncryptionMode::BucketDefault => Self::BucketDefault,
+            EncryptionMode::Aes256 => Self::Aes256,
+            EncryptionMode::AwsKms => Self::AwsKms { key_id: kms_key_id },
+            EncryptionMode::CustomerKey => {
+                let key = customer_key
+                    .ok_or_else(|| anyhow::anyhow!("SSE-C needs a customer key"))?;
+                let key_md5 = STANDARD.encode(Md5::digest(decode_customer_key(&key)?));
+                Self::CustomerKey { key, key_md5 }
+            }
+        })
+    }
+
+    /// Headers for the requests which create an object, PutObject and
+    /// CreateMultipartUpload
+    pub fn create_headers(&self) -> Vec<(String, String)> {
+        match self {
+            Self::BucketDefault => Vec::new(),
+            Self::Aes256 => vec![(
+                "x-amz-server-side-encryption".to_owned(),
+                "AES256".to_owned(),
+            )],
+            Self::AwsKms { key_id } => {
+                let mut headers = vec![(
+                    "x-amz-server-side-encryption".to_owned(),
+                    "aws:kms".to_owned(),
+                )];
+                if let Some(key_id) = key_id {
+                    headers.push((
+                        "x-amz-server-side-encryption-aws-kms-key-id".to_owned(),
+                        key_id.clone(),
+                    ));
+                }
+                headers
+            }
+            Self::CustomerKey { .. } => self.customer_key_headers(),
+        }
+    }
+
+    /// Headers for every other request touching the object, only SSE-C
+    /// needs any: UploadPart and HeadObject have to repeat the key
+    pub fn customer_key_headers(&self) -> Vec<(String, String)> {
+        match self {
+            Self::CustomerKey { key, key_md5 } => vec![
+                (
+                    "x-amz-server-side-encryption-customer-algorithm".to_owned(),
+                    "AES256".to_owned(),
+                ),
+                (
+                    "x-amz-server-side-encryption-customer-key".to_owned(),
+                    key.clone(),
+                ),
+                (
+                    "x-amz-server-side-encryption-customer-key-MD5".to_owned(),
+                    key_md5.clone(),
+                ),
+            ],
+            _ => Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rusty_s3::{actions::HeadObject, S3Action};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::http::header::CONTENT_LENGTH;
+use tauri_plugin_http::reqwest::Client;
+
+use super::{
+    retry::RetryPolicy,
+    uploader::{with_headers, S3Config},
+};
+use crate::error::AnyhowError;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum ChecksumAlgorithm {
+    /// only the final size of the object is verified
+    #[default]
+    #[serde(rename = "none")]
+    #[sqlx(rename = "none")]
+    Disabled,
+    Sha256,
+    Crc32c,
+}
+
+impl ChecksumAlgorithm {
+    /// The value of `x-amz-checksum-algorithm`
+    fn name(&self) -> Option<&'static str> {
+        match self {
+            Self::Disabled => None,
+            Self::Sha256 => Some("SHA256"),
+            Self::Crc32c => Some("CRC32C"),
+        }
+    }
+
+    fn header(&self) -> Option<&'static str> {
+        match self {
+            Self::Disabled => None,
+            Self::Sha256 => Some("x-amz-checksum-sha256"),
+            Self::Crc32c => Some("x-amz-checksum-crc32c"),
+        }
+    }
+
+    /// The element holding the checksum in CompleteMultipartUpload bodies
+    /// and responses
+    pub fn xml_tag(&self) -> Option<&'static str> {
+        match self {
+            Self::Disabled => None,
+            Self::Sha256 => Some("ChecksumSHA256"),
+            Self::Crc32c => Some("ChecksumCRC32C"),
+        }
+    }
+
+    fn digest(&self, bytes: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Self::Disabled => None,
+            Self::Sha256 => Some(Sha256::digest(bytes).to_vec()),
+            Self::Crc32c => Some(crc32c::crc32c(bytes).to_be_bytes().to_vec()),
+        }
+    }
+
+    /// The base64 checksum S3 expects for `bytes`
+    pub fn checksum(&self, bytes: &[u8]) -> Option<String> {
+        self.digest(bytes).map(|d| STANDARD.encode(d))
+    }
+
+    /// Header announcing the algorithm when a multipart upload is created
+    pub fn create_headers(&self) -> Vec<(String, String)> {
+        self.name()
+            .map(|name| vec![("x-amz-checksum-algorithm".to_owned(), name.to_owned())])
+            .unwrap_or_default()
+    }
+
+    /// Header carrying the checksum of a part or a whole object
+    pub fn checksum_headers(&self, checksum: Option<&str>) -> Vec<(String, String)> {
+        match (self.header(), checksum) {
+            (Some(header), Some(checksum)) => vec![(header.to_owned(), checksum.to_owned())],
+            _ => Vec::new(),
+        }
+    }
+
+    /// The checksum S3 reports for a completed multipart upload, which is
+    /// the checksum of the concatenated part checksums suffixed by the
+    /// number of parts
+    pub fn composite<'a>(&self, parts: impl Iterator<Item = &'a str>) -> Option<String> {
+        let mut concatenated = Vec::new();
+        let mut count = 0;
+        for part in parts {
+            concatenated.extend(STANDARD.decode(part).ok()?);
+            count += 1;
+        }
+        self.checksum(&concatenated)
+            .map(|c| format!("{}-{}", c, count))
+    }
+}
+
+/// Check the object which landed in the bucket has the size we sent. A
+/// mismatching object is deleted so a broken recording is never shared.
+pub async fn verify_size(
+    config: &S3Config,
+    client: &Client,
+    obj_name: &str,
+    expected: u64,
+) -> Result<(), AnyhowError> {
+    let headers = config.read_headers();
+    let mut action = HeadObject::new(config.bucket(), Some(config.credentials()), obj_name);
+    for (name, value) in &headers {
+        action.headers_mut().insert(name.as_str(), value.as_str());
+    }
+    let url = action.sign(Duration::from_secs(3600));
+    let res = RetryPolicy::default()
+        .send(|| with_headers(client.head(url.clone()), &headers))
+        .await?;
+    let size = res
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if size == Some(expected) {
+        return Ok(());
+    }
+
+    let _ = super::backend::delete_object(config, client, obj_name).await;
+    Err(anyhow::anyhow!(
+        "{} has {} bytes in the bucket but {} were uploaded",
+        obj_name,
+        size.map_or("an unknown number of".to_owned(), |s| s.to_string()),
+        expected
+    )
+    .into())
+}
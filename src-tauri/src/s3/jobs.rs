@@ -0,0 +1,865 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use bytes::Bytes;
+use mime::Mime;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use tauri::{
+    async_runtime::{Receiver, Sender},
+    AppHandle, Manager, Runtime,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+};
+
+use super::backend::S3Backend;
+use super::metadata::ObjectMetadata;
+use super::progress::ProgressTracker;
+use super::retry::FailureKind;
+use super::spool::Spool;
+use super::uploader::{CompletedData, UploadEvent, MIB};
+use crate::{
+    db::crud::{Create, PendingMultipart, SpooledUpload, Upload, UploadBuilder},
+    error::AnyhowError,
+    storage::{
+        keys::{unique_key, ObjectKind},
+        NewObject, StorageBackend, UploadSession,
+    },
+};
+
+pub type JobId = u64;
+
+/// Files at least this large are streamed through a multipart upload
+/// instead of being read into memory for a single request
+pub const STREAM_THRESHOLD: u64 = 16 * MIB as u64;
+
+/// How much of a streamed file is read at a time, sessions buffer reads
+/// until they make up a part
+const READ_CHUNK: usize = MIB;
+
+/// Finished jobs which are kept around to be listed, failed and cancelled
+/// jobs are always kept so they can be retried unless they were spooled
+const MAX_DONE_JOBS: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Uploading,
+    Completing,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    fn is_finished(&self) -> bool {
+        matches!(self, Self::Done | Self::Failed | Self::Cancelled)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadJob {
+    pub id: JobId,
+    pub obj_name: String,
+    pub mime_type: String,
+    pub kind: ObjectKind,
+    pub config_id: i64,
+    pub state: JobState,
+    pub error: Option<String>,
+    pub url: Option<String>,
+    /// recordings are streamed by the webview as they happen, only jobs
+    /// which were handed their bytes up front can be sent again
+    pub retryable: bool,
+    /// the spooled upload this job sends, failed jobs are spooled and
+    /// retried from there
+    pub spooled: Option<i64>,
+}
+
+/// Something about a job changed, the plugin forwards these to the frontend
+#[derive(Debug)]
+pub enum JobEvent {
+    Changed(UploadJob),
+    Upload {
+        job: JobId,
+        obj_name: String,
+        event: UploadEvent,
+    },
+    /// The spool couldn't be updated after a job, nothing else would tell
+    /// the user
+    SpoolError(String),
+}
+
+/// An object which is handed all of its bytes up front
+#[derive(Debug, Clone)]
+pub struct QueuedObject {
+    /// picked from the key template when the job starts if not set, so an
+    /// upload can be queued while the backend is unreachable
+    pub key: Option<String>,
+    pub mime: Mime,
+    pub kind: ObjectKind,
+    pub metadata: ObjectMetadata,
+}
+
+/// Where the bytes of a queued job come from
+#[derive(Debug, Clone)]
+enum Source {
+    Bytes(Bytes),
+    /// a file which is too large to hold in memory, it stays on disk so
+    /// failed jobs are retried from it rather than spooled
+    File(PathBuf),
+    /// the rest of a file whose multipart upload was interrupted
+    Resume {
+        path: PathBuf,
+        record: PendingMultipart,
+    },
+}
+
+/// The bytes of a queued job and where they go
+#[derive(Debug, Clone)]
+struct Work {
+    backend: Arc<dyn StorageBackend>,
+    object: QueuedObject,
+    source: Source,
+    spooled: Option<i64>,
+}
+
+/// Set when a running job is cancelled. The job checks it between steps
+/// so it can clean up what it sent so far, rather than being dropped
+/// halfway through an upload.
+#[derive(Clone, Default)]
+struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn check(&self) -> Result<(), AnyhowError> {
+        if self.is_cancelled() {
+            return Err(anyhow::anyhow!("The upload was cancelled").into());
+        }
+        Ok(())
+    }
+}
+
+struct Entry {
+    job: UploadJob,
+    work: Option<Work>,
+    /// set while the job runs
+    cancel: Option<CancelToken>,
+    waiter: Option<oneshot::Sender<Result<Upload, AnyhowError>>>,
+}
+
+struct Jobs {
+    entries: BTreeMap<JobId, Entry>,
+    queued: VecDeque<JobId>,
+    running: usize,
+    limit: usize,
+    next_id: JobId,
+}
+
+/// A job which was added to the queue
+pub struct JobHandle {
+    pub id: JobId,
+    rx: oneshot::Receiver<Result<Upload, AnyhowError>>,
+}
+
+impl JobHandle {
+    /// Wait for the job to be uploaded and recorded in the history
+    pub async fn finished(self) -> Result<Upload, AnyhowError> {
+        self.rx
+            .await
+            .map_err(|_| anyhow::anyhow!("Upload job {} was dropped", self.id))?
+    }
+}
+
+/// Every upload runs as a job, uploads which are handed their bytes wait
+/// here until one of `limit` slots is free
+pub struct JobQueue {
+    pool: SqlitePool,
+    spool: Arc<Spool>,
+    events: UnboundedSender<JobEvent>,
+    jobs: Mutex<Jobs>,
+}
+
+impl JobQueue {
+    pub fn new(
+        pool: SqlitePool,
+        spool: Arc<Spool>,
+        limit: usize,
+    ) -> (Arc<Self>, UnboundedReceiver<JobEvent>) {
+        let (events, rx) = unbounded_channel();
+        let queue = Self {
+            pool,
+            spool,
+            events,
+            jobs: Mutex::new(Jobs {
+                entries: BTreeMap::new(),
+                queued: VecDeque::new(),
+                running: 0,
+                limit: limit.max(1),
+                next_id: 1,
+            }),
+        };
+        (Arc::new(queue), rx)
+    }
+
+    /// Change how many queued jobs run at once, running jobs are never
+    /// interrupted when the limit shrinks
+    pub fn set_limit(self: &Arc<Self>, limit: usize) {
+        self.jobs.lock().unwrap().limit = limit.max(1);
+        self.pump();
+    }
+
+    pub fn list(&self) -> Vec<UploadJob> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.entries.values().map(|e| e.job.clone()).collect()
+    }
+
+    pub fn spool(&self) -> &Arc<Spool> {
+        &self.spool
+    }
+
+    /// Whether a spooled upload is queued or being sent already
+    pub fn is_spooled_pending(&self, spooled: i64) -> bool {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.entries
+            .values()
+            .any(|e| e.job.spooled == Some(spooled) && !e.job.state.is_finished())
+    }
+
+    /// Whether a job which hasn't stopped yet is uploading to `key`, keys
+    /// are unique so an open multipart upload under it belongs to that job
+    pub fn is_key_live(&self, key: &str) -> bool {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.entries
+            .values()
+            .any(|e| e.job.obj_name == key && (!e.job.state.is_finished() || e.cancel.is_some()))
+    }
+
+    fn emit(&self, job: &UploadJob) {
+        let _ = self.events.send(JobEvent::Changed(job.clone()));
+    }
+
+    fn spool_error(&self, error: String) {
+        let _ = self.events.send(JobEvent::SpoolError(error));
+    }
+
+    fn insert(
+        &self,
+        jobs: &mut Jobs,
+        obj_name: String,
+        mime: &Mime,
+        kind: ObjectKind,
+        config_id: i64,
+        work: Option<Work>,
+    ) -> JobId {
+        let id = jobs.next_id;
+        jobs.next_id += 1;
+        let spooled = work.as_ref().and_then(|w| w.spooled);
+        let job = UploadJob {
+            id,
+            obj_name,
+            mime_type: mime.to_string(),
+            kind,
+            config_id,
+            state: if work.is_some() {
+                JobState::Queued
+            } else {
+                JobState::Uploading
+            },
+            error: None,
+            url: None,
+            retryable: work.is_some(),
+            spooled,
+        };
+        self.emit(&job);
+        jobs.entries.insert(
+            id,
+            Entry {
+                job,
+                work,
+                cancel: None,
+                waiter: None,
+            },
+        );
+        id
+    }
+
+    /// Queue an object to be uploaded with `backend`
+    pub fn enqueue(
+        self: &Arc<Self>,
+        backend: Arc<dyn StorageBackend>,
+        object: QueuedObject,
+        bytes: Bytes,
+    ) -> JobHandle {
+        self.push(Work {
+            backend,
+            object,
+            source: Source::Bytes(bytes),
+            spooled: None,
+        })
+    }
+
+    /// Queue a file to be streamed from disk with `backend`
+    pub fn enqueue_file(
+        self: &Arc<Self>,
+        backend: Arc<dyn StorageBackend>,
+        object: QueuedObject,
+        path: PathBuf,
+    ) -> JobHandle {
+        self.push(Work {
+            backend,
+            object,
+            source: Source::File(path),
+            spooled: None,
+        })
+    }
+
+    /// Queue the rest of an interrupted multipart upload, read again from
+    /// the file it was streamed from
+    pub fn enqueue_resume(
+        self: &Arc<Self>,
+        backend: S3Backend,
+        record: PendingMultipart,
+        path: PathBuf,
+    ) -> Result<JobHandle, AnyhowError> {
+        let object = QueuedObject {
+            key: Some(record.obj_name.clone()),
+            mime: record.mime()?,
+            kind: ObjectKind::File,
+            metadata: ObjectMetadata::default(),
+        };
+        Ok(self.push(Work {
+            backend: Arc::new(backend),
+            object,
+            source: Source::Resume { path, record },
+            spooled: None,
+        }))
+    }
+
+    /// Queue another attempt at a spooled upload
+    pub fn enqueue_spooled(
+        self: &Arc<Self>,
+        backend: Arc<dyn StorageBackend>,
+        record: &SpooledUpload,
+        bytes: Bytes,
+    ) -> Result<JobHandle, AnyhowError> {
+        let object = QueuedObject {
+            key: record.obj_name.clone(),
+            mime: record.mime()?,
+            kind: record.kind,
+            metadata: record.metadata.0.clone(),
+        };
+        Ok(self.push(Work {
+            backend,
+            object,
+            source: Source::Bytes(bytes),
+            spooled: Some(record.id()),
+        }))
+    }
+
+    fn push(self: &Arc<Self>, work: Work) -> JobHandle {
+        let (tx, rx) = oneshot::channel();
+        let id = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let config_id = work.backend.config_id();
+            let obj_name = work.object.key.clone().unwrap_or_default();
+            let (mime, kind) = (work.object.mime.clone(), work.object.kind);
+            let id = self.insert(&mut jobs, obj_name, &mime, kind, config_id, Some(work));
+            if let Some(entry) = jobs.entries.get_mut(&id) {
+                entry.waiter = Some(tx);
+            }
+            jobs.queued.push_back(id);
+            id
+        };
+        self.pump();
+        JobHandle { id, rx }
+    }
+
+    /// Register an upload which is streamed as it is recorded, it starts
+    /// uploading right away. Its events are reported through the returned
+    /// sender.
+    pub fn start_stream(
+        &self,
+        obj_name: &str,
+        mime: &Mime,
+        kind: ObjectKind,
+        config_id: i64,
+    ) -> (JobId, Sender<UploadEvent>) {
+        let id = {
+            let mut jobs = self.jobs.lock().unwrap();
+            self.insert(&mut jobs, obj_name.to_owned(), mime, kind, config_id, None)
+        };
+        (id, self.relay(id, obj_name.to_owned()))
+    }
+
+    /// Forward the `UploadEvent`s of one job to the event channel
+    fn relay(&self, job: JobId, obj_name: String) -> Sender<UploadEvent> {
+        let (tx, mut rx): (_, Receiver<UploadEvent>) = tauri::async_runtime::channel(10);
+        let events = self.events.clone();
+        tauri::async_runtime::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                let _ = events.send(JobEvent::Upload {
+                    job,
+                    obj_name: obj_name.clone(),
+                    event,
+                });
+            }
+        });
+        tx
+    }
+
+    /// Move a job to a new state, finished jobs stay finished
+    pub fn set_state(&self, id: JobId, state: JobState) {
+        self.update(id, |job| job.state = state);
+    }
+
+    /// A streamed job completed or failed
+    pub fn finish_stream(&self, id: JobId, res: Result<&CompletedData, &AnyhowError>) {
+        self.update(id, |job| match res {
+            Ok(completed) => {
+                job.state = JobState::Done;
+                job.url = Some(completed.upload_url.to_string());
+            }
+            Err(e) => {
+                job.state = JobState::Failed;
+                job.error = Some(e.to_string());
+            }
+        });
+        self.prune();
+    }
+
+    fn update(&self, id: JobId, f: impl FnOnce(&mut UploadJob)) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(entry) = jobs.entries.get_mut(&id) {
+            if entry.job.state.is_finished() {
+                return;
+            }
+            f(&mut entry.job);
+            self.emit(&entry.job);
+        }
+    }
+
+    /// Start queued jobs until the limit is reached
+    fn pump(self: &Arc<Self>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        while jobs.running < jobs.limit {
+            let Some(id) = jobs.queued.pop_front() else {
+                break;
+            };
+            let Some(entry) = jobs.entries.get_mut(&id) else {
+                continue;
+            };
+            let Some(work) = entry.work.clone() else {
+                continue;
+            };
+            entry.job.state = JobState::Uploading;
+            self.emit(&entry.job);
+            let queue = self.clone();
+            let cancel = CancelToken::default();
+            // the task can't finish before its token is stored, it needs
+            // the lock which is held here
+            entry.cancel = Some(cancel.clone());
+            tauri::async_runtime::spawn(async move { queue.run(id, work, cancel).await });
+            jobs.running += 1;
+        }
+    }
+
+    async fn run(self: Arc<Self>, id: JobId, work: Work, cancel: CancelToken) {
+        let res = self.send(id, &work, &cancel).await;
+        let spooled = match (&res, work.spooled) {
+            // nothing failed, the job was stopped on purpose
+            (Err(_), _) if cancel.is_cancelled() => None,
+            (Ok(_), Some(spooled)) => {
+                if let Err(e) = self.spool.remove(spooled).await {
+                    self.spool_error(format!(
+                        "A pending upload went through but is still listed, discard it: {}",
+                        e
+                    ));
+                }
+                None
+            }
+            (Err(e), Some(spooled)) => {
+                if let Err(e) = self.spool.failed(spooled, e).await {
+                    self.spool_error(format!("Failed to record another failed attempt: {}", e));
+                }
+                None
+            }
+            // keep the bytes around so the upload isn't lost
+            (Err(e), None) => match &work.source {
+                Source::Bytes(bytes) => match self
+                    .spool
+                    .store(work.backend.config_id(), &work.object, bytes, e)
+                    .await
+                {
+                    Ok(record) => Some(record.id()),
+                    Err(spool_error) => {
+                        self.spool_error(format!(
+                            "The failed upload couldn't be kept for a retry: {}",
+                            spool_error
+                        ));
+                        None
+                    }
+                },
+                Source::File(_) => None,
+            },
+            (Ok(_), None) => None,
+        };
+        self.finish(id, res, spooled);
+        self.pump();
+    }
+
+    /// Upload the bytes of a job and record it in the history
+    async fn send(
+        &self,
+        id: JobId,
+        work: &Work,
+        cancel: &CancelToken,
+    ) -> Result<Upload, AnyhowError> {
+        cancel.check()?;
+        let object = &work.object;
+        let key = match &object.key {
+            Some(key) => key.clone(),
+            None => {
+                let key = unique_key(
+                    work.backend.as_ref(),
+                    object.kind,
+                    &object.mime,
+                    object.metadata.filename.as_deref(),
+                )
+                .await?;
+                self.update(id, |job| job.obj_name = key.clone());
+                key
+            }
+        };
+        let tx = self.relay(id, key.clone());
+        let _ = tx.send(UploadEvent::Started).await;
+        let progress = ProgressTracker::new(Some(tx.clone()));
+        let new_object = NewObject {
+            key,
+            mime: object.mime.clone(),
+            kind: object.kind,
+            metadata: object.metadata.clone(),
+            source: match &work.source {
+                Source::File(path) => Some(path.clone()),
+                _ => None,
+            },
+        };
+        let res = match &work.source {
+            Source::Bytes(bytes) => work.backend.put(new_object, bytes.clone(), progress).await,
+            Source::File(path) => {
+                stream_file(work.backend.as_ref(), new_object, path, progress, cancel).await
+            }
+            Source::Resume { path, record } => {
+                resume_file(
+                    work.backend.as_ref(),
+                    record.clone(),
+                    path,
+                    tx.clone(),
+                    cancel,
+                )
+                .await
+            }
+        };
+        let res = match res {
+            Ok(completed) if !self.begin_completing(id) => {
+                // cancelled while the bytes were sent, the object would
+                // never make it into the history
+                let _ = work
+                    .backend
+                    .delete_upload(&completed.obj_key, completed.deletion_url.as_deref())
+                    .await;
+                cancel.check().map(|_| completed)
+            }
+            res => res,
+        };
+        let event = match &res {
+            Ok(_) => UploadEvent::Done,
+            Err(_) if cancel.is_cancelled() => UploadEvent::Aborted,
+            Err(e) => UploadEvent::Failed(FailureKind::of(e)),
+        };
+        let _ = tx.send(event).await;
+        let completed = res?;
+        self.record(completed, object.mime.clone()).await
+    }
+
+    /// Move a running job on to recording its upload unless it was
+    /// cancelled, from here on it can't be cancelled anymore
+    fn begin_completing(&self, id: JobId) -> bool {
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(entry) = jobs.entries.get_mut(&id) else {
+            return false;
+        };
+        if entry.job.state.is_finished() {
+            return false;
+        }
+        entry.job.state = JobState::Completing;
+        self.emit(&entry.job);
+        true
+    }
+
+    async fn record(&self, completed: CompletedData, mime: Mime) -> Result<Upload, AnyhowError> {
+        let builder = UploadBuilder::completed(completed, mime);
+        Ok(Upload::create(builder, &self.pool).await?)
+    }
+
+    /// Record how a job ended, a job which failed and was `spooled` is
+    /// retried from the spool instead of from memory
+    fn finish(&self, id: JobId, res: Result<Upload, AnyhowError>, spooled: Option<i64>) {
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            jobs.running -= 1;
+            let Some(entry) = jobs.entries.get_mut(&id) else {
+                return;
+            };
+            entry.cancel = None;
+            // spooled bytes are read again from the spool for the next
+            // attempt, a copy is only needed while the job runs
+            if entry.job.spooled.is_some() {
+                entry.work = None;
+            }
+            // a cancelled job was marked and answered when it was cancelled
+            if entry.job.state.is_finished() {
+                return;
+            }
+            match &res {
+                Ok(upload) => {
+                    entry.job.state = JobState::Done;
+                    entry.job.url = upload.url().ok().map(|url| url.to_string());
+                    entry.work = None;
+                }
+                Err(e) => {
+                    entry.job.state = JobState::Failed;
+                    entry.job.error = Some(e.to_string());
+                }
+            }
+            if let Some(spooled) = spooled {
+                entry.job.spooled = Some(spooled);
+                entry.job.retryable = false;
+                entry.work = None;
+            }
+            self.emit(&entry.job);
+            if let Some(waiter) = entry.waiter.take() {
+                let _ = waiter.send(res);
+            }
+        }
+        self.prune();
+    }
+
+    /// Forget the oldest jobs which finished successfully or were spooled,
+    /// spooled uploads are listed and retried from the spool
+    fn prune(&self) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let done: Vec<JobId> = jobs
+            .entries
+            .values()
+            .filter(|e| {
+                e.job.state == JobState::Done
+                    || (e.job.spooled.is_some() && e.job.state.is_finished())
+            })
+            .map(|e| e.job.id)
+            .collect();
+        for id in done.iter().take(done.len().saturating_sub(MAX_DONE_JOBS)) {
+            jobs.entries.remove(id);
+        }
+    }
+
+    /// Cancel a queued or running job, streamed jobs are aborted by their
+    /// owner which then marks them cancelled with `set_state`. A running job
+    /// stops at its next step and removes what it sent, it keeps its slot
+    /// until then.
+    pub fn cancel(self: &Arc<Self>, id: JobId) -> Result<(), AnyhowError> {
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            let entry = jobs
+                .entries
+                .get_mut(&id)
+                .ok_or_else(|| anyhow::anyhow!("No upload job {}", id))?;
+            let state = entry.job.state;
+            if state.is_finished() {
+                return Err(anyhow::anyhow!("Upload job {} already finished", id).into());
+            }
+            if entry.work.is_none() || state == JobState::Completing {
+                return Err(
+                    anyhow::anyhow!("{} is already being completed", entry.job.obj_name).into(),
+                );
+            }
+            if let Some(cancel) = &entry.cancel {
+                cancel.cancel();
+            }
+            entry.job.state = JobState::Cancelled;
+            self.emit(&entry.job);
+            if let Some(waiter) = entry.waiter.take() {
+                let _ = waiter.send(Err(
+                    anyhow::anyhow!("Upload job {} was cancelled", id).into()
+                ));
+            }
+            if state == JobState::Queued {
+                if entry.job.spooled.is_some() {
+                    entry.work = None;
+                }
+                jobs.queued.retain(|queued| *queued != id);
+            }
+        }
+        self.pump();
+        Ok(())
+    }
+
+    /// Queue a failed or cancelled job again
+    pub fn retry(self: &Arc<Self>, id: JobId) -> Result<(), AnyhowError> {
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            let entry = jobs
+                .entries
+                .get_mut(&id)
+                .ok_or_else(|| anyhow::anyhow!("No upload job {}", id))?;
+            if !matches!(entry.job.state, JobState::Failed | JobState::Cancelled) {
+                return Err(anyhow::anyhow!("Only failed or cancelled jobs can be retried").into());
+            }
+            if entry.cancel.is_some() {
+                return Err(anyhow::anyhow!("Upload job {} is still being cancelled", id).into());
+            }
+            if entry.job.spooled.is_some() {
+                return Err(anyhow::anyhow!(
+                    "Upload job {} was spooled, retry it from the pending uploads",
+                    id
+                )
+                .into());
+            }
+            if entry.work.is_none() {
+                return Err(anyhow::anyhow!(
+                    "{} was streamed while recording and can't be sent again",
+                    entry.job.obj_name
+                )
+                .into());
+            }
+            entry.job.state = JobState::Queued;
+            entry.job.error = None;
+            self.emit(&entry.job);
+            jobs.queued.push_back(id);
+        }
+        self.pump();
+        Ok(())
+    }
+}
+
+/// Send a file through an upload session a chunk at a time, so it is never
+/// held in memory as a whole
+async fn stream_file(
+    backend: &dyn StorageBackend,
+    object: NewObject,
+    path: &Path,
+    progress: ProgressTracker,
+    cancel: &CancelToken,
+) -> Result<CompletedData, AnyhowError> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut session = backend.begin_upload(object, progress).await?;
+    let res = copy_file(&mut file, session.as_mut(), cancel).await;
+    if res.is_err() {
+        // don't leave the parts sent so far behind
+        let _ = session.abort().await;
+    }
+    res
+}
+
+/// Send the rest of a file whose multipart upload was interrupted. It is
+/// only aborted when cancelled, after any other failure the parts stay
+/// stored so it can be resumed again.
+async fn resume_file(
+    backend: &dyn StorageBackend,
+    record: PendingMultipart,
+    path: &Path,
+    tx: Sender<UploadEvent>,
+    cancel: &CancelToken,
+) -> Result<CompletedData, AnyhowError> {
+    let backend = backend
+        .as_s3()
+        .ok_or_else(|| anyhow::anyhow!("Only uploads to S3 can be resumed"))?;
+    let mut file = tokio::fs::File::open(path).await?;
+    let (mut session, offset) = backend.resume_source(record, Some(tx)).await?;
+    if file.metadata().await?.len() < offset {
+        return Err(anyhow::anyhow!(
+            "{} is shorter than the part which was uploaded already",
+            path.display()
+        )
+        .into());
+    }
+    file.seek(SeekFrom::Start(offset)).await?;
+    let res = copy_file(&mut file, &mut session, cancel).await;
+    if res.is_err() && cancel.is_cancelled() {
+        let _ = session.abort().await;
+    }
+    res
+}
+
+async fn copy_file(
+    file: &mut tokio::fs::File,
+    session: &mut dyn UploadSession,
+    cancel: &CancelToken,
+) -> Result<CompletedData, AnyhowError> {
+    let mut buf = vec![0; READ_CHUNK];
+    loop {
+        cancel.check()?;
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            return session.complete(&[]).await;
+        }
+        session.write(&buf[..n]).await?;
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct UploadEventPayload<'a> {
+    job_id: JobId,
+    obj_name: &'a str,
+    #[serde(flatten)]
+    event: &'a UploadEvent,
+}
+
+/// Forward job changes to the frontend as `upload-job`, the events of each
+/// upload as `upload-event` and spool failures as `spool-error`
+pub fn forward_events<R: Runtime>(app: AppHandle<R>, mut rx: UnboundedReceiver<JobEvent>) {
+    tauri::async_runtime::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            let _ = match event {
+                JobEvent::Changed(job) => {
+                    if job.state == JobState::Done {
+                        let _ = app.emit_all("reload-uploads", ());
+                    }
+                    app.emit_all("upload-job", job)
+                }
+                JobEvent::Upload {
+                    job,
+                    obj_name,
+                    event,
+                } => app.emit_all(
+                    "upload-event",
+                    UploadEventPayload {
+                        job_id: job,
+                        obj_name: &obj_name,
+                        event: &event,
+                    },
+                ),
+                JobEvent::SpoolError(error) => app.emit_all("spool-error", error),
+            };
+        }
+    });
+}
@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+
+use percent_encoding::{utf8_percent_encode, CONTROLS, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+
+use super::uploader::S3Config;
+use crate::{error::AnyhowError, storage::keys::ObjectKind, template};
+
+pub const METADATA_PLACEHOLDERS: &[&str] = &["hostname", "kind", "version", "filename"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum ContentDisposition {
+    /// no header, browsers decide from the content type
+    #[default]
+    None,
+    /// shown in the browser, saved under the original filename
+    Inline,
+    /// always downloaded under the original filename
+    Attachment,
+}
+
+/// Overrides for a single upload, anything left unset comes from the config
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ObjectMetadata {
+    pub cache_control: Option<String>,
+    pub content_disposition: Option<ContentDisposition>,
+    /// the name the object is downloaded as, the last segment of the object
+    /// key if not set
+    pub filename: Option<String>,
+    /// merged into the metadata of the config
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// Metadata keys end up in header names, keep them to what every S3
+/// compatible server accepts
+pub fn validate_metadata_key(key: &str) -> anyhow::Result<()> {
+    if key.is_empty()
+        || !key
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+    {
+        anyhow::bail!("Invalid metadata key {:?}, use lowercase letters, digits, - and _", key);
+    }
+    Ok(())
+}
+
+/// Header values have to be ascii, anything else is percent encoded
+fn header_value(value: &str) -> String {
+    utf8_percent_encode(value, CONTROLS).to_string()
+}
+
+fn content_disposition(mode: ContentDisposition, filename: &str) -> Option<String> {
+    let disposition = match mode {
+        ContentDisposition::None => return None,
+        ContentDisposition::Inline => "inline",
+        ContentDisposition::Attachment => "attachment",
+    };
+    // the quoted name is a plain ascii fallback for clients which don't
+    // understand the RFC 5987 encoded `filename*`
+    let fallback: String = filename
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c.is_ascii() && !c.is_ascii_control() => c,
+            _ => '_',
+        })
+        .collect();
+    Some(format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        disposition,
+        fallback,
+        utf8_percent_encode(filename, NON_ALPHANUMERIC)
+    ))
+}
+
+/// Metadata of a new object, after the overrides of an upload were applied
+/// to the defaults of its config
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedMetadata {
+    pub cache_control: Option<String>,
+    pub content_disposition: Option<String>,
+    /// user metadata with placeholders rendered, values are header safe
+    pub metadata: Vec<(String, String)>,
+}
+
+/// Apply the `overrides` of an upload to the metadata defaults of a config
+pub fn resolve_metadata(
+    cache_control: Option<&str>,
+    disposition: ContentDisposition,
+    metadata: &BTreeMap<String, String>,
+    kind: ObjectKind,
+    obj_name: &str,
+    overrides: &ObjectMetadata,
+) -> Result<ResolvedMetadata, AnyhowError> {
+    let filename = overrides
+        .filename
+        .as_deref()
+        .unwrap_or_else(|| obj_name.rsplit('/').next().unwrap_or(obj_name));
+
+    let cache_control = overrides
+        .cache_control
+        .as_deref()
+        .or(cache_control)
+        .map(header_value);
+
+    let disposition = overrides.content_disposition.unwrap_or(disposition);
+    let content_disposition = content_disposition(disposition, filename);
+
+    let mut merged = metadata.clone();
+    merged.extend(overrides.metadata.clone());
+    let mut metadata = Vec::with_capacity(merged.len());
+    for (key, value) in merged {
+        validate_metadata_key(&key)?;
+        let value = template::render(&value, |name| match name {
+            "hostname" => Some(gethostname::gethostname().to_string_lossy().into_owned()),
+            "kind" => Some(kind.as_str().to_owned()),
+            "version" => Some(env!("CARGO_PKG_VERSION").to_owned()),
+            "filename" => Some(filename.to_owned()),
+            _ => None,
+        })?;
+        metadata.push((key, header_value(&value)));
+    }
+
+    Ok(ResolvedMetadata {
+        cache_control,
+        content_disposition,
+        metadata,
+    })
+}
+
+/// `Cache-Control`, `Content-Disposition` and `x-amz-meta-*` headers for a
+/// new object, sent with PutObject or CreateMultipartUpload
+pub fn metadata_headers(
+    config: &S3Config,
+    kind: ObjectKind,
+    obj_name: &str,
+    overrides: &ObjectMetadata,
+) -> Result<Vec<(String, String)>, AnyhowError> {
+    let resolved = resolve_metadata(
+        config.cache_control(),
+        config.content_disposition(),
+        config.metadata(),
+        kind,
+        obj_name,
+        overrides,
+    )?;
+    let mut headers = Vec::new();
+    if let Some(cache_control) = resolved.cache_control {
+        headers.push(("cache-control".to_owned(), cache_control));
+    }
+    if let Some(value) = resolved.content_disposition {
+        headers.push(("content-disposition".to_owned(), value));
+    }
+    for (key, value) in resolved.metadata {
+        headers.push((format!("x-amz-meta-{}", key), value));
+    }
+    Ok(headers)
+}
@@ -1,3 +1,14 @@
 pub mod uploader;
 pub mod plugin;
-
+pub mod retry;
+pub mod maintenance;
+pub mod progress;
+pub mod jobs;
+pub mod spool;
+pub mod diagnostics;
+pub mod browse;
+pub mod retention;
+pub mod integrity;
+pub mod encryption;
+pub mod metadata;
+pub mod backend;
@@ -1,166 +1,417 @@
-
-use anyhow::Context;
-use tauri::{async_runtime::RwLock, Manager};
-use super::uploader::{
-    CompletedData, InProgressUploadBuilder, UploadEvent, Uploader,
-InProgressUploadNotifierBuilder, InProgressUploadNotifier, S3Config
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::PathBuf,
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
 };
 
+use anyhow::Context;
+use bytes::Bytes;
 use mime::Mime;
-use rusty_s3::{
-    actions::{DeleteObject, PutObject},
-    S3Action,
-};
 use sqlx::SqlitePool;
-use std::{borrow::Cow, time::Duration};
-use tauri::{async_runtime::Receiver, http::header::CONTENT_TYPE, Runtime, plugin::TauriPlugin};
-use tauri_plugin_http::reqwest::{Body, Client};
-
-use crate::{error::AnyhowError, db::crud::SelectedConfig};
-use tauri::plugin::Builder as PluginBuilder;
+use tauri::{
+    async_runtime::{Mutex, RwLock},
+    plugin::{Builder as PluginBuilder, TauriPlugin},
+    Manager, Runtime,
+};
+use tauri_plugin_http::reqwest::{Client, Url};
+use time::OffsetDateTime;
+
+use super::backend::S3Backend;
+use super::maintenance::{abort_multipart, list_multipart_uploads, OpenMultipartUpload};
+use super::jobs::{
+    forward_events, JobHandle, JobId, JobQueue, JobState, QueuedObject, UploadJob,
+    STREAM_THRESHOLD,
+};
+use super::diagnostics::{self, ConfigReport};
+use super::metadata::ObjectMetadata;
+use super::retention::spawn_sweeper;
+use super::spool::{spawn_worker, Spool};
+use super::uploader::CompletedData;
+use crate::{
+    db::crud::{
+        Create, Delete, List, PendingMultipart, Read, S3ConfigFields, S3ConfigRaw, SelectedConfig,
+        SpooledUpload, Upload, UploadBuilder, UploadSettings,
+    },
+    error::AnyhowError,
+    files::LocalFile,
+    storage::{
+        keys::{unique_key, ObjectKind},
+        open_backend, replicated::ReplicatedBackend, NewObject, StorageBackend, UploadNotifier,
+        UploadSession,
+    },
+};
 
-enum ManagerState {
-    InProgress(InProgressUploadNotifier, S3Config),
-    Idle(S3Config),
-    NotInitialized,
+/// A recording which is being streamed, parts of different recordings can
+/// be sent at the same time
+struct Stream {
+    upload: Mutex<UploadNotifier>,
+    mime: Mime,
 }
 
 pub struct UploadClient {
     client: Client,
-    state: ManagerState,
+    pool: SqlitePool,
+    backend: Option<Arc<dyn StorageBackend>>,
+    jobs: Arc<JobQueue>,
+    streams: StdMutex<HashMap<JobId, Arc<Stream>>>,
 }
 
-impl Default for UploadClient {
-    fn default() -> Self {
+impl UploadClient {
+    pub fn new(pool: SqlitePool, jobs: Arc<JobQueue>) -> Self {
         Self {
             client: Client::default(),
-            state: ManagerState::NotInitialized,
+            pool,
+            backend: None,
+            jobs,
+            streams: StdMutex::new(HashMap::new()),
         }
     }
-}
 
-impl UploadClient {
-    pub async fn complete_upload(&mut self, slice: &[u8]) -> Result<CompletedData, AnyhowError> {
-        let out = match &mut self.state {
-            ManagerState::InProgress(upload, config) => {
-                upload.complete_upload(slice, config, &self.client).await
-            }
-            _ => Err(anyhow::anyhow!("No upload in progress").into()),
-        };
-        self.make_idle()?;
-        out
+    fn stream(&self, job: JobId) -> Result<Arc<Stream>, AnyhowError> {
+        Ok(self
+            .streams
+            .lock()
+            .unwrap()
+            .get(&job)
+            .cloned()
+            .with_context(|| format!("Upload job {} is not streaming", job))?)
+    }
+
+    fn take_stream(&self, job: JobId) -> Option<Arc<Stream>> {
+        self.streams.lock().unwrap().remove(&job)
     }
 
-    fn make_idle(&mut self) -> Result<(), AnyhowError> {
-        let conf: Result<S3Config, AnyhowError> = match &self.state {
-            ManagerState::InProgress(_, a) => Ok(a.clone()),
-            ManagerState::Idle(a) => Ok(a.clone()),
-            _ => Err(anyhow::anyhow!("No internal s3 config").into()),
+    fn add_stream(&self, job: JobId, upload: UploadNotifier, mime: Mime) {
+        let stream = Stream {
+            upload: Mutex::new(upload),
+            mime,
         };
+        self.streams.lock().unwrap().insert(job, Arc::new(stream));
+    }
 
-        self.state = ManagerState::Idle(conf?);
+    /// Send the last part of a recording and record it in the history
+    pub async fn complete_upload(&self, job: JobId, slice: &[u8]) -> Result<Upload, AnyhowError> {
+        let stream = self
+            .take_stream(job)
+            .with_context(|| format!("Upload job {} is not streaming", job))?;
+        self.jobs.set_state(job, JobState::Completing);
+        let mut upload = stream.upload.lock().await;
+        let res = upload.complete_upload(slice).await;
+        if res.is_err() {
+            // don't leave what was uploaded so far behind
+            let _ = upload.abort().await;
+        }
+        self.jobs.finish_stream(job, res.as_ref());
+        let builder = UploadBuilder::completed(res?, stream.mime.clone());
+        Ok(Upload::create(builder, &self.pool).await?)
+    }
 
-        Ok(())
+    /// Cancel a job, a recording which is streaming is aborted, discarding
+    /// everything sent so far
+    pub async fn cancel_job(&self, job: JobId) -> Result<(), AnyhowError> {
+        match self.take_stream(job) {
+            Some(stream) => {
+                let res = stream.upload.lock().await.abort().await;
+                self.jobs.set_state(job, JobState::Cancelled);
+                res
+            }
+            None => self.jobs.cancel(job),
+        }
+    }
+
+    /// Send a failed or cancelled job again
+    pub fn retry_job(&self, job: JobId) -> Result<(), AnyhowError> {
+        self.jobs.retry(job)
+    }
+
+    pub fn list_jobs(&self) -> Vec<UploadJob> {
+        self.jobs.list()
+    }
+
+    /// How many queued jobs are uploaded at the same time
+    pub fn set_job_limit(&self, limit: usize) {
+        self.jobs.set_limit(limit)
+    }
+
+    fn get_backend(&self) -> Result<&Arc<dyn StorageBackend>, AnyhowError> {
+        Ok(self.backend.as_ref().context("No internal s3 config")?)
+    }
+
+    /// The current backend for features only S3 has
+    fn get_s3(&self) -> Result<&S3Backend, AnyhowError> {
+        Ok(self
+            .get_backend()?
+            .as_s3()
+            .context("The selected config doesn't use S3")?)
+    }
+
+    /// The S3 backend of the config an interrupted upload was made with
+    async fn s3_backend_for(&self, config_id: i64) -> Result<S3Backend, AnyhowError> {
+        let config = S3ConfigRaw::read(config_id, &self.pool).await?.build()?;
+        Ok(S3Backend::new(config, self.client.clone(), self.pool.clone()))
     }
 
-    fn get_config(&self) -> Result<&S3Config, AnyhowError> {
-        match &self.state {
-            ManagerState::InProgress(_, conf) => Ok(conf),
-            ManagerState::Idle(conf) => Ok(conf),
-            _ => Err(anyhow::anyhow!("No internal s3 config").into()),
+    pub async fn upload_part(&self, job: JobId, slice: &[u8]) -> Result<(), AnyhowError> {
+        let stream = self.stream(job)?;
+        let mut upload = stream.upload.lock().await;
+        let res = upload.upload_part(slice).await;
+        if let Err(e) = &res {
+            let _ = upload.abort().await;
+            self.take_stream(job);
+            self.jobs.finish_stream(job, Err(e));
         }
+        res
     }
 
-    pub async fn upload_part(&mut self, slice: &[u8]) -> Result<(), AnyhowError> {
-        match &mut self.state {
-            ManagerState::InProgress(upload, config) => {
-                upload.upload_part(slice, config, &self.client).await
+    /// Start streaming a recording to the current backend as a new job
+    pub async fn new_multipart_upload(&self, object: NewObject) -> Result<JobId, AnyhowError> {
+        let backend = self.get_backend()?.clone();
+        let (job, tx) =
+            self.jobs
+                .start_stream(&object.key, &object.mime, object.kind, backend.config_id());
+        let mime = object.mime.clone();
+        match UploadNotifier::start(backend.as_ref(), object, tx).await {
+            Ok(upload) => {
+                self.add_stream(job, upload, mime);
+                Ok(job)
+            }
+            Err(e) => {
+                self.jobs.finish_stream(job, Err(&e));
+                Err(e)
             }
-            _ => Err(anyhow::anyhow!("No upload in progress").into()),
         }
     }
 
-    pub async fn new_multipart_upload(
-        &mut self,
-        obj_name: String,
-    ) -> Result<Receiver<UploadEvent>, AnyhowError> {
-        let conf: Result<S3Config, AnyhowError> = match &self.state {
-            ManagerState::Idle(a) => Ok(a.clone()),
-            ManagerState::NotInitialized => Err(anyhow::anyhow!("No internal s3 config").into()),
-            ManagerState::InProgress(_, _) => {
-                Err(anyhow::anyhow!("There is already an upload in progress").into())
-            }
-        };
+    /// Multipart uploads which were interrupted before they were completed
+    pub async fn pending_uploads(&self) -> Result<Vec<PendingMultipart>, AnyhowError> {
+        PendingMultipart::list(&self.pool).await
+    }
 
-        let conf = conf?;
-        dbg!(&conf);
-        let (tx, rx) = tauri::async_runtime::channel(10);
-        let upload = InProgressUploadNotifier::new(
-            InProgressUploadNotifierBuilder {
-                config: InProgressUploadBuilder { obj_name },
-                tx
+    /// Complete an interrupted upload with the parts which made it to the
+    /// bucket before the interruption
+    pub async fn finalize_upload(
+        &self,
+        record: PendingMultipart,
+    ) -> Result<CompletedData, AnyhowError> {
+        let backend = self.s3_backend_for(record.config_id).await?;
+        let mut session = backend.resume(record, None).await?;
+        session.complete(&[]).await
+    }
+
+    /// Queue the rest of an interrupted upload, read again from the file it
+    /// was streamed from. A recording is gone once it was interrupted, it
+    /// can only be finalized or discarded.
+    pub async fn resume_upload(&self, record: PendingMultipart) -> Result<JobHandle, AnyhowError> {
+        let Some(path) = record.source_path.clone() else {
+            return Err(anyhow::anyhow!(
+                "{} was recorded and can't be resumed, finalize or discard it",
+                record.obj_name
+            )
+            .into());
+        };
+        if self.jobs.is_key_live(&record.obj_name) {
+            return Err(anyhow::anyhow!("{} is already being uploaded", record.obj_name).into());
+        }
+        let backend = self.s3_backend_for(record.config_id).await?;
+        self.jobs
+            .enqueue_resume(backend, record, PathBuf::from(path))
+    }
 
-        }, &conf, &self.client)
+    /// Abort an interrupted upload instead of completing it
+    pub async fn discard_upload(&self, record: PendingMultipart) -> Result<(), AnyhowError> {
+        let backend = self.s3_backend_for(record.config_id).await?;
+        abort_multipart(
+            backend.config(),
+            backend.client(),
+            &record.obj_name,
+            &record.multipart_id,
+        )
         .await?;
+        PendingMultipart::delete(&record, &self.pool).await?;
+        Ok(())
+    }
 
-        self.state = ManagerState::InProgress(upload, conf);
-        Ok(rx)
+    /// Every multipart upload which is open in the configured bucket,
+    /// including ones started by other machines
+    pub async fn list_open_uploads(&self) -> Result<Vec<OpenMultipartUpload>, AnyhowError> {
+        let backend = self.get_s3()?;
+        list_multipart_uploads(backend.config(), backend.client()).await
     }
 
-    pub fn set_config(&mut self, config: S3Config) -> Result<&mut Self, AnyhowError> {
-        if let ManagerState::InProgress(_, _) = self.state {
-            return Err(anyhow::anyhow!("There is already an upload in progress").into());
+    /// Abort every open multipart upload in the bucket which was started
+    /// longer than `max_age` ago, returning the uploads that were aborted.
+    /// Uploads this app is still sending, such as a long recording, are
+    /// left alone.
+    pub async fn abort_stale_uploads(
+        &self,
+        max_age: Duration,
+    ) -> Result<Vec<OpenMultipartUpload>, AnyhowError> {
+        let backend = self.get_s3()?;
+        let (conf, client) = (backend.config(), backend.client());
+        let cutoff = OffsetDateTime::now_utc() - max_age;
+        let mut aborted = Vec::new();
+        for upload in list_multipart_uploads(conf, client).await? {
+            if upload.initiated_at()? > cutoff || self.jobs.is_key_live(&upload.key) {
+                continue;
+            }
+            abort_multipart(conf, client, &upload.key, &upload.upload_id).await?;
+            PendingMultipart::delete_by_multipart_id(&upload.upload_id, &self.pool).await?;
+            aborted.push(upload);
+        }
+        Ok(aborted)
+    }
+
+    /// Switch the config new uploads go to, each of `replicas` gets a copy.
+    /// Uploads which already started finish with the configs they were
+    /// started with. Replicas which can't be used are left out and reported.
+    pub fn set_config(
+        &mut self,
+        config: S3ConfigRaw,
+        replicas: Vec<S3ConfigRaw>,
+    ) -> Result<&mut Self, AnyhowError> {
+        let primary = open_backend(config, &self.client, &self.pool)?;
+        let mut backends = Vec::with_capacity(replicas.len());
+        let mut errors = Vec::new();
+        for replica in replicas {
+            let name = replica.fields.nickname.clone();
+            match open_backend(replica, &self.client, &self.pool) {
+                Ok(backend) => backends.push(backend),
+                Err(e) => errors.push(format!("{} ({})", name, e)),
+            }
+        }
+        self.backend = Some(if backends.is_empty() {
+            primary
+        } else {
+            Arc::new(ReplicatedBackend::new(primary, backends))
+        });
+        if !errors.is_empty() {
+            return Err(anyhow::anyhow!("Uploads won't be copied to {}", errors.join(", ")).into());
         }
-        self.state = ManagerState::Idle(config);
         Ok(self)
     }
 
-    pub async fn delete(&self, obj_name: &str) -> Result<(), AnyhowError> {
-        let conf = self.get_config()?;
-        let d = DeleteObject::new(conf.bucket(), Some(conf.credentials()), obj_name);
-        let signed = d.sign(Duration::from_secs(3600));
-        let res = self.client.delete(signed).send().await?;
-        dbg!(res.status());
-        res.error_for_status()?;
-        Ok(())
+    /// Try out a config before it is saved. The test takes a while so it
+    /// doesn't hold on to the manager.
+    pub fn test_config(&self, fields: S3ConfigFields) -> impl Future<Output = ConfigReport> {
+        let client = self.client.clone();
+        let pool = self.pool.clone();
+        async move { diagnostics::test_config(fields, &client, &pool).await }
     }
 
-    pub async fn new_upload(
+    /// The backend an earlier upload was made with, uploads which don't know
+    /// their config use the current one
+    async fn config_for(
         &self,
-        obj_name: String,
-        bytes: impl Into<Body>,
-        mime: &Mime,
-    ) -> Result<CompletedData, AnyhowError> {
-        let conf = self.get_config()?;
-        let mut up = PutObject::new(conf.bucket(), Some(conf.credentials()), &obj_name);
-
-        let headers = up.headers_mut();
-        let content = Cow::from(CONTENT_TYPE.to_string());
-        headers.insert(content, mime.essence_str());
-        headers.insert("x-amz-acl", "public-read");
-        let signed = up.sign(Duration::from_secs(3600));
-        self.client
-            .put(signed)
-            .body(bytes)
-            .header(CONTENT_TYPE, mime.essence_str())
-            .header("x-amz-acl", "public-read")
-            .send()
+        config_id: Option<i64>,
+    ) -> Result<Arc<dyn StorageBackend>, AnyhowError> {
+        match config_id {
+            // the current backend also makes the copies to the replicas
+            Some(id) if self.backend.as_ref().is_some_and(|b| b.config_id() == id) => {
+                Ok(self.get_backend()?.clone())
+            }
+            Some(id) => {
+                let config = S3ConfigRaw::read(id, &self.pool).await?;
+                Ok(open_backend(config, &self.client, &self.pool)?)
+            }
+            None => Ok(self.get_backend()?.clone()),
+        }
+    }
+
+    /// Pick the key for a new capture from the key template of the current
+    /// config, making sure nothing is stored under it yet
+    pub async fn new_object_key(&self, kind: ObjectKind, mime: &Mime) -> Result<String, AnyhowError> {
+        unique_key(self.get_backend()?.as_ref(), kind, mime, None).await
+    }
+
+    /// A fresh link for an object uploaded with the config `config_id`, for
+    /// private buckets this signs a new presigned url
+    pub async fn share_url(&self, config_id: Option<i64>, obj_name: &str) -> Result<Url, AnyhowError> {
+        self.config_for(config_id).await?.share_link(obj_name).await
+    }
+
+    pub async fn delete(&self, upload: &Upload) -> Result<(), AnyhowError> {
+        self.config_for(upload.config_id())
             .await?
-            .error_for_status()?;
-        let upload_url = conf.bucket().object_url(&obj_name)?;
-        dbg!("done upload");
-        Ok(CompletedData { upload_url })
+            .delete_upload(&upload.obj_key()?, upload.deletion_url())
+            .await
+    }
+
+    /// The backend of a saved config, for work which shouldn't hold on to
+    /// the manager
+    pub async fn backend_for(&self, config_id: i64) -> Result<Arc<dyn StorageBackend>, AnyhowError> {
+        self.config_for(Some(config_id)).await
+    }
+
+    /// Queue an object to be uploaded in one go with the current config, if
+    /// it fails it is spooled and retried in the background
+    pub fn new_upload(
+        &self,
+        object: QueuedObject,
+        bytes: impl Into<Bytes>,
+    ) -> Result<JobHandle, AnyhowError> {
+        let backend = self.get_backend()?.clone();
+        Ok(self.jobs.enqueue(backend, object, bytes.into()))
+    }
+
+    /// Queue a file from disk with the current config, large files are
+    /// streamed from disk instead of being read into memory
+    pub async fn upload_file(&self, path: PathBuf) -> Result<JobHandle, AnyhowError> {
+        let file = LocalFile::inspect(path).await?;
+        let backend = self.get_backend()?.clone();
+        let object = QueuedObject {
+            key: None,
+            mime: file.mime,
+            kind: ObjectKind::File,
+            metadata: ObjectMetadata {
+                filename: file.filename,
+                ..Default::default()
+            },
+        };
+        if file.size >= STREAM_THRESHOLD {
+            return Ok(self.jobs.enqueue_file(backend, object, file.path));
+        }
+        let bytes = tokio::fs::read(&file.path).await?;
+        Ok(self.jobs.enqueue(backend, object, bytes.into()))
+    }
+
+    /// Uploads which failed and wait in the spool to be tried again
+    pub async fn spooled_uploads(&self) -> Result<Vec<SpooledUpload>, AnyhowError> {
+        self.jobs.spool().list().await
+    }
+
+    /// Queue another attempt at a spooled upload, unless one is under way
+    pub async fn retry_spooled(&self, record: &SpooledUpload) -> Result<(), AnyhowError> {
+        if self.jobs.is_spooled_pending(record.id()) {
+            return Ok(());
+        }
+        let backend = self.config_for(Some(record.config_id)).await?;
+        let bytes = self.jobs.spool().load(record).await?;
+        self.jobs.enqueue_spooled(backend, record, bytes)?;
+        Ok(())
     }
-}
 
+    /// Retry a spooled upload without waiting for its backoff
+    pub async fn retry_spooled_now(&self, id: i64) -> Result<(), AnyhowError> {
+        self.jobs.spool().retry_now(id).await
+    }
+
+    /// Give up on a spooled upload, deleting its bytes
+    pub async fn discard_spooled(&self, id: i64) -> Result<(), AnyhowError> {
+        if self.jobs.is_spooled_pending(id) {
+            return Err(anyhow::anyhow!("The upload is being retried, cancel its job first").into());
+        }
+        self.jobs.spool().remove(id).await
+    }
+}
 
 pub struct S3Plugin;
 
-async fn get_s3_config(pool: &SqlitePool) -> Result<S3Config, AnyhowError> {
+async fn get_selected_config(pool: &SqlitePool) -> Result<S3ConfigRaw, AnyhowError> {
     Ok(SelectedConfig::get(pool)
         .await?
-        .context("No config selected")?
-        .build()?)
+        .context("No config selected")?)
 }
 
 pub type UploadManager = RwLock<UploadClient>;
@@ -168,13 +419,33 @@ impl S3Plugin {
     pub fn build<R: Runtime>(self) -> TauriPlugin<R, ()> {
         PluginBuilder::<R, ()>::new("s3")
         .setup(move |app, _api| {
-                let mut manager = UploadClient::default();
                 let pool = app.state::<SqlitePool>();
+                let spool_dir = app.path().app_data_dir()?.join("spool");
+                let spool = Arc::new(Spool::new(spool_dir, pool.inner().clone()));
+                let app = app.clone();
                 tauri::async_runtime::block_on(async move {
-                    if let Ok(config) = get_s3_config(&pool).await {
-                        let _ = manager.set_config(config);
+                    let settings = UploadSettings::get(&pool).await.unwrap_or_default();
+                    let (jobs, events) = JobQueue::new(
+                        pool.inner().clone(),
+                        spool.clone(),
+                        settings.max_concurrent_jobs as usize,
+                    );
+                    forward_events(app.clone(), events);
+                    let mut manager = UploadClient::new(pool.inner().clone(), jobs);
+                    if let Ok(config) = get_selected_config(&pool).await {
+                        let replicas = SelectedConfig::replicas(&pool).await.unwrap_or_default();
+                        let _ = manager.set_config(config, replicas);
+                    }
+                    // the history offers to resume, finalize or discard them
+                    if let Ok(pending) = manager.pending_uploads().await {
+                        if !pending.is_empty() {
+                            let _ = app.emit_all("interrupted-uploads", &pending);
+                        }
                     }
                     app.manage::<UploadManager>(RwLock::new(manager));
+                    // the workers need the manager to be managed
+                    spawn_worker(app.clone(), spool);
+                    spawn_sweeper(app, pool.inner().clone());
                 });
                 Ok(())
             })
@@ -0,0 +1,176 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use futures_util::stream;
+use serde::Serialize;
+use tauri::async_runtime::Sender;
+use tauri_plugin_http::reqwest::Body;
+
+use super::uploader::UploadEvent;
+
+/// Bodies are split into chunks of this size so progress is reported while
+/// a request is being written rather than once it is done
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Don't flood the frontend, one progress event per interval is plenty
+const EMIT_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadProgress {
+    pub bytes_sent: u64,
+    pub total_bytes: u64,
+    pub part_number: Option<u16>,
+    pub bytes_per_second: f64,
+    pub eta_seconds: Option<f64>,
+}
+
+#[derive(Debug)]
+struct ProgressState {
+    bytes_sent: u64,
+    total_bytes: u64,
+    last_emit: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    tx: Option<Sender<UploadEvent>>,
+    started: Instant,
+    /// bytes which were already sent before tracking started, these don't
+    /// count towards the throughput
+    baseline: u64,
+    state: Mutex<ProgressState>,
+}
+
+/// Counts the bytes of an upload as they are written to the network and
+/// reports them as `UploadEvent::Progress`
+#[derive(Debug, Clone)]
+pub struct ProgressTracker(Arc<Inner>);
+
+impl Default for ProgressTracker {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl ProgressTracker {
+    pub fn new(tx: Option<Sender<UploadEvent>>) -> Self {
+        Self::resumed(tx, 0)
+    }
+
+    /// Track an upload of which `already_sent` bytes were sent in the past
+    pub fn resumed(tx: Option<Sender<UploadEvent>>, already_sent: u64) -> Self {
+        Self(Arc::new(Inner {
+            tx,
+            started: Instant::now(),
+            baseline: already_sent,
+            state: Mutex::new(ProgressState {
+                bytes_sent: already_sent,
+                total_bytes: already_sent,
+                last_emit: None,
+            }),
+        }))
+    }
+
+    /// More bytes are known to be part of the upload
+    pub fn add_total(&self, n: u64) {
+        self.0.state.lock().unwrap().total_bytes += n;
+    }
+
+    /// `n` more bytes were written, backends which don't send a
+    /// `TrackedBody` report their writes with this
+    pub fn sent(&self, n: u64, part_number: Option<u16>) {
+        let progress = {
+            let mut state = self.0.state.lock().unwrap();
+            state.bytes_sent += n;
+            let now = Instant::now();
+            let due = state
+                .last_emit
+                .map_or(true, |t| now.duration_since(t) >= EMIT_INTERVAL);
+            if !due && state.bytes_sent < state.total_bytes {
+                return;
+            }
+            state.last_emit = Some(now);
+            self.snapshot(&state, part_number)
+        };
+        if let Some(tx) = &self.0.tx {
+            // progress is best effort, a full channel just drops the event
+            let _ = tx.try_send(UploadEvent::Progress(progress));
+        }
+    }
+
+    /// Roll back bytes from an attempt which failed and will be resent
+    fn unsent(&self, n: u64) {
+        let mut state = self.0.state.lock().unwrap();
+        state.bytes_sent = state.bytes_sent.saturating_sub(n);
+    }
+
+    fn snapshot(&self, state: &ProgressState, part_number: Option<u16>) -> UploadProgress {
+        let elapsed = self.0.started.elapsed().as_secs_f64();
+        let bytes_per_second = if elapsed > 0.0 {
+            state.bytes_sent.saturating_sub(self.0.baseline) as f64 / elapsed
+        } else {
+            0.0
+        };
+        let remaining = state.total_bytes.saturating_sub(state.bytes_sent);
+        let eta_seconds = (bytes_per_second > 0.0).then(|| remaining as f64 / bytes_per_second);
+        UploadProgress {
+            bytes_sent: state.bytes_sent,
+            total_bytes: state.total_bytes,
+            part_number,
+            bytes_per_second,
+            eta_seconds,
+        }
+    }
+
+    /// Prepare to report progress for one request body which may be sent
+    /// several times when it is retried
+    pub fn body(&self, bytes: Bytes, part_number: Option<u16>) -> TrackedBody {
+        TrackedBody {
+            tracker: self.clone(),
+            bytes,
+            part_number,
+            attempt_sent: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+pub struct TrackedBody {
+    tracker: ProgressTracker,
+    bytes: Bytes,
+    part_number: Option<u16>,
+    attempt_sent: Arc<AtomicU64>,
+}
+
+impl TrackedBody {
+    pub fn content_length(&self) -> u64 {
+        self.bytes.len() as u64
+    }
+
+    /// A fresh request body for the next attempt, whatever the previous
+    /// attempt managed to send is no longer counted as sent
+    pub fn attempt(&self) -> Body {
+        self.tracker
+            .unsent(self.attempt_sent.swap(0, Ordering::SeqCst));
+
+        let tracker = self.tracker.clone();
+        let attempt_sent = self.attempt_sent.clone();
+        let part_number = self.part_number;
+        let bytes = self.bytes.clone();
+        let chunks = (0..bytes.len())
+            .step_by(CHUNK_SIZE)
+            .map(move |start| bytes.slice(start..(start + CHUNK_SIZE).min(bytes.len())));
+        let stream = stream::iter(chunks.map(move |chunk| {
+            let n = chunk.len() as u64;
+            attempt_sent.fetch_add(n, Ordering::SeqCst);
+            tracker.sent(n, part_number);
+            Ok::<Bytes, std::io::Error>(chunk)
+        }));
+        Body::wrap_stream(stream)
+    }
+}
@@ -0,0 +1,161 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use tauri::{AppHandle, Manager, Runtime};
+
+use super::plugin::UploadManagerExt;
+use crate::{
+    db::crud::{Delete, ExpiredUpload, RetentionRule, Upload},
+    error::AnyhowError,
+};
+
+/// Give the app time to start up before the first sweep
+const FIRST_SWEEP: Duration = Duration::from_secs(5 * 60);
+const SWEEP_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// How many uploads the background sweep deletes for a rule which was never
+/// confirmed by hand, a new rule which would delete more (for example after
+/// years of screenshots were imported) waits to be previewed and confirmed
+const UNCONFIRMED_LIMIT: usize = 10;
+
+/// The part of a mime type before the slash, retention rules can be limited
+/// to one of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum MimeClass {
+    /// screenshots
+    Image,
+    /// recordings
+    Video,
+    Audio,
+    Text,
+    Application,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FailedDeletion {
+    pub id: i64,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sweep {
+    /// only report what would be deleted
+    DryRun,
+    /// started by hand after a preview, which confirms the rules involved
+    Confirmed,
+    /// the periodic sweep, see `UNCONFIRMED_LIMIT`
+    Background,
+}
+
+/// What a sweep deleted, or would delete when it is a dry run
+#[derive(Debug, Serialize)]
+pub struct RetentionReport {
+    pub dry_run: bool,
+    pub expired: Vec<ExpiredUpload>,
+    pub deleted: usize,
+    /// uploads left alone because their rule wasn't confirmed yet
+    pub held: usize,
+    pub failed: Vec<FailedDeletion>,
+}
+
+/// Delete every upload which is older than its retention rule allows,
+/// pinned uploads are left alone
+pub async fn sweep<R: Runtime>(
+    app: &AppHandle<R>,
+    pool: &SqlitePool,
+    mode: Sweep,
+) -> Result<RetentionReport, AnyhowError> {
+    let mut report = RetentionReport {
+        dry_run: mode == Sweep::DryRun,
+        expired: Upload::expired(pool).await?,
+        deleted: 0,
+        held: 0,
+        failed: Vec::new(),
+    };
+    if report.dry_run {
+        return Ok(report);
+    }
+    let mut unconfirmed = BTreeMap::<i64, usize>::new();
+    for expired in report.expired.iter().filter(|e| !e.rule_confirmed) {
+        *unconfirmed.entry(expired.rule_id).or_default() += 1;
+    }
+    for expired in &report.expired {
+        let too_many = unconfirmed
+            .get(&expired.rule_id)
+            .is_some_and(|count| *count > UNCONFIRMED_LIMIT);
+        if mode == Sweep::Background && too_many {
+            report.held += 1;
+            continue;
+        }
+        match expire(app, pool, &expired.upload).await {
+            Ok(()) => report.deleted += 1,
+            // the upload stays in the history so the next sweep tries again
+            Err(e) => report.failed.push(FailedDeletion {
+                id: expired.upload.id(),
+                error: e.to_string(),
+            }),
+        }
+    }
+    if mode == Sweep::Confirmed {
+        for rule in unconfirmed.into_keys() {
+            RetentionRule::confirm(rule, pool).await?;
+        }
+    }
+    if report.deleted > 0 {
+        let _ = app.emit_all("reload-uploads", ());
+    }
+    Ok(report)
+}
+
+async fn expire<R: Runtime>(
+    app: &AppHandle<R>,
+    pool: &SqlitePool,
+    upload: &Upload,
+) -> Result<(), AnyhowError> {
+    app.upload_manager().read().await.delete(upload).await?;
+    Upload::delete(upload, pool).await?;
+    Ok(())
+}
+
+/// Sweep expired uploads every few hours for as long as the app runs,
+/// problems and held back rules are sent to the frontend as
+/// `retention-failed`
+pub fn spawn_sweeper<R: Runtime>(app: AppHandle<R>, pool: SqlitePool) {
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(FIRST_SWEEP).await;
+        loop {
+            let mut problems = Vec::new();
+            match sweep(&app, &pool, Sweep::Background).await {
+                Ok(report) => {
+                    if report.held > 0 {
+                        problems.push(format!(
+                            "{} expired uploads were kept because a new retention rule would delete more than {} at once, preview and delete them in the settings to confirm it",
+                            report.held, UNCONFIRMED_LIMIT
+                        ));
+                    }
+                    if !report.failed.is_empty() {
+                        problems.push(format!(
+                            "{} of {} expired uploads could not be deleted: {}",
+                            report.failed.len(),
+                            report.expired.len(),
+                            report
+                                .failed
+                                .iter()
+                                .map(|f| format!("upload {}: {}", f.id, f.error))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ));
+                    }
+                }
+                Err(e) => problems.push(format!("Failed to sweep expired uploads: {}", e)),
+            }
+            if !problems.is_empty() {
+                let _ = app.emit_all("retention-failed", problems.join(". "));
+            }
+            tokio::time::sleep(SWEEP_INTERVAL).await;
+        }
+    });
+}
@@ -0,0 +1,212 @@
+use std::{fmt::Display, future::Future, time::Duration};
+
+use rand::Rng;
+use serde::Serialize;
+use tauri_plugin_http::reqwest::{RequestBuilder, Response, StatusCode};
+use thiserror::Error;
+
+use crate::error::AnyhowError;
+
+/// S3 error codes which are worth retrying even though they come back as a
+/// 4xx or inside a 200 response. `RequestTimeTooSkewed` is not one of them,
+/// urls are signed once before the retries so every attempt is as skewed.
+const RETRYABLE_CODES: &[&str] = &[
+    "SlowDown",
+    "RequestTimeout",
+    "InternalError",
+    "ServiceUnavailable",
+    "OperationAborted",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum FailureKind {
+    /// a transient failure, trying again later may succeed
+    Retryable,
+    /// the request will never succeed without changing the config
+    Permanent,
+}
+
+impl Display for FailureKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Retryable => write!(f, "retryable"),
+            Self::Permanent => write!(f, "permanent"),
+        }
+    }
+}
+
+impl FailureKind {
+    /// Classify any error which made it out of the upload code, errors which
+    /// did not come from a request are treated as permanent
+    pub fn of(e: &AnyhowError) -> Self {
+        e.downcast_ref::<S3Error>()
+            .map(|e| e.kind)
+            .unwrap_or(FailureKind::Permanent)
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("{kind} S3 failure after {attempts} attempt(s): {message}")]
+pub struct S3Error {
+    pub kind: FailureKind,
+    pub status: Option<u16>,
+    pub code: Option<String>,
+    pub message: String,
+    pub attempts: u32,
+}
+
+impl S3Error {
+    pub fn from_reqwest(e: tauri_plugin_http::reqwest::Error) -> Self {
+        // builder errors mean the request itself is malformed, everything
+        // else (timeouts, refused or reset connections) is a network failure
+        let kind = if e.is_builder() {
+            FailureKind::Permanent
+        } else {
+            FailureKind::Retryable
+        };
+        Self {
+            kind,
+            status: e.status().map(|s| s.as_u16()),
+            code: None,
+            message: e.to_string(),
+            attempts: 1,
+        }
+    }
+
+    fn from_body(status: StatusCode, body: &str) -> Self {
+        let code = xml_tag(body, "Code");
+        let kind = match code {
+            Some(c) if RETRYABLE_CODES.contains(&c) => FailureKind::Retryable,
+            _ if status.is_server_error() => FailureKind::Retryable,
+            _ if status == StatusCode::REQUEST_TIMEOUT => FailureKind::Retryable,
+            _ if status == StatusCode::TOO_MANY_REQUESTS => FailureKind::Retryable,
+            _ => FailureKind::Permanent,
+        };
+        let message = match (code, xml_tag(body, "Message")) {
+            (Some(c), Some(m)) => format!("{} {}: {}", status, c, m),
+            (Some(c), None) => format!("{} {}", status, c),
+            _ => status.to_string(),
+        };
+        Self {
+            kind,
+            status: Some(status.as_u16()),
+            code: code.map(ToOwned::to_owned),
+            message,
+            attempts: 1,
+        }
+    }
+}
+
+fn xml_tag<'a>(body: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = body.find(&open)? + open.len();
+    let end = body[start..].find(&close)? + start;
+    Some(&body[start..end])
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with full jitter
+    fn delay(&self, attempt: u32) -> Duration {
+        let cap = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        let millis = rand::thread_rng().gen_range(0..=cap.as_millis() as u64);
+        Duration::from_millis(millis)
+    }
+
+    /// Run `op` until it succeeds, fails with a permanent error or runs out
+    /// of attempts
+    async fn retry<T, F, Fut>(&self, op: F) -> Result<T, S3Error>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, S3Error>>,
+    {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let err = match op().await {
+                Ok(t) => return Ok(t),
+                Err(e) => e,
+            };
+            if err.kind == FailureKind::Permanent || attempt >= self.max_attempts {
+                return Err(S3Error {
+                    attempts: attempt,
+                    ..err
+                });
+            }
+            tokio::time::sleep(self.delay(attempt)).await;
+        }
+    }
+
+    /// Send the request built by `make_request` with retries. The builder is
+    /// called once per attempt since a request can only be sent once.
+    pub async fn send<F>(&self, make_request: F) -> Result<Response, S3Error>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        let make_request = &make_request;
+        self.retry(move || async move {
+            let res = make_request()
+                .send()
+                .await
+                .map_err(S3Error::from_reqwest)?;
+            check_response(res).await
+        })
+        .await
+    }
+
+    /// Like `send` but also reads the body, some S3 actions (notably
+    /// CompleteMultipartUpload) report errors inside a 200 response
+    pub async fn send_text<F>(&self, make_request: F) -> Result<String, S3Error>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        let make_request = &make_request;
+        self.retry(move || async move {
+            let res = make_request()
+                .send()
+                .await
+                .map_err(S3Error::from_reqwest)?;
+            let res = check_response(res).await?;
+            let status = res.status();
+            let body = res.text().await.map_err(S3Error::from_reqwest)?;
+            if body.contains("<Error>") {
+                return Err(S3Error::from_body(status, &body));
+            }
+            Ok(body)
+        })
+        .await
+    }
+}
+
+/// Turn an error response into an `S3Error`, for requests which are sent
+/// without `RetryPolicy` because their body can't be sent twice
+pub async fn check_response(res: Response) -> Result<Response, S3Error> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+    let body = res.text().await.unwrap_or_default();
+    Err(S3Error::from_body(status, &body))
+}
@@ -0,0 +1,150 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use sqlx::SqlitePool;
+use tauri::{AppHandle, Manager, Runtime};
+use tokio::sync::Notify;
+
+use super::jobs::QueuedObject;
+use super::plugin::UploadManagerExt;
+use super::retry::FailureKind;
+use crate::{
+    db::crud::{Create, Delete, List, Read, SpooledUpload, SpooledUploadBuilder},
+    error::AnyhowError,
+};
+
+/// Spooled uploads are checked this often even when nothing wakes the worker
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+const MIN_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// How long to wait before trying an upload which failed `attempts` times
+pub fn backoff(attempts: u32) -> Duration {
+    MIN_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+/// Uploads which failed, for example because the network is down, are
+/// written to a directory and retried in the background so no capture is
+/// lost
+pub struct Spool {
+    dir: PathBuf,
+    pool: SqlitePool,
+    wake: Notify,
+}
+
+impl Spool {
+    pub fn new(dir: PathBuf, pool: SqlitePool) -> Self {
+        Self {
+            dir,
+            pool,
+            wake: Notify::new(),
+        }
+    }
+
+    fn path(&self, id: i64) -> PathBuf {
+        self.dir.join(id.to_string())
+    }
+
+    /// Keep the bytes of an upload which failed to be tried again later
+    pub async fn store(
+        &self,
+        config_id: i64,
+        object: &QueuedObject,
+        bytes: &Bytes,
+        error: &AnyhowError,
+    ) -> Result<SpooledUpload, AnyhowError> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let record = SpooledUpload::create(
+            SpooledUploadBuilder {
+                config_id,
+                obj_name: object.key.clone(),
+                mime: object.mime.clone(),
+                kind: object.kind,
+                metadata: object.metadata.clone(),
+                error: error.to_string(),
+                failure_kind: FailureKind::of(error),
+                retry_in: backoff(1),
+            },
+            &self.pool,
+        )
+        .await?;
+        if let Err(e) = tokio::fs::write(self.path(record.id()), bytes).await {
+            SpooledUpload::delete(&record, &self.pool).await?;
+            return Err(e.into());
+        }
+        Ok(record)
+    }
+
+    pub async fn load(&self, record: &SpooledUpload) -> Result<Bytes, AnyhowError> {
+        Ok(tokio::fs::read(self.path(record.id())).await?.into())
+    }
+
+    /// The upload went through, or is given up on
+    pub async fn remove(&self, id: i64) -> Result<(), AnyhowError> {
+        match tokio::fs::remove_file(self.path(id)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => (),
+        }
+        SpooledUpload::delete(id, &self.pool).await?;
+        Ok(())
+    }
+
+    /// Another attempt failed, back off further. Permanent failures are
+    /// not scheduled again until they are retried by hand.
+    pub async fn failed(&self, id: i64, error: &AnyhowError) -> Result<(), AnyhowError> {
+        let record = SpooledUpload::read(id, &self.pool).await?;
+        let attempts = u32::try_from(record.attempts + 1).unwrap_or(u32::MAX);
+        SpooledUpload::failed(
+            id,
+            &error.to_string(),
+            FailureKind::of(error),
+            backoff(attempts),
+            &self.pool,
+        )
+        .await
+    }
+
+    pub async fn list(&self) -> Result<Vec<SpooledUpload>, AnyhowError> {
+        SpooledUpload::list(&self.pool).await
+    }
+
+    /// Retry an upload now instead of waiting for its backoff
+    pub async fn retry_now(&self, id: i64) -> Result<(), AnyhowError> {
+        SpooledUpload::make_due(id, &self.pool).await?;
+        self.wake.notify_one();
+        Ok(())
+    }
+}
+
+/// Queue every spooled upload which is due, the worker is woken early when
+/// an upload is retried by hand. Uploads which can't be queued back off like
+/// failed attempts and stay listed until they are discarded.
+pub fn spawn_worker<R: Runtime>(app: AppHandle<R>, spool: Arc<Spool>) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match SpooledUpload::due(&spool.pool).await {
+                Ok(due) => {
+                    for record in due {
+                        let manager = app.upload_manager().read().await;
+                        if let Err(e) = manager.retry_spooled(&record).await {
+                            let _ = match spool.failed(record.id(), &e).await {
+                                Ok(()) => app.emit_all("reload-spooled-uploads", ()),
+                                Err(e) => app.emit_all("spool-error", e.to_string()),
+                            };
+                        }
+                    }
+                }
+                Err(e) => {
+                    let _ = app.emit_all("spool-error", e.to_string());
+                }
+            }
+            tokio::select! {
+                _ = spool.wake.notified() => (),
+                _ = tokio::time::sleep(POLL_INTERVAL) => (),
+            }
+        }
+    });
+}
@@ -1,155 +1,668 @@
-use std::{time::Duration, fmt::Debug};
+use std::{collections::BTreeMap, path::PathBuf, time::Duration};
 
 use bytes::BytesMut;
 use rusty_s3::{
-    actions::{CompleteMultipartUpload, CreateMultipartUpload, UploadPart},
-    Bucket, Credentials, S3Action,
+    actions::{CompleteMultipartUpload, CreateMultipartUpload, GetObject, UploadPart},
+    Bucket, Credentials, S3Action, UrlStyle,
 };
-use tauri::{async_runtime::Sender, http::header::ETAG};
-use tauri_plugin_http::reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use tauri::{
+    async_runtime::Sender,
+    http::header::{CONTENT_LENGTH, ETAG},
+};
+use tauri_plugin_http::reqwest::{Client, RequestBuilder, Url};
+use url::Host;
+use tokio::task::JoinSet;
+
+use mime::Mime;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sqlx::SqlitePool;
+
+use super::{
+    encryption::ServerSideEncryption,
+    metadata::ContentDisposition,
+    integrity::{verify_size, ChecksumAlgorithm},
+    maintenance::abort_multipart,
+    backend::delete_object,
+    progress::{ProgressTracker, UploadProgress},
+    retry::{FailureKind, RetryPolicy},
+};
+use crate::{
+    db::crud::{
+        Create, Delete, PendingMultipart, PendingMultipartBuilder, PendingPart,
+    },
+    error::AnyhowError,
+    storage::replicated::Replica,
+    template,
+};
+
+pub const DEFAULT_MAX_CONCURRENT_PARTS: usize = 4;
+
+/// S3 rejects any part number above this
+pub const MAX_PARTS: u16 = 10_000;
+
+pub const MIB: usize = 1024 * 1024;
 
-use crate::error::AnyhowError;
+/// S3 requires every part but the last to be at least 5 MiB
+pub const DEFAULT_MIN_PART_SIZE_MIB: usize = 5;
 
+/// The largest part S3 accepts, 5 GiB
+pub const DEFAULT_MAX_PART_SIZE_MIB: usize = 5 * 1024;
+
+/// The part size doubles every this many parts, starting from the minimum
+/// part size of 5 MiB this keeps objects up to the 5 TB limit within
+/// `MAX_PARTS` parts
+const PARTS_PER_SIZE_STEP: u16 = 1_000;
+
+/// The longest a SigV4 presigned url can be valid for, 7 days
+pub const DEFAULT_PRESIGN_EXPIRY_SECS: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
 pub enum UploadEvent {
     Started,
-    Progress,
+    Progress(UploadProgress),
     Done,
-    Failed,
+    Aborted,
+    Failed(FailureKind),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum AccessMode {
+    /// objects are uploaded with a public-read ACL
+    #[default]
+    PublicAcl,
+    /// a bucket policy makes objects public, buckets with ACLs disabled
+    /// reject any request which sends one
+    BucketPolicy,
+    /// objects stay private and are shared with presigned links
+    Private,
+}
+
+impl AccessMode {
+    fn acl(&self) -> Option<&'static str> {
+        match self {
+            Self::PublicAcl => Some("public-read"),
+            Self::BucketPolicy | Self::Private => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum AddressingStyle {
+    /// path style for IP and localhost endpoints, virtual host otherwise
+    #[default]
+    Auto,
+    /// `https://endpoint/bucket/key`, for servers without wildcard DNS
+    Path,
+    /// `https://bucket.endpoint/key`
+    VirtualHost,
+}
+
+impl AddressingStyle {
+    pub fn resolve(&self, endpoint: &Url) -> UrlStyle {
+        match self {
+            Self::Path => UrlStyle::Path,
+            Self::VirtualHost => UrlStyle::VirtualHost,
+            Self::Auto => match endpoint.host() {
+                Some(Host::Ipv4(_)) | Some(Host::Ipv6(_)) => UrlStyle::Path,
+                Some(Host::Domain(d)) if d == "localhost" || d.ends_with(".localhost") => {
+                    UrlStyle::Path
+                }
+                _ => UrlStyle::VirtualHost,
+            },
+        }
+    }
+}
+
+/// How large the parts of a multipart upload are. Parts start at `min`
+/// bytes so short recordings are sent without much buffering and grow as
+/// the part count rises so long recordings don't run out of part numbers.
+#[derive(Clone, Copy, Debug)]
+pub struct PartSizing {
+    min: usize,
+    max: usize,
+}
+
+impl Default for PartSizing {
+    fn default() -> Self {
+        Self::from_mib(DEFAULT_MIN_PART_SIZE_MIB, DEFAULT_MAX_PART_SIZE_MIB)
+    }
+}
+
+impl PartSizing {
+    pub fn from_mib(min: usize, max: usize) -> Self {
+        let min = min.max(1) * MIB;
+        Self {
+            min,
+            max: (max * MIB).max(min),
+        }
+    }
+
+    /// The number of buffered bytes at which `part_number` is sent. The last
+    /// part number holds everything left until the upload is completed, it
+    /// is held to the same size so it can't take up to `max` bytes of memory.
+    pub fn threshold(&self, part_number: u16) -> usize {
+        let step = u32::from(part_number.saturating_sub(1) / PARTS_PER_SIZE_STEP);
+        self.min
+            .checked_shl(step)
+            .filter(|size| *size <= self.max)
+            .unwrap_or(self.max)
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct S3Config {
+    id: i64,
     bucket: Bucket,
     credentials: Credentials,
     host_rewrite: Option<String>,
+    url_template: Option<String>,
+    key_template: Option<String>,
+    key_prefix: Option<String>,
+    max_concurrent_parts: usize,
+    access_mode: AccessMode,
+    presign_expiry: Duration,
+    disable_acl: bool,
+    unsigned_payload: bool,
+    checksum: ChecksumAlgorithm,
+    part_sizing: PartSizing,
+    encryption: ServerSideEncryption,
+    storage_class: Option<String>,
+    cache_control: Option<String>,
+    content_disposition: ContentDisposition,
+    metadata: BTreeMap<String, String>,
 }
 
 impl S3Config {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
     pub fn bucket(&self) -> &Bucket {
         &self.bucket
     }
     pub fn credentials(&self) -> &Credentials {
         &self.credentials
     }
+    pub fn max_concurrent_parts(&self) -> usize {
+        self.max_concurrent_parts
+    }
+    pub fn checksum(&self) -> ChecksumAlgorithm {
+        self.checksum
+    }
+    pub fn part_sizing(&self) -> PartSizing {
+        self.part_sizing
+    }
+    pub fn key_template(&self) -> Option<&str> {
+        self.key_template.as_deref()
+    }
+    pub fn key_prefix(&self) -> Option<&str> {
+        self.key_prefix.as_deref()
+    }
+    pub fn cache_control(&self) -> Option<&str> {
+        self.cache_control.as_deref()
+    }
+    pub fn content_disposition(&self) -> ContentDisposition {
+        self.content_disposition
+    }
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    /// Headers describing how an object is stored, these are sent with both
+    /// PutObject and CreateMultipartUpload
+    pub fn object_headers(&self) -> Vec<(String, String)> {
+        let mut headers = self.payload_headers();
+        if let Some(acl) = self.access_mode.acl().filter(|_| !self.disable_acl) {
+            headers.push(("x-amz-acl".to_owned(), acl.to_owned()));
+        }
+        headers.extend(self.encryption.create_headers());
+        if let Some(storage_class) = &self.storage_class {
+            headers.push(("x-amz-storage-class".to_owned(), storage_class.clone()));
+        }
+        headers
+    }
+
+    /// Headers for every UploadPart of a multipart upload
+    pub fn part_headers(&self) -> Vec<(String, String)> {
+        let mut headers = self.payload_headers();
+        headers.extend(self.encryption.customer_key_headers());
+        headers
+    }
+
+    /// Headers for requests reading an existing object, SSE-C objects can't
+    /// even be checked without the key
+    pub fn read_headers(&self) -> Vec<(String, String)> {
+        self.encryption.customer_key_headers()
+    }
+
+    /// Headers for every request which carries object data
+    pub fn payload_headers(&self) -> Vec<(String, String)> {
+        let mut headers = Vec::new();
+        if self.unsigned_payload {
+            headers.push(("x-amz-content-sha256".to_owned(), "UNSIGNED-PAYLOAD".to_owned()));
+        }
+        headers
+    }
+
+    /// The url an object is shared with. Private objects get a presigned
+    /// link, otherwise a url template takes precedence and then
+    /// `host_rewrite` replaces the origin of the bucket url.
+    pub fn public_url(&self, obj_name: &str) -> Result<Url, AnyhowError> {
+        if self.access_mode == AccessMode::Private {
+            let action = GetObject::new(&self.bucket, Some(&self.credentials), obj_name);
+            return Ok(action.sign(self.presign_expiry));
+        }
+
+        if let Some(template) = &self.url_template {
+            let filename = obj_name.rsplit('/').next().unwrap_or(obj_name);
+            let url = template::render(template, |name| match name {
+                "key" => Some(encode_key(obj_name)),
+                "filename" => Some(encode_key(filename)),
+                "bucket" => Some(self.bucket.name().to_owned()),
+                "region" => Some(self.bucket.region().to_owned()),
+                _ => None,
+            })?;
+            return Ok(Url::parse(&url)?);
+        }
+
+        let url = self.bucket.object_url(obj_name)?;
+        match &self.host_rewrite {
+            Some(host) => Ok(Url::parse(&format!(
+                "{}{}",
+                host.trim_end_matches('/'),
+                url.path()
+            ))?),
+            None => Ok(url),
+        }
+    }
+}
+
+/// Add `headers` to a request, they have to match the ones which were signed
+pub fn with_headers(request: RequestBuilder, headers: &[(String, String)]) -> RequestBuilder {
+    headers
+        .iter()
+        .fold(request, |request, (name, value)| request.header(name, value))
+}
+
+pub const URL_TEMPLATE_PLACEHOLDERS: &[&str] = &["key", "filename", "bucket", "region"];
+
+/// Everything but the path separator and unreserved characters is escaped
+const KEY_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+pub fn encode_key(key: &str) -> String {
+    utf8_percent_encode(key, KEY_ENCODE_SET).to_string()
 }
 
 impl S3Config {
-    pub fn new(bucket: Bucket, credentials: Credentials, host_rewrite: Option<String>) -> Self {
+    pub fn new(
+        id: i64,
+        bucket: Bucket,
+        credentials: Credentials,
+        host_rewrite: Option<String>,
+    ) -> Self {
         Self {
+            id,
             bucket,
             credentials,
             host_rewrite,
+            url_template: None,
+            key_template: None,
+            key_prefix: None,
+            max_concurrent_parts: DEFAULT_MAX_CONCURRENT_PARTS,
+            access_mode: AccessMode::default(),
+            presign_expiry: Duration::from_secs(DEFAULT_PRESIGN_EXPIRY_SECS),
+            disable_acl: false,
+            unsigned_payload: false,
+            checksum: ChecksumAlgorithm::default(),
+            part_sizing: PartSizing::default(),
+            encryption: ServerSideEncryption::default(),
+            storage_class: None,
+            cache_control: None,
+            content_disposition: ContentDisposition::default(),
+            metadata: BTreeMap::new(),
         }
     }
+
+    pub fn with_metadata(
+        mut self,
+        cache_control: Option<String>,
+        content_disposition: ContentDisposition,
+        metadata: BTreeMap<String, String>,
+    ) -> Self {
+        self.cache_control = cache_control;
+        self.content_disposition = content_disposition;
+        self.metadata = metadata;
+        self
+    }
+
+    pub fn with_storage(
+        mut self,
+        encryption: ServerSideEncryption,
+        storage_class: Option<String>,
+    ) -> Self {
+        self.encryption = encryption;
+        self.storage_class = storage_class;
+        self
+    }
+
+    pub fn with_part_sizing(mut self, part_sizing: PartSizing) -> Self {
+        self.part_sizing = part_sizing;
+        self
+    }
+
+    pub fn with_checksum(mut self, checksum: ChecksumAlgorithm) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Work around S3 compatible servers which reject ACL headers or
+    /// require the payload hash header on presigned requests
+    pub fn with_quirks(mut self, disable_acl: bool, unsigned_payload: bool) -> Self {
+        self.disable_acl = disable_acl;
+        self.unsigned_payload = unsigned_payload;
+        self
+    }
+
+    pub fn with_access_mode(mut self, access_mode: AccessMode, presign_expiry: Duration) -> Self {
+        self.access_mode = access_mode;
+        self.presign_expiry = presign_expiry;
+        self
+    }
+
+    pub fn with_key_template(mut self, key_template: Option<String>, key_prefix: Option<String>) -> Self {
+        self.key_template = key_template;
+        self.key_prefix = key_prefix;
+        self
+    }
+
+    pub fn with_url_template(mut self, url_template: Option<String>) -> Self {
+        self.url_template = url_template;
+        self
+    }
+
+    pub fn with_max_concurrent_parts(mut self, max_concurrent_parts: usize) -> Self {
+        // at least one part has to be in flight for the upload to make progress
+        self.max_concurrent_parts = max_concurrent_parts.max(1);
+        self
+    }
 }
 
 #[derive(Debug)]
 pub struct InProgressUpload {
+    /// id of the `multipart_uploads` row tracking this upload
+    pub record_id: i64,
     pub multipart_id: String,
     pub obj_name: String,
     pub parts_counter: u16,
-    pub etags: Vec<String>,
+    /// completed parts keyed by part number
+    pub parts: BTreeMap<u16, PendingPart>,
+    pub checksum: ChecksumAlgorithm,
+    in_flight: JoinSet<Result<PendingPart, AnyhowError>>,
     pub buffer: BytesMut,
     pub total_size: usize,
+    pool: SqlitePool,
+    progress: ProgressTracker,
 }
 
 impl InProgressUpload {
+    /// Report the progress of a resumed upload on `tx`, parts which were
+    /// stored before it was interrupted count as sent
+    pub fn track(&mut self, tx: Option<Sender<UploadEvent>>) {
+        self.progress = ProgressTracker::resumed(tx, self.total_size as u64);
+    }
+
+    /// Rebuild an upload from the state persisted in the database, so that
+    /// it can be continued or completed after the app was restarted
+    pub async fn resume(record: PendingMultipart, pool: SqlitePool) -> Result<Self, AnyhowError> {
+        let parts = PendingMultipart::parts(&record, &pool).await?;
+        let parts_counter = parts
+            .last()
+            .map(|p| p.part_number as u16 + 1)
+            .unwrap_or(1);
+        let total_size = parts.iter().map(|p| p.size as usize).sum();
+        Ok(Self {
+            record_id: record.id(),
+            multipart_id: record.multipart_id,
+            obj_name: record.obj_name,
+            parts_counter,
+            checksum: record.checksum_algorithm,
+            parts: parts
+                .into_iter()
+                .map(|p| (p.part_number as u16, p))
+                .collect(),
+            in_flight: JoinSet::new(),
+            buffer: BytesMut::with_capacity(6 * 1024 * 1024),
+            total_size,
+            pool,
+            progress: ProgressTracker::resumed(None, total_size as u64),
+        })
+    }
+
+    /// Drop the parts which come after a gap so the upload can continue
+    /// reading its source. Parts are sent concurrently, an interruption can
+    /// leave later parts stored without the ones before them. Returns how
+    /// many bytes from the start of the source are stored.
+    pub async fn rewind(&mut self) -> Result<u64, AnyhowError> {
+        let in_order = self
+            .parts
+            .keys()
+            .zip(1u16..)
+            .take_while(|(number, expected)| **number == *expected)
+            .count() as u16;
+        self.parts.split_off(&(in_order + 1));
+        PendingMultipart::forget_parts_after(self.record_id, in_order.into(), &self.pool).await?;
+        self.parts_counter = in_order + 1;
+        self.total_size = self.parts.values().map(|p| p.size as usize).sum();
+        Ok(self.total_size as u64)
+    }
 
-    fn sign_part(&mut self, config: &S3Config) -> Url {
-        let part_upload = UploadPart::new(
+    fn sign_part(&mut self, config: &S3Config, headers: &[(String, String)]) -> Url {
+        // part numbers are checked against MAX_PARTS before a part is signed
+        // so the counter never gets anywhere near overflowing
+        let mut part_upload = UploadPart::new(
             &config.bucket,
             Some(&config.credentials),
             &self.obj_name,
             self.parts_counter,
             &self.multipart_id,
         );
+        for (name, value) in headers {
+            part_upload.headers_mut().insert(name.as_str(), value.as_str());
+        }
         let out = part_upload.sign(Duration::from_secs(3600));
         self.parts_counter += 1;
         out
     }
 
     fn write_slice(&mut self, slice: &[u8]) {
+        self.progress.add_total(slice.len() as u64);
         self.buffer.extend_from_slice(slice);
     }
 
+    /// Start uploading the buffer as the next part. Parts are uploaded in the
+    /// background, once `max_concurrent_parts` requests are in flight this
+    /// waits for one of them to finish before starting another.
     async fn upload_current_parts(
         &mut self,
         config: &S3Config,
         client: &Client,
     ) -> Result<(), AnyhowError> {
-        let url = self.sign_part(config);
+        if self.parts_counter > MAX_PARTS {
+            return Err(anyhow::anyhow!(
+                "{} needs more than {} parts, raise the maximum part size",
+                self.obj_name,
+                MAX_PARTS
+            )
+            .into());
+        }
+        while self.in_flight.len() >= config.max_concurrent_parts() {
+            self.join_next_part().await?;
+        }
+
+        let part_number = self.parts_counter;
         let bytes = self.buffer.split().freeze();
-        let len = bytes.len();
-        let res = client
-            .put(url)
-            .body(bytes)
-            .send()
-            .await?
-            .error_for_status()?;
-
-        dbg!(&res.status());
-
-        let etag = res
-            .headers()
-            .get(ETAG)
-            .expect("every UploadPart request returns an Etag");
-        self.etags.push(
-            etag.to_str()
+        let checksum = self.checksum.checksum(&bytes);
+        let mut headers = config.part_headers();
+        headers.extend(self.checksum.checksum_headers(checksum.as_deref()));
+        let url = self.sign_part(config, &headers);
+        let body = self.progress.body(bytes, Some(part_number));
+        let client = client.clone();
+        let pool = self.pool.clone();
+        let record_id = self.record_id;
+        self.in_flight.spawn(async move {
+            let len = body.content_length();
+            let res = RetryPolicy::default()
+                .send(|| {
+                    let request = client
+                        .put(url.clone())
+                        .header(CONTENT_LENGTH, len)
+                        .body(body.attempt());
+                    with_headers(request, &headers)
+                })
+                .await?;
+
+            let etag = res
+                .headers()
+                .get(ETAG)
+                .expect("every UploadPart request returns an Etag");
+            let etag = etag
+                .to_str()
                 .expect("Etag is always ascii")
-                .replace('\"', "")
-                .to_owned(),
-        );
-        self.total_size += len;
+                .replace('\"', "");
+            let part = PendingPart {
+                part_number: part_number.into(),
+                etag,
+                size: len as i64,
+                checksum,
+            };
+            PendingMultipart::record_part(record_id, &part, &pool).await?;
+            Ok::<PendingPart, AnyhowError>(part)
+        });
         Ok(())
     }
 
-    fn sign_complete_upload(&self, config: &S3Config) -> (Url, String) {
-        let etags = &self.etags;
-        let iter = etags.iter().map(AsRef::as_ref);
-        let action = CompleteMultipartUpload::new(
+    async fn join_next_part(&mut self) -> Result<(), AnyhowError> {
+        if let Some(res) = self.in_flight.join_next().await {
+            let part = res??;
+            self.total_size += part.size as usize;
+            self.parts.insert(part.part_number as u16, part);
+        }
+        Ok(())
+    }
+
+    /// Wait for every part which is still in flight
+    async fn wait_for_parts(&mut self) -> Result<(), AnyhowError> {
+        while !self.in_flight.is_empty() {
+            self.join_next_part().await?;
+        }
+        Ok(())
+    }
+
+    /// `headers` are signed into the request, S3 needs the SSE-C key on
+    /// Complete when the upload has a checksum algorithm
+    fn sign_complete_upload(
+        &self,
+        config: &S3Config,
+        headers: &[(String, String)],
+    ) -> (Url, String) {
+        // parts are keyed by part number so they are always listed in order
+        let iter = self.parts.values().map(|p| p.etag.as_str());
+        let mut action = CompleteMultipartUpload::new(
             &config.bucket,
             Some(&config.credentials),
             &self.obj_name,
             &self.multipart_id,
             iter,
         );
+        for (name, value) in headers {
+            action.headers_mut().insert(name.as_str(), value.as_str());
+        }
 
-        (action.sign(Duration::from_secs(3600)), action.body())
+        let body = match self.checksum.xml_tag() {
+            // rusty_s3 only lists etags, uploads created with a checksum
+            // algorithm have to list the checksum of every part as well
+            Some(tag) => {
+                let parts: String = self
+                    .parts
+                    .values()
+                    .map(|p| {
+                        format!(
+                            "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag><{tag}>{}</{tag}></Part>",
+                            p.part_number,
+                            p.etag,
+                            p.checksum.as_deref().unwrap_or_default(),
+                        )
+                    })
+                    .collect();
+                format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", parts)
+            }
+            None => action.body(),
+        };
+
+        (action.sign(Duration::from_secs(3600)), body)
+    }
+
+    /// Compare the checksum S3 computed for the whole object with the one
+    /// expected from the parts we sent
+    fn verify_complete_response(&self, body: &str) -> Result<(), AnyhowError> {
+        let (Some(tag), Some(expected)) = (
+            self.checksum.xml_tag(),
+            self.checksum
+                .composite(self.parts.values().filter_map(|p| p.checksum.as_deref())),
+        ) else {
+            return Ok(());
+        };
+        let open = format!("<{}>", tag);
+        let reported = body
+            .find(&open)
+            .map(|start| &body[start + open.len()..])
+            .and_then(|rest| rest.find('<').map(|end| &rest[..end]));
+        match reported {
+            // some S3 compatible servers don't report checksums at all
+            None => Ok(()),
+            Some(reported) if reported == expected => Ok(()),
+            Some(reported) => Err(anyhow::anyhow!(
+                "checksum mismatch for {}, expected {} but the bucket has {}",
+                self.obj_name,
+                expected,
+                reported
+            )
+            .into()),
+        }
     }
 }
 
 pub struct InProgressUploadBuilder {
     pub obj_name: String,
-}
-
-#[derive(Debug)]
-pub struct InProgressUploadNotifier {
-    upload: InProgressUpload,
-    tx: Sender<UploadEvent>,
-}
-
-impl InProgressUploadNotifier {
-    async fn wrap<T: Debug>(&self, res: Result<T, AnyhowError>, success_event: UploadEvent) -> Result<T, AnyhowError> {
-        Self::wrapper(&self.tx, res, success_event).await
-    }
-    async fn wrapper<T: Debug>(tx: &Sender<UploadEvent>, res: Result<T, AnyhowError>, success_event: UploadEvent) -> Result<T, AnyhowError> {
-        dbg!(&res);
-        match res {
-            Ok(t) => {
-                let _ = tx.send(success_event).await;
-                Ok(t)
-            },
-            Err(e) => {
-                let _ = tx.send(UploadEvent::Failed).await;
-                Err(e)
-            }
-        }
-    }
+    pub mime: Mime,
+    pub pool: SqlitePool,
+    pub progress: ProgressTracker,
+    /// headers only this upload is created with, on top of the ones the
+    /// config asks for
+    pub headers: Vec<(String, String)>,
+    /// the file the upload is read from, see `NewObject::source`
+    pub source: Option<PathBuf>,
 }
 
 #[derive(Debug)]
 pub struct CompletedData {
     pub upload_url: Url,
+    pub obj_key: String,
+    pub config_id: i64,
+    /// requesting this deletes the object, only custom uploaders have one
+    pub deletion_url: Option<String>,
+    /// copies made to other configs, see `ReplicatedBackend`
+    pub replicas: Vec<Replica>,
 }
 
 #[async_trait::async_trait]
@@ -167,6 +680,7 @@ pub trait Uploader<B>: Sized {
         config: &S3Config,
         client: &Client,
     ) -> Result<CompletedData, AnyhowError>;
+    async fn abort(&mut self, config: &S3Config, client: &Client) -> Result<(), AnyhowError>;
 }
 
 #[async_trait::async_trait]
@@ -174,33 +688,60 @@ impl Uploader<InProgressUploadBuilder> for InProgressUpload {
     async fn new(
         InProgressUploadBuilder {
             obj_name,
+            mime,
+            pool,
+            progress,
+            headers,
+            source,
         }: InProgressUploadBuilder,
         config: &S3Config,
         client: &Client
     ) -> Result<InProgressUpload, AnyhowError> {
+        let checksum = config.checksum();
+        let mut object_headers = config.object_headers();
+        object_headers.push(("content-type".to_owned(), mime.essence_str().to_owned()));
+        object_headers.extend(checksum.create_headers());
+        object_headers.extend(headers);
         let mut action =
             CreateMultipartUpload::new(&config.bucket, Some(&config.credentials), &obj_name);
         let headers = action.headers_mut();
-        headers.insert("x-amz-acl", "public-read");
+        for (name, value) in &object_headers {
+            headers.insert(name.as_str(), value.as_str());
+        }
 
         let url = action.sign(Duration::from_secs(3600));
-        let resp = client
-            .post(url)
-            .header("x-amz-acl", "public-read")
-            .send()
-            .await?
-            .error_for_status()?;
-
-        let body = resp.text().await?;
+        let body = RetryPolicy::default()
+            .send_text(|| with_headers(client.post(url.clone()), &object_headers))
+            .await?;
 
         let multipart = CreateMultipartUpload::parse_response(&body)?;
+        let multipart_id = multipart.upload_id().to_owned();
+        let record = PendingMultipart::create(
+            PendingMultipartBuilder {
+                config_id: config.id(),
+                multipart_id: multipart_id.clone(),
+                obj_name: obj_name.clone(),
+                mime,
+                checksum_algorithm: checksum,
+                // a path which isn't valid unicode can't be stored, the upload
+                // can still be finalized or discarded if it is interrupted
+                source_path: source.and_then(|p| p.into_os_string().into_string().ok()),
+            },
+            &pool,
+        )
+        .await?;
         Ok(Self {
+            record_id: record.id(),
             obj_name,
-            multipart_id: multipart.upload_id().to_owned(),
-            etags: Vec::new(),
+            multipart_id,
+            parts: BTreeMap::new(),
+            checksum,
+            in_flight: JoinSet::new(),
             parts_counter: 1,
             buffer: BytesMut::with_capacity(6 * 1024 * 1024),
             total_size: 0,
+            pool,
+            progress,
         })
     }
     async fn upload_part(
@@ -210,7 +751,22 @@ impl Uploader<InProgressUploadBuilder> for InProgressUpload {
         client: &Client,
     ) -> Result<(), AnyhowError> {
         self.write_slice(slice);
-        if self.buffer.len() < 5 * 1024 * 1024 {
+        let sizing = config.part_sizing();
+        let threshold = sizing.threshold(self.parts_counter);
+        if self.parts_counter >= MAX_PARTS {
+            // the last part number is kept for everything that is left, which
+            // is sent when the upload is completed
+            if self.buffer.len() > threshold {
+                return Err(anyhow::anyhow!(
+                    "{} is too large to fit in {} parts, raise the maximum part size",
+                    self.obj_name,
+                    MAX_PARTS,
+                )
+                .into());
+            }
+            return Ok(());
+        }
+        if self.buffer.len() < threshold {
             return Ok(());
         }
         self.upload_current_parts(config, client).await
@@ -223,65 +779,40 @@ impl Uploader<InProgressUploadBuilder> for InProgressUpload {
         client: &Client,
     ) -> Result<CompletedData, AnyhowError> {
         self.write_slice(slice);
-        self.upload_current_parts(config, client).await?;
-        let (url, body) = self.sign_complete_upload(config);
-        client
-            .post(url)
-            .body(body)
-            .send()
-            .await?
-            .error_for_status()?;
-        let u = config.bucket.object_url(&self.obj_name);
-        dbg!(&u);
-        match u {
-            Ok(u) => Ok(CompletedData { upload_url: u }),
-            Err(_) => Err(anyhow::anyhow!("Failed to parse").into()),
+        // a resumed upload may have nothing left in the buffer, in which case
+        // the parts which are already stored are completed as they are
+        if !self.buffer.is_empty() || (self.parts.is_empty() && self.in_flight.is_empty()) {
+            self.upload_current_parts(config, client).await?;
         }
-    }
-}
-
-pub struct InProgressUploadNotifierBuilder {
-    pub config: InProgressUploadBuilder,
-    pub tx: Sender<UploadEvent>,
-}
-
-#[async_trait::async_trait]
-impl Uploader<InProgressUploadNotifierBuilder> for InProgressUploadNotifier {
-    async fn new(InProgressUploadNotifierBuilder { tx, config }: InProgressUploadNotifierBuilder, s3_config: &S3Config, client: &Client) -> Result<Self, AnyhowError> {
-        let upload = Self::wrapper(&tx, InProgressUpload::new(config, s3_config, client).await, UploadEvent::Started).await?;
-        Ok(Self {
-            tx,
-            upload
+        self.wait_for_parts().await?;
+        let headers = config.read_headers();
+        let (url, body) = self.sign_complete_upload(config, &headers);
+        let response = RetryPolicy::default()
+            .send_text(|| with_headers(client.post(url.clone()).body(body.clone()), &headers))
+            .await?;
+        PendingMultipart::delete(self.record_id, &self.pool).await?;
+        if let Err(e) = self.verify_complete_response(&response) {
+            let _ = delete_object(config, client, &self.obj_name).await;
+            return Err(e);
+        }
+        verify_size(config, client, &self.obj_name, self.total_size as u64).await?;
+        let upload_url = config.public_url(&self.obj_name)?;
+        Ok(CompletedData {
+            upload_url,
+            obj_key: self.obj_name.clone(),
+            config_id: config.id(),
+            deletion_url: None,
+            replicas: Vec::new(),
         })
     }
 
-    async fn upload_part(
-        &mut self,
-        slice: &[u8],
-        config: &S3Config,
-        client: &Client,
-    ) -> Result<(), AnyhowError> {
-        let res = self.upload.upload_part(slice, config, client).await;
-        self.wrap(res, UploadEvent::Progress).await
-    }
-
-    async fn complete_upload(
-        &mut self,
-        slice: &[u8],
-        config: &S3Config,
-        client: &Client,
-    ) -> Result<CompletedData, AnyhowError> {
-        let res = self.upload.complete_upload(slice, config, client).await;
-        self.wrap(res, UploadEvent::Done).await
+    async fn abort(&mut self, config: &S3Config, client: &Client) -> Result<(), AnyhowError> {
+        // parts which are still in flight would otherwise be stored after the
+        // abort and be billed again
+        self.in_flight.shutdown().await;
+        self.buffer.clear();
+        abort_multipart(config, client, &self.obj_name, &self.multipart_id).await?;
+        PendingMultipart::delete(self.record_id, &self.pool).await?;
+        Ok(())
     }
-
 }
-
-
-
-
-
-
-
-
-
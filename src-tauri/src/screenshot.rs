@@ -9,12 +9,11 @@ use tauri::{
 };
 use tauri_plugin_global_shortcut::{Code, GlobalShortcutExt, Modifiers, Shortcut};
 use tokio::sync::RwLock;
-use uuid::Uuid;
 
 use crate::{
-    db::{crud::{Create, Upload, UploadBuilder}, plugin::DatabaseExt},
     error::AnyhowError,
-    s3::plugin::UploadManagerExt,
+    s3::{jobs::QueuedObject, metadata::ObjectMetadata, plugin::UploadManagerExt},
+    storage::keys::ObjectKind,
     rect::{Point, Rect},
     window_config::WindowLabel,
 };
@@ -118,21 +117,21 @@ impl<R: Runtime> ScreenshotManager<R> {
                     let mut writer = Cursor::new(Vec::with_capacity(buf.len()));
                     buf.write_to(&mut writer, ImageOutputFormat::Png)?;
                     let mime = IMAGE_PNG;
-                    let url = app
+                    // the key is picked once the job runs, if the upload
+                    // fails the capture is spooled and retried later
+                    let object = QueuedObject {
+                        key: None,
+                        mime,
+                        kind: ObjectKind::Screenshot,
+                        metadata: ObjectMetadata::default(),
+                    };
+                    let job = app
                         .upload_manager()
                         .read()
                         .await
-                        .new_upload(
-                            format!("{}.png", Uuid::new_v4()),
-                            writer.into_inner(),
-                            &mime,
-                        )
-                        .await?
-                        .upload_url;
-
-                    let pool = app.database();
-                    let builder = UploadBuilder { url, mime };
-                    Upload::create(builder, pool).await?;
+                        .new_upload(object, writer.into_inner())?;
+                    // the job records the upload once it is sent
+                    job.finished().await?;
 
                     // complete the loading state
                     app.state::<ScreenshotManagerLock<R>>()
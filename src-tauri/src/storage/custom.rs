@@ -0,0 +1,456 @@
+use std::collections::BTreeMap;
+
+use bytes::{Bytes, BytesMut};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use tauri_plugin_http::reqwest::{
+    header::HeaderMap,
+    multipart::{Form, Part},
+    Client, Method, Url,
+};
+
+use super::{NewObject, StorageBackend, UploadSession};
+use crate::{
+    db::crud::S3ConfigRaw,
+    error::AnyhowError,
+    s3::{progress::ProgressTracker, retry::RetryPolicy, uploader::CompletedData},
+    template,
+};
+
+/// Placeholders for the request, the name of the uploaded file
+pub const REQUEST_PLACEHOLDERS: &[&str] = &["filename"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RequestBody {
+    /// the file is sent as the `file_form_name` field of a form, along with
+    /// the `arguments`
+    #[default]
+    MultipartFormData,
+    /// the file is the whole request body
+    Binary,
+}
+
+/// An upload service described the way ShareX describes custom uploaders,
+/// `.sxcu` files deserialize into this as they are. The `url` and
+/// `deletion_url` are templates over the response:
+///
+/// - `{response}` the whole response body
+/// - `{json:files[0].url}` a value from a JSON response
+/// - `{regex:1}` or `{regex:1|2}` the match, or a group of it, of the first
+///   entry in `regex_list`
+/// - `{header:Location}` a response header
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CustomUploader {
+    #[serde(alias = "Name")]
+    pub name: String,
+    #[serde(alias = "RequestMethod")]
+    pub request_method: String,
+    #[serde(alias = "RequestURL")]
+    pub request_url: String,
+    /// query string of the request
+    #[serde(alias = "Parameters")]
+    pub parameters: BTreeMap<String, String>,
+    #[serde(alias = "Headers")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(alias = "Body")]
+    pub body: RequestBody,
+    /// extra form fields
+    #[serde(alias = "Arguments")]
+    pub arguments: BTreeMap<String, String>,
+    #[serde(alias = "FileFormName")]
+    pub file_form_name: String,
+    #[serde(alias = "RegexList")]
+    pub regex_list: Vec<String>,
+    #[serde(alias = "URL")]
+    pub url: String,
+    /// requested with GET when the upload is deleted, empty if the service
+    /// has no way to delete uploads
+    #[serde(alias = "DeletionURL")]
+    pub deletion_url: String,
+}
+
+impl Default for CustomUploader {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            request_method: "POST".to_owned(),
+            request_url: String::new(),
+            parameters: BTreeMap::new(),
+            headers: BTreeMap::new(),
+            body: RequestBody::default(),
+            arguments: BTreeMap::new(),
+            file_form_name: "file".to_owned(),
+            regex_list: Vec::new(),
+            url: "{response}".to_owned(),
+            deletion_url: String::new(),
+        }
+    }
+}
+
+impl CustomUploader {
+    /// Parse a definition, either one saved by this app or a ShareX `.sxcu`
+    /// file
+    pub fn parse(definition: &str) -> anyhow::Result<Self> {
+        let uploader: Self = serde_json::from_str(definition)?;
+        uploader.validate()?;
+        Ok(uploader)
+    }
+
+    fn method(&self) -> anyhow::Result<Method> {
+        match self.request_method.to_ascii_uppercase().as_str() {
+            "POST" => Ok(Method::POST),
+            "PUT" => Ok(Method::PUT),
+            "PATCH" => Ok(Method::PATCH),
+            other => anyhow::bail!("Files can't be uploaded with {}", other),
+        }
+    }
+
+    fn regexes(&self) -> anyhow::Result<Vec<Regex>> {
+        Ok(self
+            .regex_list
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<_, _>>()?)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.method()?;
+        let regexes = self.regexes()?;
+        let request_templates = self
+            .parameters
+            .values()
+            .chain(self.headers.values())
+            .chain(self.arguments.values());
+        for template in request_templates {
+            template::validate(template, REQUEST_PLACEHOLDERS)
+                .map_err(|_| anyhow::anyhow!("Only {{filename}} can be used in {}", template))?;
+        }
+        Url::parse(&render_request(&self.request_url, "file")?)?;
+        if self.body == RequestBody::MultipartFormData && self.file_form_name.is_empty() {
+            anyhow::bail!("A form upload needs the name of the file field");
+        }
+        if self.url.is_empty() {
+            anyhow::bail!("Set how the url is read from the response");
+        }
+        for template in [&self.url, &self.deletion_url] {
+            template::render(template, |name| {
+                ResponsePlaceholder::parse(name, &regexes).map(|_| String::new())
+            })
+            .map_err(|_| anyhow::anyhow!("Unknown placeholder in {}", template))?;
+        }
+        Ok(())
+    }
+}
+
+fn render_request(template: &str, filename: &str) -> anyhow::Result<String> {
+    template::render(template, |name| {
+        (name == "filename").then(|| filename.to_owned())
+    })
+    .map_err(|_| anyhow::anyhow!("Only {{filename}} can be used in {}", template))
+}
+
+enum ResponsePlaceholder<'a> {
+    Response,
+    Json(&'a str),
+    Regex {
+        regex: &'a Regex,
+        group: Option<&'a str>,
+    },
+    Header(&'a str),
+}
+
+impl<'a> ResponsePlaceholder<'a> {
+    fn parse(name: &'a str, regexes: &'a [Regex]) -> Option<Self> {
+        if name == "response" {
+            return Some(Self::Response);
+        }
+        let (kind, arg) = name.split_once(':')?;
+        match kind {
+            "json" => Some(Self::Json(arg)),
+            "header" => Some(Self::Header(arg)),
+            "regex" => {
+                let (index, group) = match arg.split_once('|') {
+                    Some((index, group)) => (index, Some(group)),
+                    None => (arg, None),
+                };
+                // indexes start at 1 like they do in ShareX
+                let regex = regexes.get(index.parse::<usize>().ok()?.checked_sub(1)?)?;
+                Some(Self::Regex { regex, group })
+            }
+            _ => None,
+        }
+    }
+
+    fn resolve(&self, body: &str, json: Option<&Value>, headers: &HeaderMap) -> Option<String> {
+        match self {
+            Self::Response => Some(body.trim().to_owned()),
+            Self::Json(path) => json_path(json?, path).map(|value| match value {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            }),
+            Self::Regex { regex, group } => {
+                let captures = regex.captures(body)?;
+                let found = match group {
+                    None => captures.get(0),
+                    Some(group) => match group.parse::<usize>() {
+                        Ok(i) => captures.get(i),
+                        Err(_) => captures.name(group),
+                    },
+                };
+                found.map(|m| m.as_str().to_owned())
+            }
+            Self::Header(name) => headers.get(*name)?.to_str().ok().map(ToOwned::to_owned),
+        }
+    }
+}
+
+/// A small subset of JSONPath, `$.files[0].url` or `files[0]["url"]`
+fn json_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    let path = path.trim_start_matches('$').trim_start_matches('.');
+    let mut current = value;
+    for segment in path.split('.').filter(|s| !s.is_empty()) {
+        let (name, mut indexes) = segment.split_at(segment.find('[').unwrap_or(segment.len()));
+        if !name.is_empty() {
+            current = current.get(name)?;
+        }
+        while let Some(rest) = indexes.strip_prefix('[') {
+            let end = rest.find(']')?;
+            let index = &rest[..end];
+            current = match index.parse::<usize>() {
+                Ok(i) => current.get(i)?,
+                Err(_) => current.get(index.trim_matches(|c| c == '"' || c == '\''))?,
+            };
+            indexes = &rest[end + 1..];
+        }
+    }
+    Some(current)
+}
+
+/// Uploads to any HTTP service described by a `CustomUploader`. The
+/// deletion url is stored as the object key, since that is all that is
+/// needed to delete the upload later.
+#[derive(Debug, Clone)]
+pub struct CustomBackend {
+    id: i64,
+    client: Client,
+    uploader: CustomUploader,
+    method: Method,
+    regexes: Vec<Regex>,
+    key_template: Option<String>,
+    key_prefix: Option<String>,
+}
+
+impl CustomBackend {
+    pub fn from_config(config: S3ConfigRaw, client: &Client) -> anyhow::Result<Self> {
+        let (id, fields) = config.into_parts();
+        let non_empty = |s: Option<String>| s.filter(|s| !s.is_empty());
+        let uploader = fields
+            .custom
+            .ok_or_else(|| anyhow::anyhow!("No custom uploader for this config"))?
+            .definition
+            .0;
+        uploader.validate()?;
+        Ok(Self {
+            id,
+            client: client.clone(),
+            method: uploader.method()?,
+            regexes: uploader.regexes()?,
+            uploader,
+            key_template: non_empty(fields.key_template),
+            key_prefix: non_empty(fields.key_prefix),
+        })
+    }
+
+    fn render_response(
+        &self,
+        template: &str,
+        body: &str,
+        headers: &HeaderMap,
+    ) -> Result<String, AnyhowError> {
+        let json = serde_json::from_str::<Value>(body).ok();
+        template::render(template, |name| {
+            ResponsePlaceholder::parse(name, &self.regexes)?.resolve(body, json.as_ref(), headers)
+        })
+        .map_err(|_| {
+            let excerpt: String = body.chars().take(200).collect();
+            anyhow::anyhow!("{} didn't match the response: {}", template, excerpt).into()
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for CustomBackend {
+    fn config_id(&self) -> i64 {
+        self.id
+    }
+
+    fn key_template(&self) -> Option<&str> {
+        self.key_template.as_deref()
+    }
+
+    fn key_prefix(&self) -> Option<&str> {
+        self.key_prefix.as_deref()
+    }
+
+    /// The service names uploads itself, keys only pick the filename
+    async fn exists(&self, _key: &str) -> Result<bool, AnyhowError> {
+        Ok(false)
+    }
+
+    async fn put(
+        &self,
+        object: NewObject,
+        bytes: Bytes,
+        progress: ProgressTracker,
+    ) -> Result<CompletedData, AnyhowError> {
+        let uploader = &self.uploader;
+        let filename = object.key.rsplit('/').next().unwrap_or(&object.key);
+        let mime = object.mime.essence_str();
+        let url = Url::parse(&render_request(&uploader.request_url, filename)?)?;
+        let render = |values: &BTreeMap<String, String>| {
+            values
+                .iter()
+                .map(|(name, value)| Ok((name.clone(), render_request(value, filename)?)))
+                .collect::<anyhow::Result<Vec<_>>>()
+        };
+        let parameters = render(&uploader.parameters)?;
+        let headers = render(&uploader.headers)?;
+        let arguments = render(&uploader.arguments)?;
+
+        progress.add_total(bytes.len() as u64);
+        let body = progress.body(bytes, None);
+        let res = RetryPolicy::default()
+            .send(|| {
+                let request = headers.iter().fold(
+                    self.client
+                        .request(self.method.clone(), url.clone())
+                        .query(&parameters),
+                    |request, (name, value)| request.header(name, value),
+                );
+                match uploader.body {
+                    RequestBody::MultipartFormData => {
+                        let file = Part::stream_with_length(body.attempt(), body.content_length())
+                            .file_name(filename.to_owned())
+                            .mime_str(mime)
+                            .expect("a parsed mime type is valid");
+                        let form = arguments
+                            .iter()
+                            .fold(Form::new(), |form, (name, value)| {
+                                form.text(name.clone(), value.clone())
+                            })
+                            .part(uploader.file_form_name.clone(), file);
+                        request.multipart(form)
+                    }
+                    RequestBody::Binary => request
+                        .header(CONTENT_TYPE, mime)
+                        .header(CONTENT_LENGTH, body.content_length())
+                        .body(body.attempt()),
+                }
+            })
+            .await?;
+
+        let response_headers = res.headers().clone();
+        let text = res.text().await?;
+        let upload_url = self.render_response(&uploader.url, &text, &response_headers)?;
+        let deletion_url = match uploader.deletion_url.as_str() {
+            "" => None,
+            template => Some(self.render_response(template, &text, &response_headers)?),
+        };
+        Ok(CompletedData {
+            upload_url: Url::parse(&upload_url)?,
+            obj_key: object.key,
+            config_id: self.id,
+            deletion_url,
+            replicas: Vec::new(),
+        })
+    }
+
+    /// Nothing can be sent until the recording is complete, it is held in
+    /// memory until then
+    async fn begin_upload(
+        &self,
+        object: NewObject,
+        progress: ProgressTracker,
+    ) -> Result<Box<dyn UploadSession>, AnyhowError> {
+        Ok(Box::new(BufferedUpload {
+            backend: self.clone(),
+            object,
+            buffer: BytesMut::new(),
+            progress,
+        }))
+    }
+
+    /// The service decides where uploads go, they can only be deleted
+    /// through the deletion url of their response
+    async fn delete(&self, _key: &str) -> Result<(), AnyhowError> {
+        Err(anyhow::anyhow!(
+            "Uploads to {} can only be deleted with a deletion url",
+            self.uploader.name
+        )
+        .into())
+    }
+
+    /// Without a deletion url there is no way to delete the upload, only
+    /// its history entry goes
+    async fn delete_upload(
+        &self,
+        _key: &str,
+        deletion_url: Option<&str>,
+    ) -> Result<(), AnyhowError> {
+        let Some(deletion_url) = deletion_url else {
+            return Ok(());
+        };
+        let url = Url::parse(deletion_url)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+            .ok_or_else(|| anyhow::anyhow!("{} is not a deletion url", deletion_url))?;
+        match RetryPolicy::default()
+            .send(|| self.client.get(url.clone()))
+            .await
+        {
+            Err(e) if e.status != Some(404) => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn public_url(&self, _key: &str) -> Result<Url, AnyhowError> {
+        Err(anyhow::anyhow!(
+            "Links from {} are only known from the upload response",
+            self.uploader.name
+        )
+        .into())
+    }
+}
+
+/// A recording held in memory until it can be sent in one request
+#[derive(Debug)]
+pub struct BufferedUpload {
+    backend: CustomBackend,
+    object: NewObject,
+    buffer: BytesMut,
+    progress: ProgressTracker,
+}
+
+#[async_trait::async_trait]
+impl UploadSession for BufferedUpload {
+    async fn write(&mut self, slice: &[u8]) -> Result<(), AnyhowError> {
+        self.buffer.extend_from_slice(slice);
+        Ok(())
+    }
+
+    async fn complete(&mut self, slice: &[u8]) -> Result<CompletedData, AnyhowError> {
+        self.buffer.extend_from_slice(slice);
+        let bytes = self.buffer.split().freeze();
+        self.backend
+            .put(self.object.clone(), bytes, self.progress.clone())
+            .await
+    }
+
+    async fn abort(&mut self) -> Result<(), AnyhowError> {
+        self.buffer.clear();
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+use std::path::Path;
+
+use mime::Mime;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::StorageBackend;
+use crate::{error::AnyhowError, template};
+
+pub const KEY_TEMPLATE_PLACEHOLDERS: &[&str] = &[
+    "yyyy", "mm", "dd", "uuid", "shortid", "hostname", "kind", "ext",
+];
+
+/// Keeps the old behaviour of naming objects after a random uuid
+pub const DEFAULT_KEY_TEMPLATE: &str = "{uuid}.{ext}";
+
+/// How many keys `unique_key` renders before giving up because each of them
+/// was taken
+const COLLISION_ATTEMPTS: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum ObjectKind {
+    Recording,
+    Screenshot,
+    /// a file uploaded from disk
+    File,
+}
+
+impl ObjectKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Recording => "recording",
+            Self::Screenshot => "screenshot",
+            Self::File => "file",
+        }
+    }
+}
+
+fn short_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(8)
+        .map(char::from)
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// The file extension an object is stored with. Files keep the extension
+/// of their original `filename`, captures get one for their mime type.
+fn extension<'a>(mime: &'a Mime, filename: Option<&'a str>) -> &'a str {
+    let original = filename
+        .and_then(|name| Path::new(name).extension())
+        .and_then(|ext| ext.to_str())
+        .filter(|ext| !ext.is_empty() && ext.chars().all(|c| c.is_ascii_alphanumeric()));
+    if let Some(ext) = original {
+        return ext;
+    }
+    match mime.subtype().as_str() {
+        "jpeg" => "jpg",
+        "plain" => "txt",
+        "octet-stream" => "bin",
+        "quicktime" => "mov",
+        "x-matroska" => "mkv",
+        ext => ext.strip_prefix("x-").unwrap_or(ext),
+    }
+}
+
+/// Render the object key for a new upload from the key template and prefix
+/// of `backend`, `filename` is the original name of a file from disk
+pub fn render_key(
+    backend: &dyn StorageBackend,
+    kind: ObjectKind,
+    mime: &Mime,
+    filename: Option<&str>,
+) -> Result<String, AnyhowError> {
+    let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+    let ext = extension(mime, filename);
+    let key = template::render(
+        backend.key_template().unwrap_or(DEFAULT_KEY_TEMPLATE),
+        |name| match name {
+            "yyyy" => Some(format!("{:04}", now.year())),
+            "mm" => Some(format!("{:02}", u8::from(now.month()))),
+            "dd" => Some(format!("{:02}", now.day())),
+            "uuid" => Some(Uuid::new_v4().to_string()),
+            "shortid" => Some(short_id()),
+            "hostname" => Some(gethostname::gethostname().to_string_lossy().into_owned()),
+            "kind" => Some(kind.as_str().to_owned()),
+            "ext" => Some(ext.to_owned()),
+            _ => None,
+        },
+    )?;
+    let key = key.trim_start_matches('/');
+    Ok(match backend.key_prefix() {
+        Some(prefix) => format!("{}/{}", prefix.trim_matches('/'), key),
+        None => key.to_owned(),
+    })
+}
+
+/// Render a key which isn't taken yet, so an upload never overwrites an
+/// existing object
+pub async fn unique_key(
+    backend: &dyn StorageBackend,
+    kind: ObjectKind,
+    mime: &Mime,
+    filename: Option<&str>,
+) -> Result<String, AnyhowError> {
+    for _ in 0..COLLISION_ATTEMPTS {
+        let key = render_key(backend, kind, mime, filename)?;
+        if !backend.exists(&key).await? {
+            return Ok(key);
+        }
+    }
+    Err(anyhow::anyhow!(
+        "Every key rendered from the key template is already taken, add {{uuid}} or {{shortid}} to it"
+    )
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_keep_their_extension() {
+        let mime: Mime = "application/vnd.ms-excel".parse().unwrap();
+        assert_eq!(extension(&mime, Some("report.xls")), "xls");
+        assert_eq!(extension(&mime::IMAGE_SVG, Some("logo.svg")), "svg");
+        assert_eq!(extension(&mime::TEXT_PLAIN, Some("notes.tar.gz")), "gz");
+    }
+
+    #[test]
+    fn captures_get_an_extension_for_their_mime_type() {
+        assert_eq!(extension(&mime::IMAGE_JPEG, None), "jpg");
+        assert_eq!(extension(&"video/webm".parse().unwrap(), None), "webm");
+        assert_eq!(extension(&mime::TEXT_PLAIN, Some("README")), "txt");
+        assert_eq!(extension(&mime::TEXT_PLAIN, Some("odd.t x")), "txt");
+    }
+}
@@ -0,0 +1,398 @@
+use std::path::{Component, Path, PathBuf};
+
+use bytes::Bytes;
+use tauri_plugin_http::reqwest::Url;
+use tokio::{
+    fs::{self, File},
+    io::AsyncWriteExt,
+};
+
+use super::{NewObject, StorageBackend, UploadSession};
+use crate::{
+    db::crud::S3ConfigRaw,
+    error::AnyhowError,
+    s3::{
+        progress::ProgressTracker,
+        uploader::{encode_key, CompletedData},
+    },
+    template,
+};
+
+/// Stores objects as files below a directory, for working offline or for a
+/// directory which is served or synced by something else
+#[derive(Debug, Clone)]
+pub struct LocalBackend {
+    id: i64,
+    root: PathBuf,
+    /// objects are linked as `{base_url}/{key}` instead of `file://` urls
+    base_url: Option<String>,
+    url_template: Option<String>,
+    key_template: Option<String>,
+    key_prefix: Option<String>,
+}
+
+impl LocalBackend {
+    pub fn from_config(config: S3ConfigRaw) -> anyhow::Result<Self> {
+        let (id, fields) = config.into_parts();
+        let non_empty = |s: Option<String>| s.filter(|s| !s.is_empty());
+        let root = non_empty(fields.local_root)
+            .map(PathBuf::from)
+            .ok_or_else(|| anyhow::anyhow!("No directory set for local storage"))?;
+        if !root.is_absolute() {
+            anyhow::bail!("{} is not an absolute path", root.display());
+        }
+        Ok(Self {
+            id,
+            root,
+            base_url: non_empty(fields.host_rewrite),
+            url_template: non_empty(fields.url_template),
+            key_template: non_empty(fields.key_template),
+            key_prefix: non_empty(fields.key_prefix),
+        })
+    }
+
+    /// The file an object is stored in, keys can't point outside the root
+    fn path(&self, key: &str) -> Result<PathBuf, AnyhowError> {
+        let relative = Path::new(key);
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(anyhow::anyhow!("{} is not a valid object key", key).into());
+        }
+        Ok(self.root.join(relative))
+    }
+
+    /// Files are written next to their final path and only renamed into
+    /// place once complete, so a partial file is never linked to
+    fn partial_path(path: &Path) -> PathBuf {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        path.with_file_name(format!(".{}.part", name))
+    }
+
+    async fn create_partial(&self, key: &str) -> Result<(PathBuf, PathBuf, File), AnyhowError> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let partial = Self::partial_path(&path);
+        let file = File::create(&partial).await?;
+        Ok((path, partial, file))
+    }
+
+    async fn finish(
+        &self,
+        key: String,
+        path: &Path,
+        partial: &Path,
+        expected: u64,
+    ) -> Result<CompletedData, AnyhowError> {
+        let size = fs::metadata(partial).await?.len();
+        if size != expected {
+            let _ = fs::remove_file(partial).await;
+            return Err(anyhow::anyhow!(
+                "{} has {} bytes on disk but {} were written",
+                key,
+                size,
+                expected
+            )
+            .into());
+        }
+        fs::rename(partial, path).await?;
+        Ok(CompletedData {
+            upload_url: self.public_url(&key)?,
+            obj_key: key,
+            config_id: self.id,
+            deletion_url: None,
+            replicas: Vec::new(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for LocalBackend {
+    fn config_id(&self) -> i64 {
+        self.id
+    }
+
+    fn key_template(&self) -> Option<&str> {
+        self.key_template.as_deref()
+    }
+
+    fn key_prefix(&self) -> Option<&str> {
+        self.key_prefix.as_deref()
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, AnyhowError> {
+        Ok(fs::try_exists(self.path(key)?).await?)
+    }
+
+    async fn put(
+        &self,
+        object: NewObject,
+        bytes: Bytes,
+        progress: ProgressTracker,
+    ) -> Result<CompletedData, AnyhowError> {
+        let (path, partial, mut file) = self.create_partial(&object.key).await?;
+        let size = bytes.len() as u64;
+        progress.add_total(size);
+        file.write_all(&bytes).await?;
+        file.sync_all().await?;
+        progress.sent(size, None);
+        self.finish(object.key, &path, &partial, size).await
+    }
+
+    async fn begin_upload(
+        &self,
+        object: NewObject,
+        progress: ProgressTracker,
+    ) -> Result<Box<dyn UploadSession>, AnyhowError> {
+        let (path, partial, file) = self.create_partial(&object.key).await?;
+        Ok(Box::new(LocalSession {
+            backend: self.clone(),
+            key: object.key,
+            path,
+            partial,
+            file,
+            written: 0,
+            progress,
+        }))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), AnyhowError> {
+        match fs::remove_file(self.path(key)?).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn public_url(&self, key: &str) -> Result<Url, AnyhowError> {
+        if let Some(template) = &self.url_template {
+            let filename = key.rsplit('/').next().unwrap_or(key);
+            let url = template::render(template, |name| match name {
+                "key" => Some(encode_key(key)),
+                "filename" => Some(encode_key(filename)),
+                // there is no bucket or region for a directory
+                "bucket" | "region" => Some(String::new()),
+                _ => None,
+            })?;
+            return Ok(Url::parse(&url)?);
+        }
+        if let Some(base) = &self.base_url {
+            return Ok(Url::parse(&format!(
+                "{}/{}",
+                base.trim_end_matches('/'),
+                encode_key(key)
+            ))?);
+        }
+        let path = self.path(key)?;
+        Url::from_file_path(&path)
+            .map_err(|_| anyhow::anyhow!("Can't link to {}", path.display()).into())
+    }
+}
+
+/// A file which is written as the recording comes in
+#[derive(Debug)]
+pub struct LocalSession {
+    backend: LocalBackend,
+    key: String,
+    path: PathBuf,
+    partial: PathBuf,
+    file: File,
+    written: u64,
+    progress: ProgressTracker,
+}
+
+#[async_trait::async_trait]
+impl UploadSession for LocalSession {
+    async fn write(&mut self, slice: &[u8]) -> Result<(), AnyhowError> {
+        self.progress.add_total(slice.len() as u64);
+        self.file.write_all(slice).await?;
+        self.written += slice.len() as u64;
+        self.progress.sent(slice.len() as u64, None);
+        Ok(())
+    }
+
+    async fn complete(&mut self, slice: &[u8]) -> Result<CompletedData, AnyhowError> {
+        self.write(slice).await?;
+        self.file.sync_all().await?;
+        self.backend
+            .finish(self.key.clone(), &self.path, &self.partial, self.written)
+            .await
+    }
+
+    async fn abort(&mut self) -> Result<(), AnyhowError> {
+        match fs::remove_file(&self.partial).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        s3::metadata::ObjectMetadata,
+        storage::keys::{unique_key, ObjectKind},
+    };
+
+    /// A backend writing to a fresh directory which is removed on drop
+    struct TempBackend(LocalBackend);
+
+    impl TempBackend {
+        fn new(key_template: Option<&str>) -> Self {
+            let root = std::env::temp_dir().join(format!("boom-local-{}", Uuid::new_v4()));
+            std::fs::create_dir_all(&root).unwrap();
+            Self(LocalBackend {
+                id: 1,
+                root,
+                base_url: None,
+                url_template: None,
+                key_template: key_template.map(str::to_owned),
+                key_prefix: None,
+            })
+        }
+    }
+
+    impl Drop for TempBackend {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0.root);
+        }
+    }
+
+    fn object(key: &str) -> NewObject {
+        NewObject {
+            key: key.to_owned(),
+            mime: mime::TEXT_PLAIN,
+            kind: ObjectKind::File,
+            metadata: ObjectMetadata::default(),
+            source: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn put_writes_the_file() {
+        let backend = TempBackend::new(None);
+        let completed = backend
+            .0
+            .put(
+                object("a/b.txt"),
+                Bytes::from_static(b"hello"),
+                ProgressTracker::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(completed.obj_key, "a/b.txt");
+        assert_eq!(completed.config_id, 1);
+        let path = backend.0.root.join("a/b.txt");
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+        assert_eq!(completed.upload_url, Url::from_file_path(&path).unwrap());
+        assert!(!LocalBackend::partial_path(&path).exists());
+    }
+
+    #[tokio::test]
+    async fn session_renames_the_file_once_complete() {
+        let backend = TempBackend::new(None);
+        let mut session = backend
+            .0
+            .begin_upload(object("rec.mp4"), ProgressTracker::default())
+            .await
+            .unwrap();
+        session.write(b"hello ").await.unwrap();
+        let path = backend.0.root.join("rec.mp4");
+        assert!(!backend.0.exists("rec.mp4").await.unwrap());
+        assert!(LocalBackend::partial_path(&path).exists());
+
+        let completed = session.complete(b"world").await.unwrap();
+        assert_eq!(completed.obj_key, "rec.mp4");
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello world");
+        assert!(!LocalBackend::partial_path(&path).exists());
+    }
+
+    #[tokio::test]
+    async fn abort_removes_the_partial_file() {
+        let backend = TempBackend::new(None);
+        let mut session = backend
+            .0
+            .begin_upload(object("rec.mp4"), ProgressTracker::default())
+            .await
+            .unwrap();
+        session.write(b"hello").await.unwrap();
+        session.abort().await.unwrap();
+        let path = backend.0.root.join("rec.mp4");
+        assert!(!path.exists());
+        assert!(!LocalBackend::partial_path(&path).exists());
+        // aborting twice is not an error
+        session.abort().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn delete_and_exists() {
+        let backend = TempBackend::new(None);
+        assert!(!backend.0.exists("a.txt").await.unwrap());
+        backend
+            .0
+            .put(
+                object("a.txt"),
+                Bytes::from_static(b"a"),
+                ProgressTracker::default(),
+            )
+            .await
+            .unwrap();
+        assert!(backend.0.exists("a.txt").await.unwrap());
+        backend.0.delete("a.txt").await.unwrap();
+        assert!(!backend.0.exists("a.txt").await.unwrap());
+        // deleting something which is gone already is not an error
+        backend.0.delete("a.txt").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn keys_outside_the_root_are_rejected() {
+        let backend = TempBackend::new(None);
+        assert!(backend.0.exists("../a.txt").await.is_err());
+        assert!(backend.0.delete("/etc/passwd").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn unique_key_renders_again_on_collision() {
+        let backend = TempBackend::new(Some("{shortid}.{ext}"));
+        let first = unique_key(&backend.0, ObjectKind::File, &mime::TEXT_PLAIN, None)
+            .await
+            .unwrap();
+        assert!(first.ends_with(".txt"));
+        backend
+            .0
+            .put(object(&first), Bytes::new(), ProgressTracker::default())
+            .await
+            .unwrap();
+        let second = unique_key(&backend.0, ObjectKind::File, &mime::TEXT_PLAIN, None)
+            .await
+            .unwrap();
+        assert_ne!(first, second);
+    }
+
+    #[tokio::test]
+    async fn unique_key_gives_up_on_a_fixed_template() {
+        let backend = TempBackend::new(Some("fixed.{ext}"));
+        let key = unique_key(&backend.0, ObjectKind::File, &mime::TEXT_PLAIN, None)
+            .await
+            .unwrap();
+        assert_eq!(key, "fixed.txt");
+        backend
+            .0
+            .put(object(&key), Bytes::new(), ProgressTracker::default())
+            .await
+            .unwrap();
+        assert!(
+            unique_key(&backend.0, ObjectKind::File, &mime::TEXT_PLAIN, None)
+                .await
+                .is_err()
+        );
+    }
+}
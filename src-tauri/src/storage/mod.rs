@@ -0,0 +1,215 @@
+pub mod azure;
+pub mod custom;
+pub mod keys;
+pub mod local;
+pub mod replicated;
+pub mod sftp;
+pub mod webdav;
+
+use std::{fmt::Debug, path::PathBuf, sync::Arc};
+
+use bytes::Bytes;
+use mime::Mime;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use tauri::async_runtime::Sender;
+use tauri_plugin_http::reqwest::{Client, Url};
+
+use crate::{
+    db::crud::S3ConfigRaw,
+    error::AnyhowError,
+    s3::{
+        backend::S3Backend,
+        metadata::ObjectMetadata,
+        progress::ProgressTracker,
+        retry::FailureKind,
+        uploader::{CompletedData, UploadEvent},
+    },
+};
+use azure::AzureBackend;
+use custom::CustomBackend;
+use keys::ObjectKind;
+use local::LocalBackend;
+use sftp::SftpBackend;
+use webdav::WebdavBackend;
+
+/// Where the objects of a config are stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum BackendKind {
+    #[default]
+    S3,
+    /// a directory on this machine
+    Local,
+    /// Azure Blob Storage, settings are stored in `azureconfig`
+    Azure,
+    /// a WebDAV server such as Nextcloud, settings are stored in
+    /// `webdavconfig`
+    Webdav,
+    /// a directory on a server reached over SFTP, settings are stored in
+    /// `sftpconfig`
+    Sftp,
+    /// any HTTP upload service, described by a ShareX style definition in
+    /// `customuploader`
+    Custom,
+}
+
+/// An object which is about to be uploaded
+#[derive(Debug, Clone)]
+pub struct NewObject {
+    pub key: String,
+    pub mime: Mime,
+    pub kind: ObjectKind,
+    pub metadata: ObjectMetadata,
+    /// the file the bytes are read from, an interrupted multipart upload of
+    /// a file can be resumed from it
+    pub source: Option<PathBuf>,
+}
+
+/// Somewhere uploads can be stored and shared from
+#[async_trait::async_trait]
+pub trait StorageBackend: Debug + Send + Sync {
+    /// id of the config this backend was built from
+    fn config_id(&self) -> i64;
+    fn key_template(&self) -> Option<&str>;
+    fn key_prefix(&self) -> Option<&str>;
+
+    /// Whether an object is already stored under `key`
+    async fn exists(&self, key: &str) -> Result<bool, AnyhowError>;
+
+    /// Store an object which is already complete in memory
+    async fn put(
+        &self,
+        object: NewObject,
+        bytes: Bytes,
+        progress: ProgressTracker,
+    ) -> Result<CompletedData, AnyhowError>;
+
+    /// Start an upload which is written as it is being recorded
+    async fn begin_upload(
+        &self,
+        object: NewObject,
+        progress: ProgressTracker,
+    ) -> Result<Box<dyn UploadSession>, AnyhowError>;
+
+    async fn delete(&self, key: &str) -> Result<(), AnyhowError>;
+
+    /// Delete an object which was uploaded with `deletion_url` in its
+    /// `CompletedData`, backends which delete by key ignore it
+    async fn delete_upload(
+        &self,
+        key: &str,
+        _deletion_url: Option<&str>,
+    ) -> Result<(), AnyhowError> {
+        self.delete(key).await
+    }
+
+    /// A link the object can be shared with
+    fn public_url(&self, key: &str) -> Result<Url, AnyhowError>;
+
+    /// A link for sharing a stored object, backends which have to ask the
+    /// server for one override this
+    async fn share_link(&self, key: &str) -> Result<Url, AnyhowError> {
+        self.public_url(key)
+    }
+
+    /// S3 only features, such as managing multipart uploads, need the S3
+    /// backend itself
+    fn as_s3(&self) -> Option<&S3Backend> {
+        None
+    }
+}
+
+/// An upload in progress, bytes are written in order until it is completed
+/// or aborted
+#[async_trait::async_trait]
+pub trait UploadSession: Debug + Send + Sync {
+    async fn write(&mut self, slice: &[u8]) -> Result<(), AnyhowError>;
+    async fn complete(&mut self, slice: &[u8]) -> Result<CompletedData, AnyhowError>;
+    /// Throw away everything written so far
+    async fn abort(&mut self) -> Result<(), AnyhowError>;
+}
+
+/// Build the backend a config describes
+pub fn open_backend(
+    config: S3ConfigRaw,
+    client: &Client,
+    pool: &SqlitePool,
+) -> anyhow::Result<Arc<dyn StorageBackend>> {
+    Ok(match config.backend() {
+        BackendKind::S3 => Arc::new(S3Backend::new(config.build()?, client.clone(), pool.clone())),
+        BackendKind::Local => Arc::new(LocalBackend::from_config(config)?),
+        BackendKind::Azure => Arc::new(AzureBackend::from_config(config, client)?),
+        BackendKind::Webdav => Arc::new(WebdavBackend::from_config(config, client)?),
+        BackendKind::Sftp => Arc::new(SftpBackend::from_config(config)?),
+        BackendKind::Custom => Arc::new(CustomBackend::from_config(config, client)?),
+    })
+}
+
+/// Reports `UploadEvent`s for every step of an upload session
+#[derive(Debug)]
+pub struct UploadNotifier {
+    session: Box<dyn UploadSession>,
+    tx: Sender<UploadEvent>,
+}
+
+impl UploadNotifier {
+    pub async fn start(
+        backend: &dyn StorageBackend,
+        object: NewObject,
+        tx: Sender<UploadEvent>,
+    ) -> Result<Self, AnyhowError> {
+        let progress = ProgressTracker::new(Some(tx.clone()));
+        let session = Self::wrapper(
+            &tx,
+            backend.begin_upload(object, progress).await,
+            Some(UploadEvent::Started),
+        )
+        .await?;
+        Ok(Self { session, tx })
+    }
+
+    async fn wrap<T>(
+        &self,
+        res: Result<T, AnyhowError>,
+        success_event: Option<UploadEvent>,
+    ) -> Result<T, AnyhowError> {
+        Self::wrapper(&self.tx, res, success_event).await
+    }
+
+    async fn wrapper<T>(
+        tx: &Sender<UploadEvent>,
+        res: Result<T, AnyhowError>,
+        success_event: Option<UploadEvent>,
+    ) -> Result<T, AnyhowError> {
+        match res {
+            Ok(t) => {
+                if let Some(event) = success_event {
+                    let _ = tx.send(event).await;
+                }
+                Ok(t)
+            }
+            Err(e) => {
+                let _ = tx.send(UploadEvent::Failed(FailureKind::of(&e))).await;
+                Err(e)
+            }
+        }
+    }
+
+    pub async fn upload_part(&mut self, slice: &[u8]) -> Result<(), AnyhowError> {
+        let res = self.session.write(slice).await;
+        // progress is reported by the tracker as bytes are written
+        self.wrap(res, None).await
+    }
+
+    pub async fn complete_upload(&mut self, slice: &[u8]) -> Result<CompletedData, AnyhowError> {
+        let res = self.session.complete(slice).await;
+        self.wrap(res, Some(UploadEvent::Done)).await
+    }
+
+    pub async fn abort(&mut self) -> Result<(), AnyhowError> {
+        let res = self.session.abort().await;
+        self.wrap(res, Some(UploadEvent::Aborted)).await
+    }
+}
@@ -0,0 +1,277 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures_util::future::join_all;
+use tauri_plugin_http::reqwest::Url;
+
+use super::{NewObject, StorageBackend, UploadSession};
+use crate::{
+    error::AnyhowError,
+    s3::{backend::S3Backend, progress::ProgressTracker, uploader::CompletedData},
+};
+
+/// Where the copy of an upload made to a replica went, or why it failed
+#[derive(Debug, Clone)]
+pub struct Replica {
+    pub config_id: i64,
+    pub obj_key: String,
+    pub url: Option<Url>,
+    pub error: Option<String>,
+    /// only needed to remove the copy again when the upload fails
+    deletion_url: Option<String>,
+}
+
+impl Replica {
+    fn new(config_id: i64, obj_key: &str, res: Result<CompletedData, String>) -> Self {
+        match res {
+            Ok(completed) => Self {
+                config_id,
+                obj_key: completed.obj_key,
+                url: Some(completed.upload_url),
+                error: None,
+                deletion_url: completed.deletion_url,
+            },
+            Err(error) => Self {
+                config_id,
+                obj_key: obj_key.to_owned(),
+                url: None,
+                error: Some(error),
+                deletion_url: None,
+            },
+        }
+    }
+}
+
+/// Sends every upload to the primary backend and a copy to each replica.
+/// The key, urls and progress are those of the primary, copies are stored
+/// under the same key. A replica which fails is recorded in the result
+/// instead of failing the upload, only the primary has to succeed.
+#[derive(Debug)]
+pub struct ReplicatedBackend {
+    primary: Arc<dyn StorageBackend>,
+    replicas: Vec<Arc<dyn StorageBackend>>,
+}
+
+impl ReplicatedBackend {
+    pub fn new(primary: Arc<dyn StorageBackend>, replicas: Vec<Arc<dyn StorageBackend>>) -> Self {
+        Self { primary, replicas }
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for ReplicatedBackend {
+    fn config_id(&self) -> i64 {
+        self.primary.config_id()
+    }
+
+    fn key_template(&self) -> Option<&str> {
+        self.primary.key_template()
+    }
+
+    fn key_prefix(&self) -> Option<&str> {
+        self.primary.key_prefix()
+    }
+
+    /// Copies are stored under the primary's key, so a key is taken when
+    /// any replica has it too. A replica which can't be asked doesn't hold
+    /// up the upload, its copy fails on its own.
+    async fn exists(&self, key: &str) -> Result<bool, AnyhowError> {
+        let (primary, replicas) = futures_util::join!(
+            self.primary.exists(key),
+            join_all(self.replicas.iter().map(|replica| replica.exists(key)))
+        );
+        Ok(primary? || replicas.into_iter().any(|res| matches!(res, Ok(true))))
+    }
+
+    async fn put(
+        &self,
+        object: NewObject,
+        bytes: Bytes,
+        progress: ProgressTracker,
+    ) -> Result<CompletedData, AnyhowError> {
+        let copies = join_all(self.replicas.iter().map(|replica| {
+            let (object, bytes) = (object.clone(), bytes.clone());
+            async move {
+                let res = replica.put(object, bytes, ProgressTracker::default()).await;
+                (replica, res)
+            }
+        }));
+        let key = object.key.clone();
+        let (primary, copies) =
+            futures_util::join!(self.primary.put(object, bytes, progress), copies);
+        let mut primary = match primary {
+            Ok(primary) => primary,
+            Err(e) => {
+                // the upload is retried as a whole, don't leave copies of an
+                // upload which doesn't exist behind
+                for (replica, res) in copies {
+                    if let Ok(completed) = res {
+                        let _ = replica
+                            .delete_upload(&completed.obj_key, completed.deletion_url.as_deref())
+                            .await;
+                    }
+                }
+                return Err(e);
+            }
+        };
+        primary.replicas = copies
+            .into_iter()
+            .map(|(replica, res)| {
+                Replica::new(replica.config_id(), &key, res.map_err(|e| e.to_string()))
+            })
+            .collect();
+        Ok(primary)
+    }
+
+    async fn begin_upload(
+        &self,
+        object: NewObject,
+        progress: ProgressTracker,
+    ) -> Result<Box<dyn UploadSession>, AnyhowError> {
+        let copies = join_all(self.replicas.iter().map(|replica| {
+            let object = object.clone();
+            async move {
+                let res = replica
+                    .begin_upload(object, ProgressTracker::default())
+                    .await;
+                (replica, res)
+            }
+        }));
+        let key = object.key.clone();
+        let (primary, copies) =
+            futures_util::join!(self.primary.begin_upload(object, progress), copies);
+        let mut replicas: Vec<ReplicaSession> = copies
+            .into_iter()
+            .map(|(replica, res)| {
+                let (session, error) = match res {
+                    Ok(session) => (Some(session), None),
+                    Err(e) => (None, Some(e.to_string())),
+                };
+                ReplicaSession {
+                    backend: replica.clone(),
+                    key: key.clone(),
+                    session,
+                    error,
+                }
+            })
+            .collect();
+        match primary {
+            Ok(primary) => Ok(Box::new(ReplicatedSession { primary, replicas })),
+            Err(e) => {
+                join_all(replicas.iter_mut().map(|r| r.abort())).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Only the primary copy is deleted, replicas are usually archives which
+    /// should outlive the history
+    async fn delete(&self, key: &str) -> Result<(), AnyhowError> {
+        self.primary.delete(key).await
+    }
+
+    async fn delete_upload(
+        &self,
+        key: &str,
+        deletion_url: Option<&str>,
+    ) -> Result<(), AnyhowError> {
+        self.primary.delete_upload(key, deletion_url).await
+    }
+
+    fn public_url(&self, key: &str) -> Result<Url, AnyhowError> {
+        self.primary.public_url(key)
+    }
+
+    async fn share_link(&self, key: &str) -> Result<Url, AnyhowError> {
+        self.primary.share_link(key).await
+    }
+
+    fn as_s3(&self) -> Option<&S3Backend> {
+        self.primary.as_s3()
+    }
+}
+
+/// The copy of a streamed upload to one replica, a replica which fails
+/// stops receiving bytes and keeps its error for the result
+#[derive(Debug)]
+struct ReplicaSession {
+    backend: Arc<dyn StorageBackend>,
+    key: String,
+    session: Option<Box<dyn UploadSession>>,
+    error: Option<String>,
+}
+
+impl ReplicaSession {
+    async fn write(&mut self, slice: &[u8]) {
+        let Some(session) = self.session.as_mut() else {
+            return;
+        };
+        if let Err(e) = session.write(slice).await {
+            self.error = Some(e.to_string());
+            self.abort().await;
+        }
+    }
+
+    async fn complete(&mut self, slice: &[u8]) -> Replica {
+        let config_id = self.backend.config_id();
+        let Some(mut session) = self.session.take() else {
+            let error = self.error.clone().unwrap_or_default();
+            return Replica::new(config_id, &self.key, Err(error));
+        };
+        let res = session.complete(slice).await;
+        if let Err(e) = &res {
+            self.error = Some(e.to_string());
+            let _ = session.abort().await;
+        }
+        Replica::new(config_id, &self.key, res.map_err(|e| e.to_string()))
+    }
+
+    async fn abort(&mut self) {
+        if let Some(mut session) = self.session.take() {
+            let _ = session.abort().await;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ReplicatedSession {
+    primary: Box<dyn UploadSession>,
+    replicas: Vec<ReplicaSession>,
+}
+
+#[async_trait::async_trait]
+impl UploadSession for ReplicatedSession {
+    async fn write(&mut self, slice: &[u8]) -> Result<(), AnyhowError> {
+        let copies = join_all(self.replicas.iter_mut().map(|r| r.write(slice)));
+        let (res, _) = futures_util::join!(self.primary.write(slice), copies);
+        res
+    }
+
+    async fn complete(&mut self, slice: &[u8]) -> Result<CompletedData, AnyhowError> {
+        let copies = join_all(self.replicas.iter_mut().map(|r| r.complete(slice)));
+        let (res, replicas) = futures_util::join!(self.primary.complete(slice), copies);
+        match res {
+            Ok(mut completed) => {
+                completed.replicas = replicas;
+                Ok(completed)
+            }
+            Err(e) => {
+                // the upload failed as a whole, remove the copies which made it
+                for (session, replica) in self.replicas.iter().zip(&replicas) {
+                    if replica.error.is_none() {
+                        let _ = session
+                            .backend
+                            .delete_upload(&replica.obj_key, replica.deletion_url.as_deref())
+                            .await;
+                    }
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn abort(&mut self) -> Result<(), AnyhowError> {
+        join_all(self.replicas.iter_mut().map(|r| r.abort())).await;
+        self.primary.abort().await
+    }
+}
@@ -0,0 +1,395 @@
+use std::{fmt, path::PathBuf, sync::Arc};
+
+use bytes::Bytes;
+use russh::client;
+use russh_keys::key::PublicKey;
+use russh_sftp::client::{fs::File, SftpSession};
+use tauri_plugin_http::reqwest::Url;
+use tokio::io::AsyncWriteExt;
+
+use super::{NewObject, StorageBackend, UploadSession};
+use crate::{
+    db::crud::S3ConfigRaw,
+    error::AnyhowError,
+    s3::{
+        progress::ProgressTracker,
+        uploader::{encode_key, CompletedData},
+    },
+    template,
+};
+
+/// Checks servers against `~/.ssh/known_hosts`
+struct KnownHosts {
+    host: String,
+    port: u16,
+}
+
+#[async_trait::async_trait]
+impl client::Handler for KnownHosts {
+    type Error = anyhow::Error;
+
+    async fn check_server_key(&mut self, key: &PublicKey) -> Result<bool, Self::Error> {
+        // a changed key is an error of its own
+        if !russh_keys::check_known_hosts(&self.host, self.port, key)? {
+            anyhow::bail!(
+                "{} is not in ~/.ssh/known_hosts, connect with ssh once to trust it",
+                self.host
+            );
+        }
+        Ok(true)
+    }
+}
+
+#[derive(Clone)]
+enum SftpAuth {
+    Password(String),
+    KeyFile {
+        path: PathBuf,
+        passphrase: Option<String>,
+    },
+}
+
+/// Leaves out the password and passphrase, backends are printed while
+/// debugging
+impl fmt::Debug for SftpAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Password(_) => f.write_str("Password(..)"),
+            Self::KeyFile { path, .. } => f
+                .debug_struct("KeyFile")
+                .field("path", path)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+/// An SFTP session, the SSH session is kept so it stays connected
+struct Connection {
+    sftp: SftpSession,
+    _session: client::Handle<KnownHosts>,
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connection").finish_non_exhaustive()
+    }
+}
+
+/// Copies objects into a directory on a server over SFTP, which is usually
+/// served by a web server under the host rewrite url
+#[derive(Debug, Clone)]
+pub struct SftpBackend {
+    id: i64,
+    host: String,
+    port: u16,
+    username: String,
+    auth: SftpAuth,
+    /// relative paths are resolved from the home directory of the user
+    remote_dir: String,
+    base_url: Option<String>,
+    url_template: Option<String>,
+    key_template: Option<String>,
+    key_prefix: Option<String>,
+}
+
+/// `~/` only means something to a shell, SFTP paths are relative to the
+/// home directory already
+fn expand_remote(dir: &str) -> String {
+    let dir = dir.strip_prefix("~/").unwrap_or(dir);
+    match dir.trim_end_matches('/') {
+        "" | "~" => ".".to_owned(),
+        dir => dir.to_owned(),
+    }
+}
+
+fn expand_local(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+impl SftpBackend {
+    pub fn from_config(config: S3ConfigRaw) -> anyhow::Result<Self> {
+        let (id, fields) = config.into_parts();
+        let non_empty = |s: Option<String>| s.filter(|s| !s.is_empty());
+        let sftp = fields
+            .sftp
+            .ok_or_else(|| anyhow::anyhow!("No SFTP settings for this config"))?;
+
+        let auth = match (non_empty(sftp.key_file), non_empty(sftp.password)) {
+            (Some(path), _) => SftpAuth::KeyFile {
+                path: expand_local(&path),
+                passphrase: non_empty(sftp.key_passphrase),
+            },
+            (None, Some(password)) => SftpAuth::Password(password),
+            (None, None) => anyhow::bail!("SFTP needs a key file or a password"),
+        };
+
+        Ok(Self {
+            id,
+            host: sftp.host,
+            port: u16::try_from(sftp.port)?,
+            username: sftp.username,
+            auth,
+            remote_dir: expand_remote(&sftp.remote_dir),
+            base_url: non_empty(fields.host_rewrite),
+            url_template: non_empty(fields.url_template),
+            key_template: non_empty(fields.key_template),
+            key_prefix: non_empty(fields.key_prefix),
+        })
+    }
+
+    async fn connect(&self) -> Result<Connection, AnyhowError> {
+        let handler = KnownHosts {
+            host: self.host.clone(),
+            port: self.port,
+        };
+        let config = Arc::new(client::Config::default());
+        let mut session = client::connect(config, (self.host.as_str(), self.port), handler).await?;
+        let authenticated = match &self.auth {
+            SftpAuth::Password(password) => {
+                session
+                    .authenticate_password(&self.username, password)
+                    .await?
+            }
+            SftpAuth::KeyFile { path, passphrase } => {
+                let key = russh_keys::load_secret_key(path, passphrase.as_deref())?;
+                session
+                    .authenticate_publickey(&self.username, Arc::new(key))
+                    .await?
+            }
+        };
+        if !authenticated {
+            return Err(anyhow::anyhow!(
+                "{}@{} rejected the credentials",
+                self.username,
+                self.host
+            )
+            .into());
+        }
+        let channel = session.channel_open_session().await?;
+        channel.request_subsystem(true, "sftp").await?;
+        let sftp = SftpSession::new(channel.into_stream()).await?;
+        Ok(Connection {
+            sftp,
+            _session: session,
+        })
+    }
+
+    /// The remote file an object is stored in, keys can't point outside the
+    /// remote directory
+    fn path(&self, key: &str) -> Result<String, AnyhowError> {
+        if key
+            .split('/')
+            .any(|segment| matches!(segment, "" | "." | ".."))
+        {
+            return Err(anyhow::anyhow!("{} is not a valid object key", key).into());
+        }
+        Ok(format!("{}/{}", self.remote_dir, key))
+    }
+
+    /// Files are written next to their final path and only renamed into
+    /// place once complete, so a partial file is never served
+    fn partial_path(path: &str) -> String {
+        match path.rsplit_once('/') {
+            Some((dir, name)) => format!("{}/.{}.part", dir, name),
+            None => format!(".{}.part", path),
+        }
+    }
+
+    /// Create every directory above `path` which doesn't exist yet
+    async fn create_parents(conn: &Connection, path: &str) -> Result<(), AnyhowError> {
+        let parents = path
+            .char_indices()
+            .filter(|(i, c)| *c == '/' && *i > 0)
+            .map(|(i, _)| &path[..i]);
+        for dir in parents {
+            if conn.sftp.metadata(dir).await.is_err() {
+                conn.sftp.create_dir(dir).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn create_partial(
+        &self,
+        key: &str,
+    ) -> Result<(Connection, String, String, File), AnyhowError> {
+        let conn = self.connect().await?;
+        let path = self.path(key)?;
+        Self::create_parents(&conn, &path).await?;
+        let partial = Self::partial_path(&path);
+        let file = conn.sftp.create(&partial).await?;
+        Ok((conn, path, partial, file))
+    }
+
+    async fn finish(
+        &self,
+        conn: &Connection,
+        key: String,
+        path: &str,
+        partial: &str,
+        expected: u64,
+    ) -> Result<CompletedData, AnyhowError> {
+        let size = conn.sftp.metadata(partial).await?.size;
+        if size != Some(expected) {
+            let _ = conn.sftp.remove_file(partial).await;
+            return Err(anyhow::anyhow!(
+                "{} has {} bytes on the server but {} were written",
+                key,
+                size.map_or("an unknown number of".to_owned(), |s| s.to_string()),
+                expected
+            )
+            .into());
+        }
+        conn.sftp.rename(partial, path).await?;
+        Ok(CompletedData {
+            upload_url: self.public_url(&key)?,
+            obj_key: key,
+            config_id: self.id,
+            deletion_url: None,
+            replicas: Vec::new(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for SftpBackend {
+    fn config_id(&self) -> i64 {
+        self.id
+    }
+
+    fn key_template(&self) -> Option<&str> {
+        self.key_template.as_deref()
+    }
+
+    fn key_prefix(&self) -> Option<&str> {
+        self.key_prefix.as_deref()
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, AnyhowError> {
+        let conn = self.connect().await?;
+        Ok(conn.sftp.metadata(self.path(key)?).await.is_ok())
+    }
+
+    async fn put(
+        &self,
+        object: NewObject,
+        bytes: Bytes,
+        progress: ProgressTracker,
+    ) -> Result<CompletedData, AnyhowError> {
+        let (conn, path, partial, mut file) = self.create_partial(&object.key).await?;
+        let size = bytes.len() as u64;
+        progress.add_total(size);
+        file.write_all(&bytes).await?;
+        file.shutdown().await?;
+        progress.sent(size, None);
+        self.finish(&conn, object.key, &path, &partial, size).await
+    }
+
+    async fn begin_upload(
+        &self,
+        object: NewObject,
+        progress: ProgressTracker,
+    ) -> Result<Box<dyn UploadSession>, AnyhowError> {
+        let (conn, path, partial, file) = self.create_partial(&object.key).await?;
+        Ok(Box::new(SftpUpload {
+            backend: self.clone(),
+            conn,
+            key: object.key,
+            path,
+            partial,
+            file,
+            written: 0,
+            progress,
+        }))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), AnyhowError> {
+        let conn = self.connect().await?;
+        let path = self.path(key)?;
+        // a file which is gone already counts as deleted
+        if conn.sftp.metadata(&path).await.is_ok() {
+            conn.sftp.remove_file(&path).await?;
+        }
+        Ok(())
+    }
+
+    fn public_url(&self, key: &str) -> Result<Url, AnyhowError> {
+        if let Some(template) = &self.url_template {
+            let filename = key.rsplit('/').next().unwrap_or(key);
+            let url = template::render(template, |name| match name {
+                "key" => Some(encode_key(key)),
+                "filename" => Some(encode_key(filename)),
+                "bucket" | "region" => Some(String::new()),
+                _ => None,
+            })?;
+            return Ok(Url::parse(&url)?);
+        }
+        let base = self
+            .base_url
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No public base url for {}", self.host))?;
+        Ok(Url::parse(&format!(
+            "{}/{}",
+            base.trim_end_matches('/'),
+            encode_key(key)
+        ))?)
+    }
+}
+
+/// A remote file which is written as the recording comes in
+pub struct SftpUpload {
+    backend: SftpBackend,
+    conn: Connection,
+    key: String,
+    path: String,
+    partial: String,
+    file: File,
+    written: u64,
+    progress: ProgressTracker,
+}
+
+impl fmt::Debug for SftpUpload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SftpUpload")
+            .field("host", &self.backend.host)
+            .field("partial", &self.partial)
+            .field("written", &self.written)
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait::async_trait]
+impl UploadSession for SftpUpload {
+    async fn write(&mut self, slice: &[u8]) -> Result<(), AnyhowError> {
+        self.progress.add_total(slice.len() as u64);
+        self.file.write_all(slice).await?;
+        self.written += slice.len() as u64;
+        self.progress.sent(slice.len() as u64, None);
+        Ok(())
+    }
+
+    async fn complete(&mut self, slice: &[u8]) -> Result<CompletedData, AnyhowError> {
+        self.write(slice).await?;
+        self.file.shutdown().await?;
+        self.backend
+            .finish(
+                &self.conn,
+                self.key.clone(),
+                &self.path,
+                &self.partial,
+                self.written,
+            )
+            .await
+    }
+
+    async fn abort(&mut self) -> Result<(), AnyhowError> {
+        let _ = self.file.shutdown().await;
+        if self.conn.sftp.metadata(&self.partial).await.is_ok() {
+            self.conn.sftp.remove_file(&self.partial).await?;
+        }
+        Ok(())
+    }
+}
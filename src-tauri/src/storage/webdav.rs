@@ -0,0 +1,691 @@
+use std::{
+    fmt,
+    pin::Pin,
+    sync::{Mutex, PoisonError},
+    task::{Context, Poll},
+};
+
+use bytes::{Bytes, BytesMut};
+use futures_util::Stream;
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use tauri::http::header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE};
+use tauri_plugin_http::reqwest::{Body, Client, Method, RequestBuilder, Url};
+use tokio::{
+    sync::mpsc,
+    task::{JoinHandle, JoinSet},
+};
+
+use super::{NewObject, StorageBackend, UploadSession};
+use crate::{
+    db::crud::S3ConfigRaw,
+    error::AnyhowError,
+    s3::{
+        progress::ProgressTracker,
+        retry::{check_response, RetryPolicy, S3Error},
+        uploader::{encode_key, CompletedData, PartSizing, MAX_PARTS},
+    },
+    template,
+};
+
+/// Where Nextcloud (and ownCloud) keep the files of the user a WebDAV url
+/// points into, which is needed for chunked uploads and share links
+#[derive(Debug, Clone)]
+struct Nextcloud {
+    /// everything before `/remote.php`, the OCS API lives below it
+    root: String,
+    /// collection chunked uploads are assembled in, only the newer
+    /// `/remote.php/dav/files/{user}` urls support chunking v2
+    uploads_url: Option<Url>,
+    /// the directory uploads go to, relative to the files of the user
+    dir: String,
+}
+
+impl Nextcloud {
+    fn from_url(url: &Url) -> Option<Self> {
+        let path = url.path();
+        let index = path.find("/remote.php/")?;
+        let root = format!("{}{}", url.origin().ascii_serialization(), &path[..index]);
+        let rest = &path[index + "/remote.php/".len()..];
+        let (uploads_url, dir) = if let Some(rest) = rest.strip_prefix("dav/files/") {
+            let (user, dir) = rest.split_once('/').unwrap_or((rest, ""));
+            let uploads = Url::parse(&format!("{}/remote.php/dav/uploads/{}", root, user)).ok();
+            (uploads, dir)
+        } else {
+            (None, rest.strip_prefix("webdav")?)
+        };
+        Some(Self {
+            root,
+            uploads_url,
+            dir: percent_decode_str(dir.trim_matches('/'))
+                .decode_utf8_lossy()
+                .into_owned(),
+        })
+    }
+
+    /// Path of an object for the OCS API
+    fn share_path(&self, key: &str) -> String {
+        if self.dir.is_empty() {
+            format!("/{}", key)
+        } else {
+            format!("/{}/{}", self.dir, key)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct OcsResponse {
+    ocs: Ocs,
+}
+
+#[derive(Deserialize)]
+struct Ocs {
+    data: OcsShare,
+}
+
+#[derive(Deserialize)]
+struct OcsShare {
+    url: String,
+}
+
+fn mkcol() -> Method {
+    Method::from_bytes(b"MKCOL").expect("MKCOL is a valid method")
+}
+
+fn move_method() -> Method {
+    Method::from_bytes(b"MOVE").expect("MOVE is a valid method")
+}
+
+/// Stores objects as files on a WebDAV server, such as Nextcloud. WebDAV
+/// has nothing like object metadata so only the content type is kept.
+#[derive(Clone)]
+pub struct WebdavBackend {
+    id: i64,
+    client: Client,
+    /// the collection uploads go to, without a trailing slash
+    files_url: Url,
+    username: String,
+    password: String,
+    nextcloud: Option<Nextcloud>,
+    /// create public share links through the OCS API
+    share_links: bool,
+    host_rewrite: Option<String>,
+    url_template: Option<String>,
+    key_template: Option<String>,
+    key_prefix: Option<String>,
+    sizing: PartSizing,
+    max_concurrent_chunks: usize,
+}
+
+/// Leaves out the password, backends are printed while debugging
+impl fmt::Debug for WebdavBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebdavBackend")
+            .field("id", &self.id)
+            .field("files_url", &self.files_url)
+            .field("username", &self.username)
+            .field("nextcloud", &self.nextcloud)
+            .field("share_links", &self.share_links)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WebdavBackend {
+    pub fn from_config(config: S3ConfigRaw, client: &Client) -> anyhow::Result<Self> {
+        let (id, fields) = config.into_parts();
+        let non_empty = |s: Option<String>| s.filter(|s| !s.is_empty());
+        let webdav = fields
+            .webdav
+            .ok_or_else(|| anyhow::anyhow!("No WebDAV settings for this config"))?;
+
+        let files_url = Url::parse(webdav.url.trim_end_matches('/'))?;
+        let nextcloud = Nextcloud::from_url(&files_url);
+        if webdav.share_links && nextcloud.is_none() {
+            anyhow::bail!("Share links need a Nextcloud or ownCloud WebDAV url");
+        }
+
+        Ok(Self {
+            id,
+            client: client.clone(),
+            files_url,
+            username: webdav.username,
+            password: webdav.password,
+            nextcloud,
+            share_links: webdav.share_links,
+            host_rewrite: non_empty(fields.host_rewrite),
+            url_template: non_empty(fields.url_template),
+            key_template: non_empty(fields.key_template),
+            key_prefix: non_empty(fields.key_prefix),
+            sizing: PartSizing::from_mib(
+                fields.min_part_size_mib as usize,
+                fields.max_part_size_mib as usize,
+            ),
+            max_concurrent_chunks: fields.max_concurrent_parts as usize,
+        })
+    }
+
+    fn file_url(&self, key: &str) -> Url {
+        let mut url = self.files_url.clone();
+        url.set_path(&format!("{}/{}", url.path(), encode_key(key)));
+        url
+    }
+
+    fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        self.client
+            .request(method, url)
+            .basic_auth(&self.username, Some(&self.password))
+    }
+
+    /// Create every directory above `key`, WebDAV won't store a file in a
+    /// collection which doesn't exist
+    async fn create_parents(&self, key: &str) -> Result<(), AnyhowError> {
+        let Some((parents, _)) = key.rsplit_once('/') else {
+            return Ok(());
+        };
+        let mut dir = String::new();
+        for segment in parents.split('/') {
+            dir.push_str(segment);
+            dir.push('/');
+            let url = self.file_url(&dir);
+            match RetryPolicy::default()
+                .send(|| self.request(mkcol(), url.clone()))
+                .await
+            {
+                // the directory exists already
+                Err(e) if e.status == Some(405) => {}
+                res => {
+                    res?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn file_size(&self, key: &str) -> Result<Option<u64>, AnyhowError> {
+        let url = self.file_url(key);
+        match RetryPolicy::default()
+            .send(|| self.request(Method::HEAD, url.clone()))
+            .await
+        {
+            Ok(res) => Ok(res
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u64>().ok())),
+            Err(e) if e.status == Some(404) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Check the stored file has every byte that was sent, a file which
+    /// doesn't is deleted again
+    async fn verify_size(&self, key: &str, expected: u64) -> Result<(), AnyhowError> {
+        let size = self.file_size(key).await?;
+        if size == Some(expected) {
+            return Ok(());
+        }
+        let _ = self.delete(key).await;
+        Err(anyhow::anyhow!(
+            "{} has {} bytes on the server but {} were uploaded",
+            key,
+            size.map_or("an unknown number of".to_owned(), |s| s.to_string()),
+            expected
+        )
+        .into())
+    }
+
+    async fn completed(&self, key: String) -> Result<CompletedData, AnyhowError> {
+        Ok(CompletedData {
+            upload_url: self.share_link(&key).await?,
+            obj_key: key,
+            config_id: self.id,
+            deletion_url: None,
+            replicas: Vec::new(),
+        })
+    }
+
+    /// Start a Nextcloud chunked upload, `None` if the server doesn't
+    /// support chunking
+    async fn begin_chunked(
+        &self,
+        object: &NewObject,
+        progress: &ProgressTracker,
+    ) -> Result<Option<ChunkedSession>, AnyhowError> {
+        let Some(uploads_url) = self.nextcloud.as_ref().and_then(|n| n.uploads_url.as_ref()) else {
+            return Ok(None);
+        };
+        let upload_url = Url::parse(&format!("{}/{}", uploads_url, uuid::Uuid::new_v4()))?;
+        let destination = self.file_url(&object.key).to_string();
+        match RetryPolicy::default()
+            .send(|| {
+                self.request(mkcol(), upload_url.clone())
+                    .header("Destination", &destination)
+            })
+            .await
+        {
+            Ok(_) => {}
+            Err(e) if matches!(e.status, Some(404 | 405 | 501)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        Ok(Some(ChunkedSession {
+            backend: self.clone(),
+            key: object.key.clone(),
+            upload_url,
+            destination,
+            chunk_counter: 1,
+            in_flight: JoinSet::new(),
+            buffer: BytesMut::with_capacity(self.sizing.threshold(1)),
+            total_size: 0,
+            progress: progress.clone(),
+        }))
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for WebdavBackend {
+    fn config_id(&self) -> i64 {
+        self.id
+    }
+
+    fn key_template(&self) -> Option<&str> {
+        self.key_template.as_deref()
+    }
+
+    fn key_prefix(&self) -> Option<&str> {
+        self.key_prefix.as_deref()
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, AnyhowError> {
+        Ok(self.file_size(key).await?.is_some())
+    }
+
+    async fn put(
+        &self,
+        object: NewObject,
+        bytes: Bytes,
+        progress: ProgressTracker,
+    ) -> Result<CompletedData, AnyhowError> {
+        self.create_parents(&object.key).await?;
+        let size = bytes.len() as u64;
+        let mime = object.mime.essence_str();
+        let url = self.file_url(&object.key);
+        progress.add_total(size);
+        let body = progress.body(bytes, None);
+        RetryPolicy::default()
+            .send(|| {
+                self.request(Method::PUT, url.clone())
+                    .header(CONTENT_TYPE, mime)
+                    .header(CONTENT_LENGTH, body.content_length())
+                    .body(body.attempt())
+            })
+            .await?;
+        self.verify_size(&object.key, size).await?;
+        self.completed(object.key).await
+    }
+
+    async fn begin_upload(
+        &self,
+        object: NewObject,
+        progress: ProgressTracker,
+    ) -> Result<Box<dyn UploadSession>, AnyhowError> {
+        self.create_parents(&object.key).await?;
+        if let Some(session) = self.begin_chunked(&object, &progress).await? {
+            return Ok(Box::new(session));
+        }
+        Ok(Box::new(StreamedSession::start(
+            self.clone(),
+            object,
+            progress,
+        )))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), AnyhowError> {
+        let url = self.file_url(key);
+        match RetryPolicy::default()
+            .send(|| self.request(Method::DELETE, url.clone()))
+            .await
+        {
+            Err(e) if e.status != Some(404) => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// The WebDAV url itself needs a login, unless the host rewrite or url
+    /// template point somewhere public
+    fn public_url(&self, key: &str) -> Result<Url, AnyhowError> {
+        if let Some(template) = &self.url_template {
+            let filename = key.rsplit('/').next().unwrap_or(key);
+            let url = template::render(template, |name| match name {
+                "key" => Some(encode_key(key)),
+                "filename" => Some(encode_key(filename)),
+                "bucket" | "region" => Some(String::new()),
+                _ => None,
+            })?;
+            return Ok(Url::parse(&url)?);
+        }
+        if let Some(base) = &self.host_rewrite {
+            return Ok(Url::parse(&format!(
+                "{}/{}",
+                base.trim_end_matches('/'),
+                encode_key(key)
+            ))?);
+        }
+        Ok(self.file_url(key))
+    }
+
+    /// A public link share created through the OCS API
+    async fn share_link(&self, key: &str) -> Result<Url, AnyhowError> {
+        let Some(nextcloud) = self.nextcloud.as_ref().filter(|_| self.share_links) else {
+            return self.public_url(key);
+        };
+        let url = Url::parse(&format!(
+            "{}/ocs/v2.php/apps/files_sharing/api/v1/shares?format=json",
+            nextcloud.root
+        ))?;
+        let path = nextcloud.share_path(key);
+        let body = RetryPolicy::default()
+            .send_text(|| {
+                self.request(Method::POST, url.clone())
+                    .header("OCS-APIRequest", "true")
+                    .header(ACCEPT, "application/json")
+                    // share type 3 is a public link
+                    .form(&[("path", path.as_str()), ("shareType", "3")])
+            })
+            .await?;
+        let res: OcsResponse = serde_json::from_str(&body)?;
+        Ok(Url::parse(&res.ocs.data.url)?)
+    }
+}
+
+/// A Nextcloud chunked upload (chunking v2), chunks are uploaded to a
+/// collection of their own and assembled into the file by moving it
+#[derive(Debug)]
+pub struct ChunkedSession {
+    backend: WebdavBackend,
+    key: String,
+    upload_url: Url,
+    /// the file the chunks are assembled into, sent with every request
+    destination: String,
+    chunk_counter: u16,
+    in_flight: JoinSet<Result<u64, AnyhowError>>,
+    buffer: BytesMut,
+    total_size: u64,
+    progress: ProgressTracker,
+}
+
+impl ChunkedSession {
+    fn chunk_url(&self, name: &str) -> Url {
+        let mut url = self.upload_url.clone();
+        url.set_path(&format!("{}/{}", url.path(), name));
+        url
+    }
+
+    fn write_slice(&mut self, slice: &[u8]) {
+        self.progress.add_total(slice.len() as u64);
+        self.buffer.extend_from_slice(slice);
+    }
+
+    /// Start uploading the buffer as the next chunk, waiting for a chunk to
+    /// finish first once `max_concurrent_parts` are in flight
+    async fn upload_chunk(&mut self) -> Result<(), AnyhowError> {
+        if self.chunk_counter > MAX_PARTS {
+            return Err(anyhow::anyhow!(
+                "{} needs more than {} chunks, raise the maximum part size",
+                self.key,
+                MAX_PARTS
+            )
+            .into());
+        }
+        while self.in_flight.len() >= self.backend.max_concurrent_chunks {
+            self.join_next_chunk().await?;
+        }
+
+        let chunk_number = self.chunk_counter;
+        self.chunk_counter += 1;
+        // chunks are assembled in the order of their names
+        let url = self.chunk_url(&format!("{:05}", chunk_number));
+        let bytes = self.buffer.split().freeze();
+        let body = self.progress.body(bytes, Some(chunk_number));
+        let backend = self.backend.clone();
+        let destination = self.destination.clone();
+        self.in_flight.spawn(async move {
+            let len = body.content_length();
+            RetryPolicy::default()
+                .send(|| {
+                    backend
+                        .request(Method::PUT, url.clone())
+                        .header("Destination", &destination)
+                        .header(CONTENT_LENGTH, len)
+                        .body(body.attempt())
+                })
+                .await?;
+            Ok::<_, AnyhowError>(len)
+        });
+        Ok(())
+    }
+
+    async fn join_next_chunk(&mut self) -> Result<(), AnyhowError> {
+        if let Some(res) = self.in_flight.join_next().await {
+            self.total_size += res??;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl UploadSession for ChunkedSession {
+    async fn write(&mut self, slice: &[u8]) -> Result<(), AnyhowError> {
+        self.write_slice(slice);
+        let sizing = &self.backend.sizing;
+        let threshold = sizing.threshold(self.chunk_counter);
+        if self.chunk_counter >= MAX_PARTS {
+            // the last chunk is kept for everything that is left, which is
+            // sent when the upload is completed
+            if self.buffer.len() > threshold {
+                return Err(anyhow::anyhow!(
+                    "{} is too large to fit in {} chunks, raise the maximum part size",
+                    self.key,
+                    MAX_PARTS,
+                )
+                .into());
+            }
+            return Ok(());
+        }
+        if self.buffer.len() < threshold {
+            return Ok(());
+        }
+        self.upload_chunk().await
+    }
+
+    async fn complete(&mut self, slice: &[u8]) -> Result<CompletedData, AnyhowError> {
+        self.write_slice(slice);
+        // an empty recording is still sent as one empty chunk
+        if !self.buffer.is_empty() || self.chunk_counter == 1 {
+            self.upload_chunk().await?;
+        }
+        while !self.in_flight.is_empty() {
+            self.join_next_chunk().await?;
+        }
+
+        let url = self.chunk_url(".file");
+        let backend = &self.backend;
+        RetryPolicy::default()
+            .send(|| {
+                backend
+                    .request(move_method(), url.clone())
+                    .header("Destination", &self.destination)
+                    .header("OC-Total-Length", self.total_size)
+                    .header("Overwrite", "T")
+            })
+            .await?;
+        backend.verify_size(&self.key, self.total_size).await?;
+        backend.completed(self.key.clone()).await
+    }
+
+    async fn abort(&mut self) -> Result<(), AnyhowError> {
+        self.in_flight.shutdown().await;
+        self.buffer.clear();
+        let backend = &self.backend;
+        match RetryPolicy::default()
+            .send(|| backend.request(Method::DELETE, self.upload_url.clone()))
+            .await
+        {
+            Err(e) if e.status != Some(404) => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Request body which is fed from a channel as the recording comes in
+struct ChannelBody(Mutex<mpsc::Receiver<Bytes>>);
+
+impl Stream for ChannelBody {
+    type Item = Result<Bytes, std::io::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut()
+            .0
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .poll_recv(cx)
+            .map(|chunk| chunk.map(Ok))
+    }
+}
+
+/// The plain PUT fallback for servers without chunking, the recording is
+/// streamed as the body of one request. It can't be retried, a failed
+/// upload has to be started again.
+#[derive(Debug)]
+pub struct StreamedSession {
+    backend: WebdavBackend,
+    key: String,
+    tx: Option<mpsc::Sender<Bytes>>,
+    request: Option<JoinHandle<Result<(), S3Error>>>,
+    written: u64,
+    progress: ProgressTracker,
+}
+
+impl StreamedSession {
+    fn start(backend: WebdavBackend, object: NewObject, progress: ProgressTracker) -> Self {
+        let (tx, rx) = mpsc::channel(4);
+        let request = backend
+            .request(Method::PUT, backend.file_url(&object.key))
+            .header(CONTENT_TYPE, object.mime.essence_str())
+            .body(Body::wrap_stream(ChannelBody(Mutex::new(rx))));
+        let request = tokio::spawn(async move {
+            let res = request.send().await.map_err(S3Error::from_reqwest)?;
+            check_response(res).await.map(|_| ())
+        });
+        Self {
+            backend,
+            key: object.key,
+            tx: Some(tx),
+            request: Some(request),
+            written: 0,
+            progress,
+        }
+    }
+
+    /// The outcome of the request, once the body has been sent or the
+    /// request stopped reading it
+    async fn finish_request(&mut self) -> Result<(), AnyhowError> {
+        self.tx = None;
+        match self.request.take() {
+            Some(request) => Ok(request.await??),
+            None => Err(anyhow::anyhow!("The upload of {} was already finished", self.key).into()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl UploadSession for StreamedSession {
+    async fn write(&mut self, slice: &[u8]) -> Result<(), AnyhowError> {
+        if slice.is_empty() {
+            return Ok(());
+        }
+        let len = slice.len() as u64;
+        self.progress.add_total(len);
+        let sent = match &self.tx {
+            Some(tx) => tx.send(Bytes::copy_from_slice(slice)).await.is_ok(),
+            None => false,
+        };
+        if !sent {
+            // the request ended early, which only happens when it failed
+            self.finish_request().await?;
+            return Err(anyhow::anyhow!("The server stopped reading {}", self.key).into());
+        }
+        self.written += len;
+        self.progress.sent(len, None);
+        Ok(())
+    }
+
+    async fn complete(&mut self, slice: &[u8]) -> Result<CompletedData, AnyhowError> {
+        self.write(slice).await?;
+        self.finish_request().await?;
+        self.backend.verify_size(&self.key, self.written).await?;
+        self.backend.completed(self.key.clone()).await
+    }
+
+    async fn abort(&mut self) -> Result<(), AnyhowError> {
+        // cutting the request off mid body keeps the server from storing a
+        // partial file, which is deleted in case it did anyway
+        self.tx = None;
+        if let Some(request) = self.request.take() {
+            request.abort();
+        }
+        self.backend.delete(&self.key).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nextcloud(url: &str) -> Option<Nextcloud> {
+        Nextcloud::from_url(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn dav_files_urls_support_chunking() {
+        let nc = nextcloud(
+            "https://cloud.example.com/remote.php/dav/files/alice/Screenshots/My%20Clips",
+        )
+        .unwrap();
+        assert_eq!(nc.root, "https://cloud.example.com");
+        assert_eq!(
+            nc.uploads_url.unwrap().as_str(),
+            "https://cloud.example.com/remote.php/dav/uploads/alice"
+        );
+        assert_eq!(nc.dir, "Screenshots/My Clips");
+    }
+
+    #[test]
+    fn dav_files_urls_of_the_home_directory() {
+        let nc = nextcloud("https://example.com/nextcloud/remote.php/dav/files/alice").unwrap();
+        assert_eq!(nc.root, "https://example.com/nextcloud");
+        assert_eq!(
+            nc.uploads_url.unwrap().as_str(),
+            "https://example.com/nextcloud/remote.php/dav/uploads/alice"
+        );
+        assert_eq!(nc.dir, "");
+        assert_eq!(nc.share_path("a.png"), "/a.png");
+    }
+
+    #[test]
+    fn legacy_webdav_urls_have_no_chunking() {
+        let nc = nextcloud("https://cloud.example.com/remote.php/webdav/Screenshots").unwrap();
+        assert_eq!(nc.root, "https://cloud.example.com");
+        assert!(nc.uploads_url.is_none());
+        assert_eq!(nc.dir, "Screenshots");
+        assert_eq!(nc.share_path("a.png"), "/Screenshots/a.png");
+
+        let nc = nextcloud("https://cloud.example.com/remote.php/webdav").unwrap();
+        assert_eq!(nc.dir, "");
+    }
+
+    #[test]
+    fn other_servers_are_not_nextcloud() {
+        assert!(nextcloud("https://dav.example.com/files/screenshots").is_none());
+        assert!(nextcloud("https://cloud.example.com/remote.php/caldav/alice").is_none());
+    }
+}
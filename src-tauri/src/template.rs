@@ -0,0 +1,31 @@
+use crate::error::AnyhowError;
+
+/// Replace every `{name}` in `template` with the value `lookup` returns for
+/// it. Unknown placeholders are an error so typos are caught when a config
+/// is saved rather than when something is uploaded.
+pub fn render<F>(template: &str, lookup: F) -> Result<String, AnyhowError>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow::anyhow!("Unclosed placeholder in {}", template))?
+            + start;
+        let name = &rest[start + 1..end];
+        let value =
+            lookup(name).ok_or_else(|| anyhow::anyhow!("Unknown placeholder {{{}}}", name))?;
+        out.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Check that `template` only uses placeholders from `known`
+pub fn validate(template: &str, known: &[&str]) -> Result<(), AnyhowError> {
+    render(template, |name| known.contains(&name).then(String::new)).map(|_| ())
+}
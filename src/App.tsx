@@ -5,6 +5,7 @@ const Index = lazy(() => import("./pages/Index"));
 const Screenshot = lazy(() => import("./pages/Screenshot"));
 const Settings = lazy(() => import("./pages/Settings"));
 const EditForm = lazy(() => import("./pages/EditForm"));
+const BrowseBucket = lazy(() => import("./pages/BrowseBucket"));
 
 export default function App() {
   return (
@@ -14,6 +15,7 @@ export default function App() {
         <Route path="/screenshot" component={Screenshot} />
         <Route path="/settings" component={Settings} />
         <Route path="/settings/config/:id/edit" component={EditForm} />
+        <Route path="/settings/config/:id/browse" component={BrowseBucket} />
       </Routes>
     </Router>
   );
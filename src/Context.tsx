@@ -11,14 +11,13 @@ import {
 import { invoke } from "@tauri-apps/api/primitives";
 import { AUDIO_BUFFER_SIZE } from "./const";
 
-function createMediaRecorderPromise(mediaRecorder: MediaRecorder) {
+function createMediaRecorderPromise(mediaRecorder: MediaRecorder, job: number) {
   const promise = new Promise<void>((resolve, reject) => {
     mediaRecorder.ondataavailable = async (e) => {
       const buf = await e.data.arrayBuffer();
-      const options =
-        mediaRecorder.state !== "recording"
-          ? { headers: { final: "true" } }
-          : undefined;
+      const headers: Record<string, string> = { job: job.toString() };
+      if (mediaRecorder.state !== "recording") headers.final = "true";
+      const options = { headers };
       invoke("upload_url_part", buf, options).then((v) => {
         console.log(v);
         if (v) resolve();
@@ -97,8 +96,10 @@ export function Provider(props: { children: JSX.Element }) {
 
   const [resource] = createResource(recorder, async (recorder) => {
     console.log(recorder);
-    await invoke("begin_upload");
-    return createMediaRecorderPromise(recorder);
+    const job: number = await invoke("begin_upload", {
+      mimeType: recorder.mimeType || null,
+    });
+    return createMediaRecorderPromise(recorder, job);
   });
   const isRecording = () => resource.loading;
 
@@ -0,0 +1,185 @@
+import { invoke } from "@tauri-apps/api/primitives";
+import { For, Show, createResource, createSignal } from "solid-js";
+
+type BucketObject = { key: string; size: number; last_modified: string };
+
+type ObjectPage = {
+  prefix: string;
+  prefixes: string[];
+  objects: BucketObject[];
+  continuation_token: string | null;
+};
+
+type ImportReport = { imported: number; skipped: number };
+
+const formatSize = (size: number) => {
+  const units = ["B", "KiB", "MiB", "GiB"];
+  let unit = 0;
+  while (size >= 1024 && unit < units.length - 1) {
+    size /= 1024;
+    unit++;
+  }
+  return `${size.toFixed(unit === 0 ? 0 : 1)} ${units[unit]}`;
+};
+
+export function BucketBrowser(props: { configId: number }) {
+  const [prefix, setPrefix] = createSignal("");
+  // tokens of the pages before the current one, to go back
+  const [tokens, setTokens] = createSignal<Array<string | null>>([]);
+  const [token, setToken] = createSignal<string | null>(null);
+  const [selected, setSelected] = createSignal<Record<string, BucketObject>>(
+    {},
+  );
+  const [status, setStatus] = createSignal("");
+
+  const [page] = createResource(
+    () => ({ prefix: prefix(), token: token() }),
+    async ({ prefix, token }) => {
+      const r: ObjectPage = await invoke("list_bucket", {
+        configId: props.configId,
+        prefix,
+        continuationToken: token,
+      });
+      return r;
+    },
+  );
+
+  const open = (next: string) => {
+    setPrefix(next);
+    setToken(null);
+    setTokens([]);
+    setSelected({});
+  };
+
+  // the prefix one level up, "a/b/" becomes "a/"
+  const parent = () => {
+    const trimmed = prefix().replace(/\/$/, "");
+    const index = trimmed.lastIndexOf("/");
+    return index === -1 ? "" : trimmed.slice(0, index + 1);
+  };
+
+  const toggle = (object: BucketObject) => {
+    const next = { ...selected() };
+    if (next[object.key]) {
+      delete next[object.key];
+    } else {
+      next[object.key] = object;
+    }
+    setSelected(next);
+  };
+
+  const report = (r: ImportReport) =>
+    setStatus(`Imported ${r.imported}, ${r.skipped} already in the history`);
+
+  const importSelected = async () => {
+    setStatus("Importing...");
+    try {
+      report(
+        await invoke<ImportReport>("import_objects", {
+          configId: props.configId,
+          objects: Object.values(selected()),
+        }),
+      );
+      setSelected({});
+    } catch (e) {
+      setStatus(String(e));
+    }
+  };
+
+  const importAll = async () => {
+    setStatus("Importing...");
+    try {
+      report(
+        await invoke<ImportReport>("import_prefix", {
+          configId: props.configId,
+          prefix: prefix(),
+        }),
+      );
+    } catch (e) {
+      setStatus(String(e));
+    }
+  };
+
+  return (
+    <div class="flex flex-col gap-2">
+      <div class="flex flex-row gap-2 items-center">
+        <Show when={prefix()}>
+          <button type="button" onClick={() => open(parent())}>
+            Up
+          </button>
+        </Show>
+        <span>/{prefix()}</span>
+      </div>
+      <Show when={page.error}>
+        <p>{String(page.error)}</p>
+      </Show>
+      <div class="flex flex-col divide-y-2 border-black">
+        <For each={page()?.prefixes}>
+          {(p) => (
+            <button type="button" class="text-left py-1" onClick={() => open(p)}>
+              {p.slice(prefix().length)}
+            </button>
+          )}
+        </For>
+        <For each={page()?.objects}>
+          {(object) => (
+            <label class="grid grid-cols-7 py-1 items-center">
+              <input
+                type="checkbox"
+                checked={!!selected()[object.key]}
+                onChange={() => toggle(object)}
+              />
+              <span class="col-span-4 truncate">
+                {object.key.slice(prefix().length)}
+              </span>
+              <span>{formatSize(object.size)}</span>
+              <span>{new Date(object.last_modified).toLocaleDateString()}</span>
+            </label>
+          )}
+        </For>
+      </div>
+      <div class="flex flex-row gap-2">
+        <Show when={tokens().length > 0}>
+          <button
+            type="button"
+            onClick={() => {
+              const previous = tokens();
+              setToken(previous[previous.length - 1]);
+              setTokens(previous.slice(0, -1));
+            }}
+          >
+            Previous
+          </button>
+        </Show>
+        <Show when={page()?.continuation_token}>
+          {(next) => (
+            <button
+              type="button"
+              onClick={() => {
+                setTokens([...tokens(), token()]);
+                setToken(next());
+              }}
+            >
+              Next
+            </button>
+          )}
+        </Show>
+      </div>
+      <div class="flex flex-row gap-2">
+        <button
+          type="button"
+          disabled={Object.keys(selected()).length === 0}
+          onClick={importSelected}
+        >
+          Import selected
+        </button>
+        <button type="button" onClick={importAll}>
+          Import everything in /{prefix()}
+        </button>
+      </div>
+      <Show when={status()}>
+        <p>{status()}</p>
+      </Show>
+    </div>
+  );
+}
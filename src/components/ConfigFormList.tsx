@@ -1,5 +1,5 @@
 import { invoke } from "@tauri-apps/api/primitives";
-import { For, createResource } from "solid-js";
+import { For, Show, createResource, createSignal } from "solid-js";
 import { IconButton } from "./IconButton";
 
 type Data = { id: number; bucket_name: string };
@@ -14,33 +14,71 @@ export function S3ConfigFormList() {
     return r;
   });
 
+  // configs which get a copy of every upload
+  const [replicas, { refetch: refetchReplicas }] = createResource(async () => {
+    const r: Array<number> = await invoke("get_replicas");
+    return r;
+  });
+  const [replicaError, setReplicaError] = createSignal("");
+
   async function onClick(configId: number) {
     await invoke("set_selected", { configId });
     refetch();
   }
 
+  async function toggleReplica(configId: number) {
+    const current = replicas() ?? [];
+    const configIds = current.includes(configId)
+      ? current.filter((id) => id !== configId)
+      : [...current, configId];
+    try {
+      await invoke("set_replicas", { configIds });
+      setReplicaError("");
+    } catch (e) {
+      setReplicaError(String(e));
+    }
+    refetchReplicas();
+  }
+
   return (
-    <div class="grid grid-cols-3 gap-4 justify-start">
-      <For each={formData()}>
-        {(d) => (
-          <>
-            <input
-              type="checkbox"
-              onclick={(e) => {
-                e.preventDefault();
-                onClick(d.id);
-              }}
-              id={`${d.id}`}
-              checked={active()?.id === d.id}
-            />
-            <label for={`${d.id}`}>{d.bucket_name}</label>
-            <IconButton as="a" href={`/settings/config/${d.id}/edit`}>
-              <div class="i-heroicons-pencil" />
-            </IconButton>
-          </>
-        )}
-      </For>
-    </div>
+    <>
+      <div class="grid grid-cols-5 gap-4 justify-start">
+        <For each={formData()}>
+          {(d) => (
+            <>
+              <input
+                type="checkbox"
+                onclick={(e) => {
+                  e.preventDefault();
+                  onClick(d.id);
+                }}
+                id={`${d.id}`}
+                checked={active()?.id === d.id}
+              />
+              <label for={`${d.id}`}>{d.bucket_name}</label>
+              <label title="Send a copy of every upload here too">
+                <input
+                  type="checkbox"
+                  checked={replicas()?.includes(d.id)}
+                  disabled={active()?.id === d.id}
+                  onChange={() => toggleReplica(d.id)}
+                />
+                Copy
+              </label>
+              <IconButton as="a" href={`/settings/config/${d.id}/edit`}>
+                <div class="i-heroicons-pencil" />
+              </IconButton>
+              <IconButton as="a" href={`/settings/config/${d.id}/browse`}>
+                <div class="i-heroicons-folder-open" />
+              </IconButton>
+            </>
+          )}
+        </For>
+      </div>
+      <Show when={replicaError()}>
+        <p>{replicaError()}</p>
+      </Show>
+    </>
   );
 }
 
@@ -1,18 +1,78 @@
 import { invoke } from "@tauri-apps/api/primitives";
-import { createStore } from "solid-js/store";
+import { createStore, reconcile } from "solid-js/store";
+import { For, Show, createSignal } from "solid-js";
+
+const defaultAzure = {
+  account_name: "",
+  account_key: "",
+  sas_token: "",
+  container: "",
+  endpoint: "",
+};
+
+const defaultWebdav = {
+  url: "",
+  username: "",
+  password: "",
+  share_links: false,
+};
+
+const defaultSftp = {
+  host: "",
+  port: 22,
+  username: "",
+  password: "",
+  key_file: "",
+  key_passphrase: "",
+  remote_dir: "",
+};
 
 const defaultState = {
   nickname: "",
+  backend: "s3",
+  local_root: "",
   endpoint: "",
   region: "",
   bucket_name: "",
   host_rewrite: "",
+  url_template: "",
+  key_template: "",
+  key_prefix: "",
+  encryption: "none",
+  kms_key_id: "",
+  customer_key: "",
+  storage_class: "",
+  cache_control: "",
+  content_disposition: "none",
+  metadata: {} as Record<string, string>,
   public_key: "",
   private_key: "",
+  max_concurrent_parts: 4,
+  access_mode: "public_acl",
+  presign_expiry_secs: 604800,
+  url_style: "auto",
+  disable_acl: false,
+  unsigned_payload: false,
+  checksum_algorithm: "none",
+  min_part_size_mib: 5,
+  max_part_size_mib: 5120,
+  azure: null as typeof defaultAzure | null,
+  webdav: null as typeof defaultWebdav | null,
+  sftp: null as typeof defaultSftp | null,
+  custom: null as { definition: Record<string, unknown> } | null,
 };
 
 type FormState = typeof defaultState;
 
+type TestStep = {
+  name: string;
+  status: "passed" | "warning" | "failed" | "skipped";
+  message: string;
+  fields: string[];
+};
+
+type ConfigReport = { ok: boolean; steps: TestStep[] };
+
 export type AlreadyExistingForm = FormState & { id: number };
 
 export function EditS3ConfigForm(props: { initialForm?: AlreadyExistingForm }) {
@@ -25,12 +85,169 @@ export function EditS3ConfigForm(props: { initialForm?: AlreadyExistingForm }) {
     });
   };
 
+  const updateNumberField = (fieldName: string) => (event: Event) => {
+    const inputElement = event.currentTarget as HTMLInputElement;
+    setForm({
+      [fieldName]: inputElement.valueAsNumber,
+    });
+  };
+
+  // metadata is edited as one `key=value` pair per line
+  const metadataText = () =>
+    Object.entries(form.metadata)
+      .map(([key, value]) => `${key}=${value}`)
+      .join("\n");
+
+  const updateMetadata = (event: Event) => {
+    const inputElement = event.currentTarget as HTMLTextAreaElement;
+    const metadata: Record<string, string> = {};
+    for (const line of inputElement.value.split("\n")) {
+      const index = line.indexOf("=");
+      if (index > 0) {
+        metadata[line.slice(0, index).trim()] = line.slice(index + 1).trim();
+      }
+    }
+    // replace rather than merge so removed lines drop their key
+    setForm("metadata", reconcile(metadata));
+  };
+
+  // configs which were saved before azure was chosen have no azure settings
+  const azure = () => form.azure ?? defaultAzure;
+
+  const updateAzureField = (fieldName: string) => (event: Event) => {
+    const inputElement = event.currentTarget as HTMLInputElement;
+    setForm("azure", { ...azure(), [fieldName]: inputElement.value });
+  };
+
+  const webdav = () => form.webdav ?? defaultWebdav;
+
+  const updateWebdavField = (fieldName: string) => (event: Event) => {
+    const inputElement = event.currentTarget as HTMLInputElement;
+    const value =
+      inputElement.type === "checkbox"
+        ? inputElement.checked
+        : inputElement.value;
+    setForm("webdav", { ...webdav(), [fieldName]: value });
+  };
+
+  const sftp = () => form.sftp ?? defaultSftp;
+
+  const updateSftpField = (fieldName: string) => (event: Event) => {
+    const inputElement = event.currentTarget as HTMLInputElement;
+    const value =
+      inputElement.type === "number"
+        ? inputElement.valueAsNumber
+        : inputElement.value;
+    setForm("sftp", { ...sftp(), [fieldName]: value });
+  };
+
+  // the definition is edited as JSON and only stored once the backend
+  // accepts it
+  const [definitionText, setDefinitionText] = createSignal(
+    form.custom ? JSON.stringify(form.custom.definition, null, 2) : "",
+  );
+  const [definitionError, setDefinitionError] = createSignal("");
+
+  const updateDefinition = async (text: string) => {
+    setDefinitionText(text);
+    try {
+      const definition = await invoke<Record<string, unknown>>(
+        "parse_custom_uploader",
+        { definition: text },
+      );
+      setForm("custom", { definition });
+      setDefinitionError("");
+    } catch (e) {
+      setDefinitionError(String(e));
+    }
+  };
+
+  const importSxcu = async (event: Event) => {
+    const inputElement = event.currentTarget as HTMLInputElement;
+    const file = inputElement.files?.[0];
+    if (file) {
+      await updateDefinition(await file.text());
+      const definition = form.custom?.definition;
+      if (definition) {
+        setDefinitionText(JSON.stringify(definition, null, 2));
+      }
+    }
+  };
+
+  const updateCheckboxField = (fieldName: string) => (event: Event) => {
+    const inputElement = event.currentTarget as HTMLInputElement;
+    setForm({
+      [fieldName]: inputElement.checked,
+    });
+  };
+
+  // only the settings of the chosen backend are sent
+  const formConfig = () => {
+    const config = JSON.parse(JSON.stringify(form));
+    if (config.backend !== "azure") {
+      config.azure = null;
+    }
+    if (config.backend !== "webdav") {
+      config.webdav = null;
+    }
+    if (config.backend !== "sftp") {
+      config.sftp = null;
+    }
+    if (config.backend !== "custom") {
+      config.custom = null;
+    }
+    return config;
+  };
+
+  const [report, setReport] = createSignal<ConfigReport | null>(null);
+  const [testError, setTestError] = createSignal("");
+  const [testing, setTesting] = createSignal(false);
+
+  const testConfig = async () => {
+    setTesting(true);
+    setReport(null);
+    setTestError("");
+    try {
+      const res = await invoke<ConfigReport | Record<string, unknown>>(
+        "test_config",
+        { config: formConfig() },
+      );
+      if ("steps" in res) {
+        setReport(res as ConfigReport);
+      } else {
+        // validation errors, keyed by field
+        setTestError(`Check the fields: ${Object.keys(res).join(", ")}`);
+      }
+    } catch (e) {
+      setTestError(String(e));
+    } finally {
+      setTesting(false);
+    }
+  };
+
+  // problems the last test found with a field, shown next to it
+  const FieldReport = (props: { field: string }) => (
+    <For
+      each={report()?.steps.filter(
+        (step) =>
+          step.fields.includes(props.field) &&
+          (step.status === "failed" || step.status === "warning"),
+      )}
+    >
+      {(step) => (
+        <small>
+          {step.status === "failed" ? "✗" : "!"} {step.message}
+        </small>
+      )}
+    </For>
+  );
+
   return (
     <form
       class="grid flow-col gap-4"
       onSubmit={async (e) => {
         e.preventDefault();
-        const config = JSON.parse(JSON.stringify(form));
+        const config = formConfig();
         console.log({ d: config });
         const res = await invoke("create_config", { config });
         console.log(res);
@@ -46,59 +263,468 @@ export function EditS3ConfigForm(props: { initialForm?: AlreadyExistingForm }) {
         Nickname
       </label>
       <label>
-        <input
-          type="text"
-          placeholder="name"
-          onChange={updateFormField("endpoint")}
-          value={form.endpoint}
-        />
-        Endpoint
+        <select onChange={updateFormField("backend")} value={form.backend}>
+          <option value="s3">S3</option>
+          <option value="local">Local directory</option>
+          <option value="azure">Azure Blob Storage</option>
+          <option value="webdav">WebDAV / Nextcloud</option>
+          <option value="sftp">SFTP</option>
+          <option value="custom">Custom Uploader</option>
+        </select>
+        Storage
       </label>
+      <Show when={form.backend === "local"}>
+        <label>
+          <input
+            type="text"
+            placeholder="/Users/me/Recordings"
+            onChange={updateFormField("local_root")}
+            value={form.local_root}
+          />
+          Directory
+        </label>
+      </Show>
+      <Show when={form.backend === "azure"}>
+        <label>
+          <input
+            type="text"
+            placeholder="mystorageaccount"
+            onChange={updateAzureField("account_name")}
+            value={azure().account_name}
+          />
+          Storage Account
+        </label>
+        <label>
+          <input
+            type="text"
+            placeholder="recordings"
+            onChange={updateAzureField("container")}
+            value={azure().container}
+          />
+          Container
+        </label>
+        <label>
+          <input
+            type="password"
+            onChange={updateAzureField("account_key")}
+            value={azure().account_key ?? ""}
+          />
+          Account Key
+        </label>
+        <label>
+          <input
+            type="password"
+            placeholder="sv=...&sig=..."
+            onChange={updateAzureField("sas_token")}
+            value={azure().sas_token ?? ""}
+          />
+          SAS Token (Instead of the account key)
+        </label>
+        <label>
+          <input
+            type="text"
+            placeholder="http://127.0.0.1:10000/devstoreaccount1"
+            onChange={updateAzureField("endpoint")}
+            value={azure().endpoint ?? ""}
+          />
+          Blob Endpoint (Optional, for Azurite)
+        </label>
+      </Show>
+      <Show when={form.backend === "webdav"}>
+        <label>
+          <input
+            type="text"
+            placeholder="https://cloud.example.com/remote.php/dav/files/me/Recordings"
+            onChange={updateWebdavField("url")}
+            value={webdav().url}
+          />
+          WebDAV URL
+        </label>
+        <label>
+          <input
+            type="text"
+            onChange={updateWebdavField("username")}
+            value={webdav().username}
+          />
+          Username
+        </label>
+        <label>
+          <input
+            type="password"
+            onChange={updateWebdavField("password")}
+            value={webdav().password}
+          />
+          Password (Use an app password for Nextcloud)
+        </label>
+        <label>
+          <input
+            type="checkbox"
+            onChange={updateWebdavField("share_links")}
+            checked={webdav().share_links}
+          />
+          Create Nextcloud Share Links
+        </label>
+      </Show>
+      <Show when={form.backend === "sftp"}>
+        <label>
+          <input
+            type="text"
+            placeholder="example.com"
+            onChange={updateSftpField("host")}
+            value={sftp().host}
+          />
+          Host
+        </label>
+        <label>
+          <input
+            type="number"
+            min="1"
+            max="65535"
+            onChange={updateSftpField("port")}
+            value={sftp().port}
+          />
+          Port
+        </label>
+        <label>
+          <input
+            type="text"
+            onChange={updateSftpField("username")}
+            value={sftp().username}
+          />
+          User
+        </label>
+        <label>
+          <input
+            type="text"
+            placeholder="~/.ssh/id_ed25519"
+            onChange={updateSftpField("key_file")}
+            value={sftp().key_file ?? ""}
+          />
+          Key File
+        </label>
+        <label>
+          <input
+            type="password"
+            onChange={updateSftpField("key_passphrase")}
+            value={sftp().key_passphrase ?? ""}
+          />
+          Key Passphrase (Optional)
+        </label>
+        <label>
+          <input
+            type="password"
+            onChange={updateSftpField("password")}
+            value={sftp().password ?? ""}
+          />
+          Password (Instead of a key file)
+        </label>
+        <label>
+          <input
+            type="text"
+            placeholder="~/public_html/shots"
+            onChange={updateSftpField("remote_dir")}
+            value={sftp().remote_dir}
+          />
+          Remote Directory
+        </label>
+      </Show>
+      <Show when={form.backend === "custom"}>
+        <label>
+          <textarea
+            rows={12}
+            placeholder={'{\n  "request_url": "https://0x0.st",\n  "file_form_name": "file",\n  "url": "{response}"\n}'}
+            onChange={(e) => updateDefinition(e.currentTarget.value)}
+            value={definitionText()}
+          />
+          Uploader Definition (JSON)
+        </label>
+        <Show when={definitionError()}>
+          <p>{definitionError()}</p>
+        </Show>
+        <label>
+          <input type="file" accept=".sxcu,.json" onChange={importSxcu} />
+          Import ShareX Uploader (.sxcu)
+        </label>
+      </Show>
+      <Show when={form.backend === "s3"}>
+        <label>
+          <input
+            type="text"
+            placeholder="name"
+            onChange={updateFormField("endpoint")}
+            value={form.endpoint}
+          />
+          Endpoint
+        </label>
+        <FieldReport field="endpoint" />
+        <label>
+          <input
+            type="text"
+            placeholder="name"
+            onChange={updateFormField("region")}
+            value={form.region}
+          />
+          Region
+        </label>
+        <FieldReport field="region" />
+        <label>
+          <input
+            type="text"
+            placeholder="name"
+            onChange={updateFormField("bucket_name")}
+            value={form.bucket_name}
+          />
+          Bucket Name
+        </label>
+        <FieldReport field="bucket_name" />
+      </Show>
       <label>
         <input
           type="text"
           placeholder="name"
-          onChange={updateFormField("region")}
-          value={form.region}
+          onChange={updateFormField("host_rewrite")}
+          value={form.host_rewrite}
         />
-        Region
+        {form.backend === "sftp"
+          ? "Public Base URL"
+          : "Host Rewrite (Optional)"}
       </label>
+      <FieldReport field="host_rewrite" />
       <label>
         <input
           type="text"
-          placeholder="name"
-          onChange={updateFormField("bucket_name")}
-          value={form.bucket_name}
+          placeholder="https://cdn.example.com/{key}"
+          onChange={updateFormField("url_template")}
+          value={form.url_template}
         />
-        Bucket Name
+        Public URL Template (Optional)
       </label>
+      <FieldReport field="url_template" />
       <label>
         <input
           type="text"
-          placeholder="name"
-          onChange={updateFormField("host_rewrite")}
-          value={form.host_rewrite}
+          placeholder="{yyyy}/{mm}/{dd}/{shortid}.{ext}"
+          onChange={updateFormField("key_template")}
+          value={form.key_template}
         />
-        Host Rewrite (Optional)
+        Object Key Template (Optional)
       </label>
       <label>
         <input
           type="text"
-          placeholder="name"
-          onChange={updateFormField("public_key")}
-          value={form.public_key}
-        />
-        Public Key
-      </label>
-      <label>
-        <input
-          type="password"
-          placeholder="name"
-          onChange={updateFormField("private_key")}
-          value={form.private_key}
+          placeholder="boom"
+          onChange={updateFormField("key_prefix")}
+          value={form.key_prefix}
         />
-        Private key
+        Object Key Prefix (Optional)
       </label>
+      <Show when={form.backend === "s3"}>
+        <label>
+          <input
+            type="text"
+            placeholder="name"
+            onChange={updateFormField("public_key")}
+            value={form.public_key}
+          />
+          Public Key
+        </label>
+        <FieldReport field="public_key" />
+        <label>
+          <input
+            type="password"
+            placeholder="name"
+            onChange={updateFormField("private_key")}
+            value={form.private_key}
+          />
+          Private key
+        </label>
+        <FieldReport field="private_key" />
+        <label>
+          <input
+            type="number"
+            min="1"
+            max="32"
+            onChange={updateNumberField("max_concurrent_parts")}
+            value={form.max_concurrent_parts}
+          />
+          Concurrent part uploads
+        </label>
+        <label>
+          <input
+            type="number"
+            min="5"
+            max="5120"
+            onChange={updateNumberField("min_part_size_mib")}
+            value={form.min_part_size_mib}
+          />
+          Minimum part size (MiB)
+        </label>
+        <label>
+          <input
+            type="number"
+            min="5"
+            max="5120"
+            onChange={updateNumberField("max_part_size_mib")}
+            value={form.max_part_size_mib}
+          />
+          Maximum part size (MiB)
+        </label>
+        <label>
+          <select
+            onChange={updateFormField("access_mode")}
+            value={form.access_mode}
+          >
+            <option value="public_acl">Public (ACL)</option>
+            <option value="bucket_policy">Public (bucket policy)</option>
+            <option value="private">Private (presigned links)</option>
+          </select>
+          Access
+        </label>
+        <FieldReport field="access_mode" />
+        <label>
+          <input
+            type="number"
+            min="1"
+            max="604800"
+            onChange={updateNumberField("presign_expiry_secs")}
+            value={form.presign_expiry_secs}
+          />
+          Presigned link expiry (seconds)
+        </label>
+        <label>
+          <select onChange={updateFormField("url_style")} value={form.url_style}>
+            <option value="auto">Auto</option>
+            <option value="path">Path style</option>
+            <option value="virtual_host">Virtual host</option>
+          </select>
+          URL Style
+        </label>
+        <label>
+          <input
+            type="checkbox"
+            onChange={updateCheckboxField("disable_acl")}
+            checked={form.disable_acl}
+          />
+          Never send ACL headers
+        </label>
+        <FieldReport field="disable_acl" />
+        <label>
+          <input
+            type="checkbox"
+            onChange={updateCheckboxField("unsigned_payload")}
+            checked={form.unsigned_payload}
+          />
+          Send unsigned payload header
+        </label>
+        <label>
+          <select
+            onChange={updateFormField("checksum_algorithm")}
+            value={form.checksum_algorithm}
+          >
+            <option value="none">Size only</option>
+            <option value="sha256">SHA-256</option>
+            <option value="crc32c">CRC32C</option>
+          </select>
+          Checksum
+        </label>
+        <label>
+          <select onChange={updateFormField("encryption")} value={form.encryption}>
+            <option value="none">Bucket default</option>
+            <option value="aes256">SSE-S3 (AES256)</option>
+            <option value="aws_kms">SSE-KMS</option>
+            <option value="customer_key">SSE-C</option>
+          </select>
+          Server-side encryption
+        </label>
+        <FieldReport field="encryption" />
+        <Show when={form.encryption === "aws_kms"}>
+          <label>
+            <input
+              type="text"
+              placeholder="arn:aws:kms:..."
+              onChange={updateFormField("kms_key_id")}
+              value={form.kms_key_id}
+            />
+            KMS key id (Optional)
+          </label>
+          <FieldReport field="kms_key_id" />
+        </Show>
+        <Show when={form.encryption === "customer_key"}>
+          <label>
+            <input
+              type="password"
+              placeholder="base64 encoded 256 bit key"
+              onChange={updateFormField("customer_key")}
+              value={form.customer_key}
+            />
+            Customer key
+          </label>
+          <FieldReport field="customer_key" />
+        </Show>
+        <label>
+          <select
+            onChange={updateFormField("storage_class")}
+            value={form.storage_class}
+          >
+            <option value="">Bucket default</option>
+            <option value="STANDARD">Standard</option>
+            <option value="REDUCED_REDUNDANCY">Reduced redundancy</option>
+            <option value="STANDARD_IA">Standard-IA</option>
+            <option value="ONEZONE_IA">One Zone-IA</option>
+            <option value="INTELLIGENT_TIERING">Intelligent-Tiering</option>
+            <option value="GLACIER_IR">Glacier Instant Retrieval</option>
+          </select>
+          Storage class
+        </label>
+        <FieldReport field="storage_class" />
+        <label>
+          <input
+            type="text"
+            placeholder="public, max-age=31536000"
+            onChange={updateFormField("cache_control")}
+            value={form.cache_control}
+          />
+          Cache-Control (Optional)
+        </label>
+        <label>
+          <select
+            onChange={updateFormField("content_disposition")}
+            value={form.content_disposition}
+          >
+            <option value="none">None</option>
+            <option value="inline">Inline</option>
+            <option value="attachment">Attachment</option>
+          </select>
+          Content-Disposition
+        </label>
+        <label>
+          <textarea
+            placeholder={"uploader={hostname}\nkind={kind}\nversion={version}"}
+            onChange={updateMetadata}
+            value={metadataText()}
+          />
+          Metadata, one key=value per line (Optional)
+        </label>
+      </Show>
+      <Show when={report()}>
+        {(report) => (
+          <ul>
+            <For each={report().steps}>
+              {(step) => (
+                <li>
+                  {step.status} {step.name}: {step.message}
+                </li>
+              )}
+            </For>
+          </ul>
+        )}
+      </Show>
+      <Show when={testError()}>
+        <p>{testError()}</p>
+      </Show>
+      <button type="button" onClick={testConfig} disabled={testing()}>
+        {testing() ? "Testing..." : "Test connection"}
+      </button>
       <button type="submit">{props.initialForm ? "Update" : "Create"}</button>
     </form>
   );
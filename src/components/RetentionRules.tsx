@@ -0,0 +1,225 @@
+import { invoke } from "@tauri-apps/api/primitives";
+import { listen } from "@tauri-apps/api/event";
+import {
+  For,
+  Show,
+  createResource,
+  createSignal,
+  onCleanup,
+  onMount,
+} from "solid-js";
+import { IconButton } from "./IconButton";
+
+type Config = { id: number; nickname: string; bucket_name: string };
+
+type Rule = {
+  id: number;
+  config_id: number;
+  mime_class: string | null;
+  max_age_days: number;
+};
+
+type RetentionReport = {
+  dry_run: boolean;
+  expired: Array<{
+    id: number;
+    obj_key: string | null;
+    created_at: string;
+    max_age_days: number;
+  }>;
+  deleted: number;
+  held: number;
+  failed: Array<{ id: number; error: string }>;
+};
+
+const classes = [
+  ["", "Everything else"],
+  ["image", "Screenshots"],
+  ["video", "Recordings"],
+  ["audio", "Audio"],
+  ["text", "Text"],
+  ["application", "Other files"],
+];
+
+const className = (mimeClass: string | null) =>
+  classes.find(([value]) => value === (mimeClass ?? ""))?.[1];
+
+export function RetentionRules() {
+  const [configs] = createResource(async () => {
+    const r: Array<Config> = await invoke("list_configs");
+    return r;
+  });
+  const [rules, { refetch }] = createResource(async () => {
+    const r: Array<Rule> = await invoke("list_retention_rules");
+    return r;
+  });
+
+  const [configId, setConfigId] = createSignal<number | null>(null);
+  const [mimeClass, setMimeClass] = createSignal("");
+  const [days, setDays] = createSignal(30);
+  const [error, setError] = createSignal("");
+  const [report, setReport] = createSignal<RetentionReport | null>(null);
+
+  const configName = (id: number) => {
+    const config = configs()?.find((c) => c.id === id);
+    return config?.nickname || config?.bucket_name || `#${id}`;
+  };
+
+  const addRule = async () => {
+    const config_id = configId() ?? configs()?.[0]?.id;
+    if (config_id === undefined) {
+      return;
+    }
+    const res = await invoke<Rule | Record<string, unknown>>(
+      "create_retention_rule",
+      {
+        rule: {
+          config_id,
+          mime_class: mimeClass() || null,
+          max_age_days: days(),
+        },
+      },
+    );
+    if ("id" in res) {
+      setError("");
+      refetch();
+    } else {
+      setError("Keep uploads between 1 and 36500 days");
+    }
+  };
+
+  const removeRule = async (id: number) => {
+    await invoke("delete_retention_rule", { id });
+    refetch();
+  };
+
+  // deleting always shows what a dry run found first, confirming it also
+  // lets the background sweep delete large batches for those rules
+  const sweep = async (dryRun: boolean) => {
+    const preview = await invoke<RetentionReport>("sweep_expired_uploads", {
+      dryRun: true,
+    });
+    setReport(preview);
+    if (
+      dryRun ||
+      !preview.expired.length ||
+      !confirm(
+        `Delete ${preview.expired.length} expired uploads from their buckets and the history?`,
+      )
+    ) {
+      return;
+    }
+    setReport(
+      await invoke<RetentionReport>("sweep_expired_uploads", { dryRun: false }),
+    );
+  };
+
+  return (
+    <div class="flex flex-col gap-2 py-4">
+      <h2>Retention</h2>
+      <For each={rules()}>
+        {(rule) => (
+          <div class="grid grid-cols-7 items-center">
+            <span class="col-span-3">{configName(rule.config_id)}</span>
+            <span class="col-span-2">{className(rule.mime_class)}</span>
+            <span>{rule.max_age_days} days</span>
+            <IconButton as="button" onclick={() => removeRule(rule.id)}>
+              <div class="i-heroicons-trash-20-solid" />
+            </IconButton>
+          </div>
+        )}
+      </For>
+      <div class="flex flex-row gap-2 items-center">
+        <select onChange={(e) => setConfigId(Number(e.currentTarget.value))}>
+          <For each={configs()}>
+            {(config) => (
+              <option value={config.id}>{configName(config.id)}</option>
+            )}
+          </For>
+        </select>
+        <select onChange={(e) => setMimeClass(e.currentTarget.value)}>
+          <For each={classes}>
+            {([value, label]) => <option value={value}>{label}</option>}
+          </For>
+        </select>
+        <input
+          type="number"
+          min="1"
+          max="36500"
+          value={days()}
+          onChange={(e) => setDays(e.currentTarget.valueAsNumber)}
+        />
+        days
+        <button type="button" onClick={addRule}>
+          Add
+        </button>
+      </div>
+      <Show when={error()}>
+        <p>{error()}</p>
+      </Show>
+      <div class="flex flex-row gap-2">
+        <button type="button" onClick={() => sweep(true)}>
+          Preview
+        </button>
+        <button type="button" onClick={() => sweep(false)}>
+          Delete expired now
+        </button>
+      </div>
+      <Show when={report()}>
+        {(report) => (
+          <div>
+            <p>
+              {report().dry_run
+                ? `${report().expired.length} uploads would be deleted`
+                : `Deleted ${report().deleted} of ${report().expired.length} expired uploads`}
+            </p>
+            <ul>
+              <For each={report().expired}>
+                {(upload) => (
+                  <li>
+                    {upload.obj_key} from{" "}
+                    {new Date(upload.created_at).toLocaleDateString()}, kept
+                    for {upload.max_age_days} days
+                  </li>
+                )}
+              </For>
+              <For each={report().failed}>
+                {(failed) => (
+                  <li>
+                    Upload {failed.id} could not be deleted: {failed.error}
+                  </li>
+                )}
+              </For>
+            </ul>
+          </div>
+        )}
+      </Show>
+    </div>
+  );
+}
+
+/** Problems of the background sweep, shown until they are dismissed */
+export function RetentionFailures() {
+  const [error, setError] = createSignal<string | null>(null);
+
+  onMount(() => {
+    const unlisten = listen<string>("retention-failed", (e) =>
+      setError(e.payload),
+    );
+    onCleanup(() => unlisten.then((f) => f()));
+  });
+
+  return (
+    <Show when={error()}>
+      {(e) => (
+        <div class="flex flex-row items-center gap-2 py-2">
+          <div class="i-heroicons-exclamation-triangle-20-solid" />
+          {e()}
+          <IconButton as="button" onclick={() => setError(null)}>
+            <div class="i-heroicons-x-mark-20-solid" />
+          </IconButton>
+        </div>
+      )}
+    </Show>
+  );
+}
@@ -0,0 +1,55 @@
+import { listen } from "@tauri-apps/api/event";
+import { createEffect, createSignal, Show } from "solid-js";
+
+type Progress = {
+  bytes_sent: number;
+  total_bytes: number;
+  part_number: number | null;
+  bytes_per_second: number;
+  eta_seconds: number | null;
+};
+
+type UploadEvent = { obj_name: string } & (
+  | { type: "started" | "done" | "aborted" }
+  | { type: "progress"; data: Progress }
+  | { type: "failed"; data: "retryable" | "permanent" }
+);
+
+function formatBytes(n: number) {
+  if (n < 1024 * 1024) return `${(n / 1024).toFixed(0)} KiB`;
+  return `${(n / 1024 / 1024).toFixed(1)} MiB`;
+}
+
+export function UploadProgress() {
+  const [event, setEvent] = createSignal<UploadEvent | null>(null);
+
+  createEffect(() => {
+    return listen<UploadEvent>("upload-event", (e) => setEvent(e.payload));
+  });
+
+  return (
+    <Show when={event()}>
+      {(e) => (
+        <div class="flex flex-row gap-2 py-2 text-sm">
+          <Show when={e().type === "progress" && (e() as any).data}>
+            {(p: () => Progress) => (
+              <>
+                <progress value={p().bytes_sent} max={p().total_bytes} />
+                {formatBytes(p().bytes_sent)} / {formatBytes(p().total_bytes)}
+                {" at "}
+                {formatBytes(p().bytes_per_second)}/s
+                <Show when={p().eta_seconds !== null}>
+                  {" "}({Math.ceil(p().eta_seconds!)}s left)
+                </Show>
+              </>
+            )}
+          </Show>
+          <Show when={e().type === "failed"}>
+            Upload failed ({(e() as any).data})
+          </Show>
+          <Show when={e().type === "done"}>Upload complete</Show>
+        </div>
+      )}
+    </Show>
+  );
+}
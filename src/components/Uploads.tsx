@@ -6,7 +6,11 @@ import {
   createResource,
   createSignal,
   For,
+  Match,
+  onCleanup,
+  onMount,
   Show,
+  Switch,
 } from "solid-js";
 import { useAppContext } from "../Context";
 import { Details } from "./Details";
@@ -23,15 +27,26 @@ export function Uploads() {
         url: string;
         created_at: string;
         mime_type: string;
+        pinned: boolean;
       }> = await invoke("list_uploads");
       console.log(r);
       return r;
     },
   );
 
+  // copies of uploads which were sent to replica configs
+  const [replicas, { refetch: refetchReplicas }] = createResource(
+    () => !ctx.isRecording(),
+    async () => {
+      const r: Array<Replica> = await invoke("list_upload_replicas");
+      return r;
+    },
+  );
+
   const r = () => {
     console.log("refetch from backend");
     refetch();
+    refetchReplicas();
   };
 
   createEffect(() => {
@@ -39,18 +54,313 @@ export function Uploads() {
   });
   return (
     <div class="flex flex-col divide-y-2 border-black">
-      <For each={uploads()}>{(d) => <Upload {...d} refetch={refetch} />}</For>
+      <UploadJobs />
+      <SpooledUploads />
+      <InterruptedUploads refetch={refetch} />
+      <For each={uploads()}>
+        {(d) => (
+          <Upload
+            {...d}
+            replicas={(replicas() ?? []).filter((c) => c.upload_id === d.id)}
+            refetch={refetch}
+          />
+        )}
+      </For>
     </div>
   );
 }
 
+type Replica = {
+  upload_id: number;
+  config_id: number | null;
+  obj_key: string;
+  url: string | null;
+  error: string | null;
+};
+
+type UploadJob = {
+  id: number;
+  obj_name: string;
+  mime_type: string;
+  state: "queued" | "uploading" | "completing" | "done" | "failed" | "cancelled";
+  error: string | null;
+  retryable: boolean;
+  spooled: number | null;
+};
+
+function UploadJobs() {
+  const [jobs, { mutate }] = createResource(async () => {
+    const r: Array<UploadJob> = await invoke("list_upload_jobs");
+    return r;
+  });
+
+  onMount(() => {
+    const unlisten = listen<UploadJob>("upload-job", (e) => {
+      mutate((jobs) => [
+        ...(jobs ?? []).filter((j) => j.id !== e.payload.id),
+        e.payload,
+      ]);
+    });
+    onCleanup(() => unlisten.then((f) => f()));
+  });
+
+  const visible = () =>
+    (jobs() ?? [])
+      // spooled jobs are retried from the pending uploads
+      .filter((j) => j.state !== "done" && j.spooled === null)
+      .sort((a, b) => a.id - b.id);
+
+  return (
+    <For each={visible()}>
+      {(j) => (
+        <div class="grid grid-cols-7 py-2 items-center">
+          <div class="col-span-4 flex flex-row items-center gap-2">
+            <Icon mime_type={j.mime_type} />
+            <span title={j.error ?? undefined}>{j.state}</span>
+          </div>
+          <div class="mx-auto col-span-2">
+            <Show when={["failed", "cancelled"].includes(j.state) && j.retryable}>
+              <button
+                type="button"
+                onClick={() => invoke("retry_upload_job", { id: j.id })}
+              >
+                Retry
+              </button>
+            </Show>
+          </div>
+          <div class="mx-auto col-span-1">
+            <Show when={!["failed", "cancelled"].includes(j.state)}>
+              <IconButton
+                as="button"
+                onclick={() => invoke("cancel_upload_job", { id: j.id })}
+              >
+                <div class="i-heroicons-x-mark-20-solid" />
+              </IconButton>
+            </Show>
+          </div>
+        </div>
+      )}
+    </For>
+  );
+}
+
+function SpooledUploads() {
+  const [spooled, { refetch }] = createResource(async () => {
+    const r: Array<{
+      id: number;
+      mime_type: string;
+      attempts: number;
+      last_error: string | null;
+      failure_kind: "retryable" | "permanent";
+      next_attempt_at: string;
+    }> = await invoke("list_spooled_uploads");
+    return r;
+  });
+
+  // failures the background worker couldn't record on an upload
+  const [error, setError] = createSignal<string | null>(null);
+
+  onMount(() => {
+    const unlisten = Promise.all([
+      listen<UploadJob>("upload-job", (e) => {
+        if (e.payload.spooled !== null) refetch();
+      }),
+      listen("reload-spooled-uploads", refetch),
+      listen<string>("spool-error", (e) => setError(e.payload)),
+    ]);
+    onCleanup(() => unlisten.then((fs) => fs.forEach((f) => f())));
+  });
+
+  const retry = async (id: number) => {
+    await invoke("retry_spooled_upload", { id });
+    refetch();
+  };
+
+  const discard = async (id: number) => {
+    await invoke("discard_spooled_upload", { id });
+    refetch();
+  };
+
+  return (
+    <>
+      <Show when={error()}>
+        {(e) => (
+          <div class="flex flex-row items-center gap-2 py-2">
+            <div class="i-heroicons-exclamation-triangle-20-solid" />
+            {e()}
+            <IconButton as="button" onclick={() => setError(null)}>
+              <div class="i-heroicons-x-mark-20-solid" />
+            </IconButton>
+          </div>
+        )}
+      </Show>
+      <For each={spooled()}>
+        {(p) => (
+          <div class="grid grid-cols-7 py-2 items-center">
+            <div
+              class="col-span-4 flex flex-row items-center gap-2"
+              title={p.last_error ?? undefined}
+            >
+              {/* permanent failures are not retried in the background */}
+              <Show
+                when={p.failure_kind === "permanent"}
+                fallback={
+                  <>
+                    <div class="i-heroicons-cloud-arrow-up-20-solid" />
+                    Pending, {p.attempts} attempt(s)
+                  </>
+                }
+              >
+                <div class="i-heroicons-exclamation-triangle-20-solid" />
+                Failed, retry or discard it
+              </Show>
+            </div>
+            <div class="mx-auto col-span-2">
+              <button type="button" onClick={() => retry(p.id)}>
+                Retry
+              </button>
+            </div>
+            <div class="mx-auto col-span-1">
+              <IconButton as="button" onclick={() => discard(p.id)}>
+                <div class="i-heroicons-trash-20-solid" />
+              </IconButton>
+            </div>
+          </div>
+        )}
+      </For>
+    </>
+  );
+}
+
+// uploads interrupted by the last shutdown are only offered once a session
+let promptedInterrupted = false;
+
+function InterruptedUploads(props: { refetch: () => void }) {
+  const [pending, { refetch }] = createResource(async () => {
+    const r: Array<{
+      id: number;
+      obj_name: string;
+      created_at: string;
+      mime_type: string;
+      source_path: string | null;
+    }> = await invoke("list_interrupted_uploads");
+    return r;
+  });
+  // resumed uploads stay listed until they complete, their job shows progress
+  const [resuming, setResuming] = createSignal<number[]>([]);
+
+  const resume = async (id: number) => {
+    await invoke("resume_upload", { id });
+    setResuming((r) => [...r, id]);
+  };
+
+  const finalize = async (id: number) => {
+    await invoke("finalize_upload", { id });
+    refetch();
+    props.refetch();
+  };
+
+  // sent on startup when uploads were cut off, the list covers the case
+  // where it was sent before this window listened
+  onMount(() => {
+    const unlisten = Promise.all([
+      listen("interrupted-uploads", refetch),
+      // a resumed upload leaves the list once its job is done, a failed one
+      // can be resumed again
+      listen<UploadJob>("upload-job", (e) => {
+        const { state, obj_name } = e.payload;
+        if (state !== "done" && state !== "failed" && state !== "cancelled") {
+          return;
+        }
+        const row = pending()?.find(
+          (p) => p.obj_name === obj_name && resuming().includes(p.id),
+        );
+        if (!row) {
+          return;
+        }
+        setResuming((r) => r.filter((id) => id !== row.id));
+        refetch();
+        props.refetch();
+      }),
+    ]);
+    onCleanup(() => unlisten.then((fs) => fs.forEach((f) => f())));
+  });
+
+  createEffect(() => {
+    const p = pending();
+    if (!p?.length || promptedInterrupted) {
+      return;
+    }
+    promptedInterrupted = true;
+    if (
+      confirm(
+        `${p.length} upload(s) were interrupted before they finished. Resume the files and finalize the recordings with the parts which were uploaded?`,
+      )
+    ) {
+      Promise.all(
+        p.map((u) =>
+          u.source_path ? resume(u.id) : invoke("finalize_upload", { id: u.id }),
+        ),
+      ).finally(() => {
+        refetch();
+        props.refetch();
+      });
+    }
+  });
+
+  const discard = async (id: number) => {
+    await invoke("discard_upload", { id });
+    refetch();
+  };
+
+  return (
+    <For each={pending()}>
+      {(p) => (
+        <div class="grid grid-cols-7 py-2 items-center">
+          <div class="col-span-4 flex flex-row items-center gap-2">
+            <div class="i-heroicons-exclamation-triangle-20-solid" />
+            Interrupted {new Date(p.created_at).toLocaleString()}
+          </div>
+          <div class="mx-auto col-span-2 flex flex-row gap-2">
+            <Show
+              when={!resuming().includes(p.id)}
+              fallback={<span>Resuming</span>}
+            >
+              <Show when={p.source_path}>
+                <button type="button" onClick={() => resume(p.id)}>
+                  Resume
+                </button>
+              </Show>
+              <button type="button" onClick={() => finalize(p.id)}>
+                Finalize
+              </button>
+            </Show>
+          </div>
+          <div class="mx-auto col-span-1">
+            <IconButton as="button" onclick={() => discard(p.id)}>
+              <div class="i-heroicons-trash-20-solid" />
+            </IconButton>
+          </div>
+        </div>
+      )}
+    </For>
+  );
+}
+
 function Upload(props: {
   id: number;
   url: string;
   created_at: string;
   mime_type: string;
+  pinned: boolean;
+  replicas: Replica[];
   refetch: () => void;
 }) {
+  // pinned uploads are never deleted by retention rules
+  const togglePin = async () => {
+    await invoke("set_upload_pinned", { id: props.id, pinned: !props.pinned });
+    props.refetch();
+  };
   const [delSignal, setDelSignal] = createSignal(false);
   const [deletion] = createResource(delSignal, async () => {
     await invoke("delete_upload", { id: props.id });
@@ -66,10 +376,21 @@ function Upload(props: {
     <Details
       summary={
         <summary class="grid grid-cols-7 py-2 items-center">
-          <div class="col-span-4 flex flex-row items-center gap-2">
+          <div class="col-span-3 flex flex-row items-center gap-2">
             <Icon mime_type={props.mime_type} />
             {datestr}
           </div>
+          <div class="mx-auto col-span-1">
+            <IconButton as="button" onclick={togglePin}>
+              <div
+                class={
+                  props.pinned
+                    ? "i-heroicons-bookmark-20-solid"
+                    : "i-heroicons-bookmark"
+                }
+              />
+            </IconButton>
+          </div>
           {/* <div>{(deletion.error as Error)?.message}</div> */}
           <div class="mx-auto col-span-1">
             <IconButton
@@ -107,28 +428,47 @@ function Upload(props: {
       }
     >
       <Media mime_type={props.mime_type} src={props.url} />
+      <For each={props.replicas}>
+        {(replica) => (
+          <p title={replica.url ?? undefined}>
+            {replica.error
+              ? `Copy to config ${replica.config_id ?? "(deleted)"} failed: ${replica.error}`
+              : `Copied to config ${replica.config_id ?? "(deleted)"}`}
+          </p>
+        )}
+      </For>
     </Details>
   );
 }
 
 function Icon(props: { mime_type: string }) {
   return (
-    <Show
-      when={props.mime_type.includes("video")}
-      fallback={<div class="i-heroicons-photo-20-solid" />}
-    >
-      <div class="i-heroicons-video-camera-20-solid" />
-    </Show>
+    <Switch fallback={<div class="i-heroicons-document-20-solid" />}>
+      <Match when={props.mime_type.includes("video")}>
+        <div class="i-heroicons-video-camera-20-solid" />
+      </Match>
+      <Match when={props.mime_type.startsWith("image/")}>
+        <div class="i-heroicons-photo-20-solid" />
+      </Match>
+    </Switch>
   );
 }
 
 function Media(props: { mime_type: string; src: string }) {
   return (
-    <Show
-      when={props.mime_type.includes("video")}
-      fallback={<img src={props.src} loading="lazy" decoding="async" />}
+    <Switch
+      fallback={
+        <a href={props.src} target="_blank">
+          {props.src}
+        </a>
+      }
     >
-      <video src={props.src} controls />
-    </Show>
+      <Match when={props.mime_type.includes("video")}>
+        <video src={props.src} controls />
+      </Match>
+      <Match when={props.mime_type.startsWith("image/")}>
+        <img src={props.src} loading="lazy" decoding="async" />
+      </Match>
+    </Switch>
   );
 }
@@ -0,0 +1,13 @@
+import { useParams } from "@solidjs/router";
+import { BucketBrowser } from "../components/BucketBrowser";
+import Layout from "../components/Layout";
+
+export default function BrowseBucket() {
+  const params = useParams<{ id: string }>();
+
+  return (
+    <Layout>
+      <BucketBrowser configId={Number(params.id)} />
+    </Layout>
+  );
+}
@@ -1,11 +1,26 @@
 import { Uploads } from "../components/Uploads";
+import { RetentionFailures } from "../components/RetentionRules";
+import { UploadProgress } from "../components/UploadProgress";
 import { SelectDevices } from "../components/SelectDevices";
 import { RecordControls } from "../components/RecordControls";
 import { PeakRmsMeter } from "../components/PeakMeter";
 import { A } from "@solidjs/router";
+import { invoke } from "@tauri-apps/api/primitives";
+import { getCurrent } from "@tauri-apps/api/window";
+import { onCleanup, onMount } from "solid-js";
 import Layout from "../components/Layout";
 
 function App() {
+  // files dropped onto the window are uploaded
+  onMount(() => {
+    const unlisten = getCurrent().onFileDropEvent((e) => {
+      if (e.payload.type === "drop") {
+        invoke("upload_file", { paths: e.payload.paths });
+      }
+    });
+    onCleanup(() => unlisten.then((f) => f()));
+  });
+
   return (
     <Layout>
       <div class="flex flex-row justify-between">
@@ -18,6 +33,8 @@ function App() {
       <SelectDevices />
       {/* <CameraPreview /> */}
       <PeakRmsMeter />
+      <UploadProgress />
+      <RetentionFailures />
       <Uploads />
     </Layout>
   );
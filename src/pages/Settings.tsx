@@ -1,11 +1,13 @@
 import { S3ConfigFormList } from "../components/ConfigFormList";
 import Layout from "../components/Layout";
+import { RetentionRules } from "../components/RetentionRules";
 
 export default function Settings() {
   console.log("render")
   return (
     <Layout>
       <S3ConfigFormList />
+      <RetentionRules />
     </Layout>
   );
 }